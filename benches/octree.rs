@@ -45,6 +45,18 @@ fn octree_insertion<I: IntoIterator<Item = (Vector3<f64>, i32)>>(
     octree
 }
 
+fn arena_octree_insertion<I: IntoIterator<Item = (Vector3<f64>, i32)>>(
+    vecs: I,
+) -> ArenaOctree<i32, u64> {
+    let mut octree = ArenaOctree::<_, u64>::new();
+    let space = LeveledRegion(0);
+    octree.extend(
+        vecs.into_iter()
+            .map(|(v, i)| (space.discretize(v).unwrap(), i)),
+    );
+    octree
+}
+
 fn random_points(num: usize) -> Vec<Vector3<f64>> {
     let mut xrng = SmallRng::from_seed([1; 16]);
     let mut yrng = SmallRng::from_seed([4; 16]);
@@ -76,6 +88,31 @@ fn points(c: &mut Criterion) {
             let octree = octree_insertion(points.iter().cloned().map(|v| (v, 0)));
             b.iter(move || octree.iter().count())
         })
+        .with_function("bulk_insertion", |b, &n| {
+            let space = LeveledRegion(0);
+            let items: Vec<(u64, i32)> = random_points(n)
+                .into_iter()
+                .map(|v| (space.discretize(v).unwrap(), 0))
+                .collect();
+            b.iter(move || PointerOctree::from_unsorted(items.clone()))
+        })
+        .with_function("parallel_bulk_insertion", |b, &n| {
+            let space = LeveledRegion(0);
+            let items: Vec<(u64, i32)> = random_points(n)
+                .into_iter()
+                .map(|v| (space.discretize(v).unwrap(), 0))
+                .collect();
+            b.iter(move || PointerOctree::par_from_unsorted(items.clone()))
+        })
+        .with_function("arena_insertion", |b, &n| {
+            let points = random_points(n);
+            b.iter(move || arena_octree_insertion(points.iter().cloned().map(|v| (v, 0))))
+        })
+        .with_function("arena_iteration", |b, &n| {
+            let points = random_points(n);
+            let octree = arena_octree_insertion(points.iter().cloned().map(|v| (v, 0)));
+            b.iter(move || octree.iter().count())
+        })
         .with_function("full_fold", |b, &n| {
             let points = random_points(n);
             let octree = octree_insertion(points.iter().cloned().map(|v| (v, 0)));
@@ -88,6 +125,18 @@ fn points(c: &mut Criterion) {
                     .count()
             })
         })
+        .with_function("arena_full_fold", |b, &n| {
+            let points = random_points(n);
+            let octree = arena_octree_insertion(points.iter().cloned().map(|v| (v, 0)));
+            b.iter(move || {
+                octree
+                    .iter_fold(
+                        PositiveX,
+                        MortonRegionCache::with_hasher(1, MortonBuildHasher::default()),
+                    )
+                    .count()
+            })
+        })
         .sample_size(5)
         .warm_up_time(std::time::Duration::from_millis(1000))
         .measurement_time(std::time::Duration::from_millis(5000)),
@@ -0,0 +1,264 @@
+//! A common query interface for the spatial data structures in this crate.
+
+use crate::morton::{Morton, MortonWrapper};
+use crate::octree::{Ball, Distance, SquaredEuclidean};
+
+use nalgebra::Vector3;
+use num_traits::{Float, FromPrimitive, ToPrimitive};
+
+/// A key in a `SpatialIndex`, which has a position in space that queries are measured against.
+///
+/// Mortons are positioned at the center of their voxel in the normalized space of the octrees, where every axis is
+/// in `[0, 1)`, while points are positioned at themselves.
+pub trait SpatialKey<S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    /// Gets the position of the key.
+    fn position(&self) -> Vector3<S>;
+}
+
+impl<S, M> SpatialKey<S> for M
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton,
+{
+    #[inline]
+    fn position(&self) -> Vector3<S> {
+        MortonWrapper(*self).into()
+    }
+}
+
+impl<S> SpatialKey<S> for Vector3<S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    #[inline]
+    fn position(&self) -> Vector3<S> {
+        *self
+    }
+}
+
+/// A spatial data structure which holds items at keys and can be searched by their position.
+///
+/// This allows code to be generic over the spatial index it uses. Every query measures from the position of the
+/// keys (see `SpatialKey`), so every index gives back the same items for the same query, although items at the same
+/// distance from a point may be given back in a different order by `knn`.
+///
+/// This is implemented by `PointerOctree` and `LinearOctree`, which are keyed by morton, by `KdTree`, which is keyed by
+/// point, and by `Vec<(K, T)>`, which searches every item for every query and is the reference that the other indices
+/// are tested against.
+///
+/// ```
+/// use space::{Euclidean, LinearOctree, MortonWrapper, PointerOctree, SpatialIndex};
+/// use nalgebra::Vector3;
+///
+/// fn closest<I: SpatialIndex<&'static str, f64>>(index: &I) -> &'static str {
+///     index.nearest(Vector3::new(0.3, 0.5, 0.5), &Euclidean).unwrap().1
+/// }
+///
+/// let mut pointer = PointerOctree::<_, u64>::new();
+/// let mut linear = LinearOctree::<_, u64>::new();
+/// let mut brute = Vec::new();
+/// for &(name, x) in &[("a", 0.1), ("b", 0.4), ("c", 0.9)] {
+///     let point = Vector3::new(x, 0.5, 0.5);
+///     let MortonWrapper(m) = point.into();
+///     pointer.insert(m, name);
+///     linear.insert(m, name);
+///     brute.push((point, name));
+/// }
+/// assert_eq!(closest(&pointer), "b");
+/// assert_eq!(closest(&linear), "b");
+/// assert_eq!(closest(&brute), "b");
+/// ```
+pub trait SpatialIndex<T, S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    /// The key that items are stored at.
+    type Key: SpatialKey<S>;
+
+    /// Inserts an item at a key and gives back the item that was there before, if any.
+    fn insert(&mut self, key: Self::Key, item: T) -> Option<T>;
+
+    /// Removes the item at a key and gives it back, if it was present.
+    fn remove(&mut self, key: Self::Key) -> Option<T>;
+
+    /// Finds the `k` items closest to `point` according to `distance`, sorted from closest to farthest, along with
+    /// their distance.
+    fn knn<D>(&self, point: Vector3<S>, k: usize, distance: &D) -> Vec<(Self::Key, &T, S)>
+    where
+        D: Distance<S>;
+
+    /// Finds the item closest to `point` according to `distance`. See `knn`.
+    fn nearest<D>(&self, point: Vector3<S>, distance: &D) -> Option<(Self::Key, &T, S)>
+    where
+        D: Distance<S>,
+    {
+        self.knn(point, 1, distance).pop()
+    }
+
+    /// Finds every item within the Euclidean distance `ball.radius` of `ball.center` (inclusive), along with its
+    /// distance. The items are not given back in any particular order.
+    fn within_radius(&self, ball: &Ball<S>) -> Vec<(Self::Key, &T, S)>;
+
+    /// Finds every item inside of the axis-aligned box from `min` to `max` (inclusive). The items are not given back
+    /// in any particular order.
+    fn query_aabb(&self, min: Vector3<S>, max: Vector3<S>) -> Vec<(Self::Key, &T)>;
+}
+
+impl<K, T, S> SpatialIndex<T, S> for Vec<(K, T)>
+where
+    K: SpatialKey<S> + Copy + PartialEq,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    type Key = K;
+
+    fn insert(&mut self, key: K, item: T) -> Option<T> {
+        if let Some((_, existing)) = self.iter_mut().find(|(k, _)| *k == key) {
+            Some(std::mem::replace(existing, item))
+        } else {
+            self.push((key, item));
+            None
+        }
+    }
+
+    fn remove(&mut self, key: K) -> Option<T> {
+        let ix = self.iter().position(|(k, _)| *k == key)?;
+        Some(self.swap_remove(ix).1)
+    }
+
+    fn knn<D>(&self, point: Vector3<S>, k: usize, distance: &D) -> Vec<(K, &T, S)>
+    where
+        D: Distance<S>,
+    {
+        let mut found: Vec<(K, &T, S)> = self
+            .iter()
+            .map(|(key, item)| (*key, item, distance.distance(point, key.position())))
+            .collect();
+        found.sort_by(|a, b| a.2.partial_cmp(&b.2).unwrap_or(std::cmp::Ordering::Equal));
+        found.truncate(k);
+        found
+    }
+
+    fn within_radius(&self, ball: &Ball<S>) -> Vec<(K, &T, S)> {
+        // This uses the squared distance, the same as the trees, so that they agree about items on the boundary.
+        let radius_squared = ball.radius.powi(2);
+        self.iter()
+            .map(|(key, item)| (*key, item, SquaredEuclidean.distance(ball.center, key.position())))
+            .filter(|&(_, _, distance)| distance <= radius_squared)
+            .map(|(key, item, distance)| (key, item, distance.sqrt()))
+            .collect()
+    }
+
+    fn query_aabb(&self, min: Vector3<S>, max: Vector3<S>) -> Vec<(K, &T)> {
+        self.iter()
+            .filter(|(key, _)| aabb_contains(min, max, key.position()))
+            .map(|(key, item)| (*key, item))
+            .collect()
+    }
+}
+
+/// Checks if `point` is inside of the box from `min` to `max` (inclusive).
+pub(crate) fn aabb_contains<S>(min: Vector3<S>, max: Vector3<S>, point: Vector3<S>) -> bool
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    (0..3).all(|i| min[i] <= point[i] && point[i] <= max[i])
+}
+
+/// Gets the mortons of the voxels that hold the corners of a box in normalized space, clamped to the space.
+///
+/// Every morton whose voxel center is inside of the box is between these mortons.
+pub(crate) fn aabb_mortons<S, M>(min: Vector3<S>, max: Vector3<S>) -> (M, M)
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton,
+{
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let scale = (S::one() + S::one()).powi(M::dim_bits() as i32);
+    let voxel = |point: Vector3<S>| {
+        M::encode(point.map(|n| {
+            let n = (n * scale).floor().max(S::zero()).min(scale - S::one());
+            M::from_u64(n.to_u64().unwrap()).unwrap()
+        }))
+    };
+    (voxel(min), voxel(max))
+}
+
+/// The tests that every `SpatialIndex` must pass.
+#[cfg(test)]
+pub(crate) mod conformance {
+    use super::*;
+    use crate::octree::Euclidean;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Checks that `index` gives back the same results as a brute-force `Vec` through random insertions, removals
+    /// and queries. `key` makes random keys, which should repeat often enough that items get replaced.
+    pub(crate) fn check<I, F>(mut index: I, mut key: F)
+    where
+        I: SpatialIndex<usize, f64>,
+        I::Key: Copy + PartialEq + std::fmt::Debug,
+        F: FnMut(&mut SmallRng) -> I::Key,
+    {
+        let mut rng = SmallRng::from_seed([22; 16]);
+        let mut reference: Vec<(I::Key, usize)> = Vec::new();
+        for round in 0..20 {
+            for i in 0..100 {
+                let k = key(&mut rng);
+                let item = round * 100 + i;
+                assert_eq!(index.insert(k, item), SpatialIndex::<_, f64>::insert(&mut reference, k, item));
+            }
+            for _ in 0..30 {
+                let k = if rng.gen() && !reference.is_empty() {
+                    reference[rng.gen_range(0, reference.len())].0
+                } else {
+                    key(&mut rng)
+                };
+                assert_eq!(index.remove(k), SpatialIndex::<_, f64>::remove(&mut reference, k));
+            }
+            for _ in 0..10 {
+                let point = Vector3::new(rng.gen_range(-0.1, 1.1), rng.gen_range(-0.1, 1.1), rng.gen_range(-0.1, 1.1));
+
+                let k = rng.gen_range(0, 20);
+                let found = index.knn(point, k, &Euclidean);
+                let expected = reference.knn(point, k, &Euclidean);
+                assert_eq!(found.len(), expected.len());
+                for (&(key, &item, distance), &(_, _, expected_distance)) in found.iter().zip(&expected) {
+                    // Items at the same distance can come back in any order, so only the distances are compared.
+                    assert!((distance - expected_distance).abs() < 1e-9);
+                    assert!((distance - Euclidean.distance(point, key.position())).abs() < 1e-9);
+                    assert!(reference.contains(&(key, item)));
+                }
+                let nearest = index.nearest(point, &Euclidean).map(|(_, _, distance)| distance);
+                let expected_nearest = reference.nearest(point, &Euclidean).map(|(_, _, distance)| distance);
+                assert_eq!(nearest.is_some(), expected_nearest.is_some());
+                assert!((nearest.unwrap_or(0.0) - expected_nearest.unwrap_or(0.0)).abs() < 1e-9);
+
+                let ball = Ball::new(point, rng.gen_range(0.0, 0.5));
+                let found: Vec<_> = index.within_radius(&ball).into_iter().map(|(key, &item, _)| (key, item)).collect();
+                let expected: Vec<_> =
+                    reference.within_radius(&ball).into_iter().map(|(key, &item, _)| (key, item)).collect();
+                assert_same_items(&found, &expected);
+
+                let extent = Vector3::new(rng.gen_range(0.0, 0.6), rng.gen_range(0.0, 0.6), rng.gen_range(0.0, 0.6));
+                let (min, max) = (point - extent / 2.0, point + extent / 2.0);
+                let found: Vec<_> = index.query_aabb(min, max).into_iter().map(|(key, &item)| (key, item)).collect();
+                let expected: Vec<_> =
+                    reference.query_aabb(min, max).into_iter().map(|(key, &item)| (key, item)).collect();
+                assert_same_items(&found, &expected);
+            }
+        }
+    }
+
+    fn assert_same_items<K>(found: &[(K, usize)], expected: &[(K, usize)])
+    where
+        K: PartialEq + std::fmt::Debug,
+    {
+        assert_eq!(found.len(), expected.len(), "found {found:?}, expected {expected:?}");
+        for item in found {
+            assert!(expected.contains(item), "found {:?}, which was not expected", item);
+        }
+    }
+}
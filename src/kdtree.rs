@@ -0,0 +1,551 @@
+//! A k-d tree over points in 3d space.
+
+use crate::index::{aabb_contains, SpatialIndex};
+use crate::octree::{Ball, Distance, SquaredEuclidean};
+use crate::queue::Queued;
+
+use nalgebra::Vector3;
+use num_traits::{Float, FromPrimitive, ToPrimitive};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// Below this many insertions and removals, they are kept beside the tree instead of rebuilding it.
+const REBUILD_MIN: usize = 32;
+
+/// A static k-d tree over points in 3d space, which carries an item with every point.
+///
+/// The tree is built by splitting the points at the median along the x, y and z axes in turn, so it is balanced and
+/// is stored in a single `Vec` without any pointers. It is meant to be built once with `build` and then queried, but
+/// it also supports insertion and removal: inserted points are kept in a list beside the tree and removed points are
+/// only marked as removed, until there are about `sqrt(n)` of them and the tree is rebuilt.
+///
+/// The tree is 3d only. It is not generic over the dimension (such as with nalgebra's `DimName`), because it works on
+/// `Vector3` to share `Distance` and `Ball` with the octrees, and those only exist in 3d. Unlike the octrees, points
+/// don't need to be in the normalized space and are never discretized, so every point is kept exactly. Each point
+/// holds a single item, so inserting at a point that is already in the tree replaces its item.
+///
+/// ```
+/// use space::{Euclidean, KdTree};
+/// use nalgebra::Vector3;
+///
+/// let tree = KdTree::build(vec![
+///     (Vector3::new(1.0, 0.0, 0.0), "a"),
+///     (Vector3::new(4.0, 0.0, 0.0), "b"),
+///     (Vector3::new(9.0, 0.0, 0.0), "c"),
+/// ]);
+/// let nearest: Vec<&str> = tree
+///     .knn(Vector3::new(3.0, 0.0, 0.0), 2, &Euclidean)
+///     .into_iter()
+///     .map(|(_, &name, _)| name)
+///     .collect();
+/// assert_eq!(nearest, vec!["b", "a"]);
+/// ```
+pub struct KdTree<T, S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    /// The node of the range `lo..hi` is at `lo + (hi - lo) / 2`, with its left subtree before it and its right
+    /// subtree after it. Removed points keep their place in the tree without an item.
+    nodes: Vec<(Vector3<S>, Option<T>)>,
+    /// The corners of the box holding every point in `nodes`.
+    bounds: (Vector3<S>, Vector3<S>),
+    /// Points that were inserted since the tree was last built.
+    pending: Vec<(Vector3<S>, T)>,
+    /// The number of points in `nodes` that were removed.
+    removed: usize,
+}
+
+impl<T, S> Default for KdTree<T, S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    /// Create an empty tree.
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            bounds: (Vector3::repeat(S::zero()), Vector3::repeat(S::zero())),
+            pending: Vec::new(),
+            removed: 0,
+        }
+    }
+}
+
+impl<T, S> KdTree<T, S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    /// Create an empty tree. Calls Default impl.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a balanced tree from points and their items in `O(n log n)` time.
+    ///
+    /// When a point appears more than once, only its last item is kept.
+    #[must_use]
+    pub fn build(mut items: Vec<(Vector3<S>, T)>) -> Self {
+        // Sorting is stable, so the last item of every point is the last one in its run.
+        items.sort_by(|a, b| lexical_cmp(a.0, b.0));
+        let mut nodes: Vec<(Vector3<S>, Option<T>)> = Vec::with_capacity(items.len());
+        for (point, item) in items {
+            match nodes.last_mut() {
+                Some(last) if last.0 == point => last.1 = Some(item),
+                _ => nodes.push((point, Some(item))),
+            }
+        }
+        let bounds = nodes.iter().fold(
+            (Vector3::repeat(S::infinity()), Vector3::repeat(S::neg_infinity())),
+            |(min, max), &(point, _)| (min.zip_map(&point, S::min), max.zip_map(&point, S::max)),
+        );
+        split(&mut nodes, 0);
+        Self {
+            nodes,
+            bounds,
+            pending: Vec::new(),
+            removed: 0,
+        }
+    }
+
+    /// Fetches an immutable reference to the item at a point.
+    pub fn get(&self, point: Vector3<S>) -> Option<&T> {
+        match self.find(point) {
+            Some(ix) => self.nodes[ix].1.as_ref(),
+            None => self.pending.iter().find(|(p, _)| *p == point).map(|(_, item)| item),
+        }
+    }
+
+    /// Fetches a mutable reference to the item at a point.
+    pub fn get_mut(&mut self, point: Vector3<S>) -> Option<&mut T> {
+        match self.find(point) {
+            Some(ix) => self.nodes[ix].1.as_mut(),
+            None => self.pending.iter_mut().find(|(p, _)| *p == point).map(|(_, item)| item),
+        }
+    }
+
+    /// Inserts an item at a point and gives back the item that was there before, if any.
+    pub fn insert(&mut self, point: Vector3<S>, item: T) -> Option<T> {
+        if let Some(existing) = self.get_mut(point) {
+            return Some(std::mem::replace(existing, item));
+        }
+        self.pending.push((point, item));
+        self.rebuild_if_needed();
+        None
+    }
+
+    /// Removes the item at a point and gives it back, if it was present.
+    pub fn remove(&mut self, point: Vector3<S>) -> Option<T> {
+        let item = if let Some(ix) = self.find(point) {
+            self.removed += 1;
+            self.nodes[ix].1.take()
+        } else {
+            let ix = self.pending.iter().position(|(p, _)| *p == point)?;
+            Some(self.pending.swap_remove(ix).1)
+        };
+        self.rebuild_if_needed();
+        item
+    }
+
+    /// Iterates over every point and its item, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (Vector3<S>, &T)> {
+        self.nodes
+            .iter()
+            .filter_map(|(point, item)| item.as_ref().map(|item| (*point, item)))
+            .chain(self.pending.iter().map(|(point, item)| (*point, item)))
+    }
+
+    /// Finds the `k` points closest to `point` according to `distance`, sorted from closest to farthest.
+    ///
+    /// Each point is given back along with its item and its distance. The search visits subtrees in order of their
+    /// distance to `point`, the same as the octrees.
+    pub fn knn<D>(&self, point: Vector3<S>, k: usize, distance: &D) -> Vec<(Vector3<S>, &T, S)>
+    where
+        D: Distance<S>,
+    {
+        let mut found = Vec::with_capacity(k);
+        if k == 0 {
+            return found;
+        }
+        let mut queue = BinaryHeap::new();
+        if !self.nodes.is_empty() {
+            let (min, max) = self.bounds;
+            queue.push(Queued {
+                distance: distance.box_distance(point, min, max),
+                candidate: Candidate::Subtree(Subtree::root(self.nodes.len(), self.bounds)),
+            });
+        }
+        queue.extend(self.pending.iter().map(|(p, item)| Queued {
+            distance: distance.distance(point, *p),
+            candidate: Candidate::Point(*p, item),
+        }));
+        while let Some(Queued { distance: d, candidate }) = queue.pop() {
+            match candidate {
+                Candidate::Point(p, item) => {
+                    // Nothing left in the queue can be closer than this point.
+                    found.push((p, item, d));
+                    if found.len() == k {
+                        break;
+                    }
+                }
+                Candidate::Subtree(subtree) => {
+                    let (node, item) = &self.nodes[subtree.mid()];
+                    if let Some(item) = item {
+                        queue.push(Queued {
+                            distance: distance.distance(point, *node),
+                            candidate: Candidate::Point(*node, item),
+                        });
+                    }
+                    for child in subtree.children(*node) {
+                        let (min, max) = child.bounds;
+                        queue.push(Queued {
+                            distance: distance.box_distance(point, min, max),
+                            candidate: Candidate::Subtree(child),
+                        });
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Finds the point closest to `point` according to `distance`. See `knn`.
+    pub fn nearest<D>(&self, point: Vector3<S>, distance: &D) -> Option<(Vector3<S>, &T, S)>
+    where
+        D: Distance<S>,
+    {
+        self.knn(point, 1, distance).pop()
+    }
+
+    /// Finds every point within the Euclidean distance `ball.radius` of `ball.center` (inclusive), along with its
+    /// item and its distance. The points are not given back in any particular order.
+    ///
+    /// ```
+    /// use space::{Ball, KdTree};
+    /// use nalgebra::Vector3;
+    ///
+    /// let tree = KdTree::build((0..10).map(|x| (Vector3::new(f64::from(x), 0.0, 0.0), x)).collect());
+    /// let mut found: Vec<i32> = tree
+    ///     .within_radius(&Ball::new(Vector3::new(4.5, 0.0, 0.0), 1.0))
+    ///     .into_iter()
+    ///     .map(|(_, &x, _)| x)
+    ///     .collect();
+    /// found.sort();
+    /// assert_eq!(found, vec![4, 5]);
+    /// ```
+    pub fn within_radius(&self, ball: &Ball<S>) -> Vec<(Vector3<S>, &T, S)> {
+        let radius_squared = ball.radius.powi(2);
+        let mut found: Vec<(Vector3<S>, &T, S)> = self
+            .pending
+            .iter()
+            .map(|(point, item)| (*point, item, SquaredEuclidean.distance(ball.center, *point)))
+            .filter(|&(_, _, distance)| distance <= radius_squared)
+            .collect();
+        let mut subtrees = self.root().into_iter().collect::<Vec<_>>();
+        while let Some(subtree) = subtrees.pop() {
+            let (min, max) = subtree.bounds;
+            if SquaredEuclidean.box_distance(ball.center, min, max) > radius_squared {
+                continue;
+            }
+            let (node, item) = &self.nodes[subtree.mid()];
+            if let Some(item) = item {
+                let distance = SquaredEuclidean.distance(ball.center, *node);
+                if distance <= radius_squared {
+                    found.push((*node, item, distance));
+                }
+            }
+            subtrees.extend(subtree.children(*node));
+        }
+        for found in &mut found {
+            found.2 = found.2.sqrt();
+        }
+        found
+    }
+
+    /// Finds every point inside of the axis-aligned box from `min` to `max` (inclusive), along with its item. The
+    /// points are not given back in any particular order.
+    pub fn query_aabb(&self, min: Vector3<S>, max: Vector3<S>) -> Vec<(Vector3<S>, &T)> {
+        let mut found: Vec<(Vector3<S>, &T)> = self
+            .pending
+            .iter()
+            .filter(|(point, _)| aabb_contains(min, max, *point))
+            .map(|(point, item)| (*point, item))
+            .collect();
+        let mut subtrees = self.root().into_iter().collect::<Vec<_>>();
+        while let Some(subtree) = subtrees.pop() {
+            let (node, item) = &self.nodes[subtree.mid()];
+            if let Some(item) = item {
+                if aabb_contains(min, max, *node) {
+                    found.push((*node, item));
+                }
+            }
+            let axis = subtree.axis();
+            subtrees.extend(subtree.children_reaching(*node, min[axis], max[axis]));
+        }
+        found
+    }
+
+    /// Removes every point from the tree.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Returns the number of points in the tree.
+    pub fn len(&self) -> usize {
+        self.nodes.len() - self.removed + self.pending.len()
+    }
+
+    /// Checks if the tree is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Gets the whole tree as a subtree, or `None` if the tree has no nodes.
+    fn root(&self) -> Option<Subtree<S>> {
+        if self.nodes.is_empty() {
+            None
+        } else {
+            Some(Subtree::root(self.nodes.len(), self.bounds))
+        }
+    }
+
+    /// Finds the index of the node at a point which hasn't been removed.
+    fn find(&self, point: Vector3<S>) -> Option<usize> {
+        let mut subtrees = self.root().into_iter().collect::<Vec<_>>();
+        while let Some(subtree) = subtrees.pop() {
+            let mid = subtree.mid();
+            let (node, item) = &self.nodes[mid];
+            if *node == point && item.is_some() {
+                return Some(mid);
+            }
+            let axis = subtree.axis();
+            subtrees.extend(subtree.children_reaching(*node, point[axis], point[axis]));
+        }
+        None
+    }
+
+    /// Rebuilds the tree with every point once enough points were inserted or removed since it was built.
+    fn rebuild_if_needed(&mut self) {
+        let changes = self.pending.len() + self.removed;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+        let threshold = REBUILD_MIN.max((self.len() as f64).sqrt() as usize);
+        if changes > threshold {
+            let items = std::mem::take(&mut self.nodes)
+                .into_iter()
+                .filter_map(|(point, item)| item.map(|item| (point, item)))
+                .chain(self.pending.drain(..))
+                .collect();
+            *self = Self::build(items);
+        }
+    }
+}
+
+impl<T, S> Extend<(Vector3<S>, T)> for KdTree<T, S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    fn extend<I>(&mut self, it: I)
+    where
+        I: IntoIterator<Item = (Vector3<S>, T)>,
+    {
+        for (point, item) in it {
+            self.insert(point, item);
+        }
+    }
+}
+
+impl<T, S> std::iter::FromIterator<(Vector3<S>, T)> for KdTree<T, S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    /// Builds a balanced tree from the points. See `KdTree::build`.
+    fn from_iter<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = (Vector3<S>, T)>,
+    {
+        Self::build(it.into_iter().collect())
+    }
+}
+
+impl<T, S> SpatialIndex<T, S> for KdTree<T, S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    type Key = Vector3<S>;
+
+    fn insert(&mut self, point: Vector3<S>, item: T) -> Option<T> {
+        KdTree::insert(self, point, item)
+    }
+
+    fn remove(&mut self, point: Vector3<S>) -> Option<T> {
+        KdTree::remove(self, point)
+    }
+
+    fn knn<D>(&self, point: Vector3<S>, k: usize, distance: &D) -> Vec<(Vector3<S>, &T, S)>
+    where
+        D: Distance<S>,
+    {
+        KdTree::knn(self, point, k, distance)
+    }
+
+    fn within_radius(&self, ball: &Ball<S>) -> Vec<(Vector3<S>, &T, S)> {
+        KdTree::within_radius(self, ball)
+    }
+
+    fn query_aabb(&self, min: Vector3<S>, max: Vector3<S>) -> Vec<(Vector3<S>, &T)> {
+        KdTree::query_aabb(self, min, max)
+    }
+}
+
+/// Orders points by x, then y, then z.
+fn lexical_cmp<S>(a: Vector3<S>, b: Vector3<S>) -> Ordering
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    (0..3)
+        .map(|i| a[i].partial_cmp(&b[i]).unwrap_or(Ordering::Equal))
+        .find(|&ordering| ordering != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Puts the median of `nodes` along the axis of `depth` in the middle, with the points before it on one side and the
+/// points after it on the other side, and does the same for both sides.
+fn split<S, N>(nodes: &mut [(Vector3<S>, N)], depth: usize)
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    if nodes.len() <= 1 {
+        return;
+    }
+    let axis = depth % 3;
+    let mid = nodes.len() / 2;
+    nodes.select_nth_unstable_by(mid, |a, b| a.0[axis].partial_cmp(&b.0[axis]).unwrap_or(Ordering::Equal));
+    let (left, right) = nodes.split_at_mut(mid);
+    split(left, depth + 1);
+    split(&mut right[1..], depth + 1);
+}
+
+/// A range of nodes which forms a subtree, along with the box that holds all of its points.
+#[derive(Copy, Clone, Debug)]
+struct Subtree<S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    lo: usize,
+    hi: usize,
+    depth: usize,
+    bounds: (Vector3<S>, Vector3<S>),
+}
+
+impl<S> Subtree<S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    fn root(len: usize, bounds: (Vector3<S>, Vector3<S>)) -> Self {
+        Self {
+            lo: 0,
+            hi: len,
+            depth: 0,
+            bounds,
+        }
+    }
+
+    /// Gets the index of the node of the subtree.
+    fn mid(&self) -> usize {
+        self.lo + (self.hi - self.lo) / 2
+    }
+
+    /// Gets the axis that the node of the subtree splits.
+    fn axis(&self) -> usize {
+        self.depth % 3
+    }
+
+    /// Gets the subtrees on either side of `node`, which is the point at the node of this subtree, skipping the
+    /// empty ones.
+    fn children(self, node: Vector3<S>) -> impl Iterator<Item = Self> {
+        let (mid, axis) = (self.mid(), self.axis());
+        let (min, max) = self.bounds;
+        let mut left_max = max;
+        left_max[axis] = node[axis];
+        let mut right_min = min;
+        right_min[axis] = node[axis];
+        let left = Self {
+            lo: self.lo,
+            hi: mid,
+            depth: self.depth + 1,
+            bounds: (min, left_max),
+        };
+        let right = Self {
+            lo: mid + 1,
+            hi: self.hi,
+            depth: self.depth + 1,
+            bounds: (right_min, max),
+        };
+        IntoIterator::into_iter([left, right]).filter(|child| child.lo < child.hi)
+    }
+
+    /// Gets the subtrees on either side of `node` which can hold points from `lo` to `hi` on the split axis.
+    ///
+    /// Points with the same coordinate as `node` on the split axis can be on either side.
+    fn children_reaching(self, node: Vector3<S>, lo: S, hi: S) -> impl Iterator<Item = Self> {
+        let (mid, split) = (self.mid(), node[self.axis()]);
+        self.children(node)
+            .filter(move |child| if child.lo < mid { lo <= split } else { hi >= split })
+    }
+}
+
+/// Something the nearest neighbor search still has to look at.
+enum Candidate<'a, T, S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    Subtree(Subtree<S>),
+    Point(Vector3<S>, &'a T),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::{Euclidean, Toroidal};
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_kdtree_spatial_index_conformance() {
+        // The coordinates are on a small grid so that points repeat and many of them share a split coordinate.
+        crate::index::conformance::check(KdTree::new(), |rng| {
+            Vector3::new(rng.gen_range(0, 12), rng.gen_range(0, 12), rng.gen_range(0, 12)).map(|n| f64::from(n) / 12.0)
+        });
+    }
+
+    #[test]
+    fn test_kdtree_build_matches_brute_force() {
+        let mut rng = SmallRng::from_seed([23; 16]);
+        let items: Vec<(Vector3<f64>, usize)> = (0..3000)
+            .map(|i| (Vector3::new(rng.gen(), rng.gen(), rng.gen()), i))
+            .collect();
+        let tree = KdTree::build(items.clone());
+        assert_eq!(tree.len(), items.len());
+        assert!(items.iter().all(|&(point, i)| tree.get(point) == Some(&i)));
+        for _ in 0..50 {
+            let point = Vector3::new(rng.gen(), rng.gen(), rng.gen());
+            let k = rng.gen_range(1, 30);
+            // The toroidal distance checks that subtrees are pruned with the distance that was given.
+            let distance = Toroidal(Euclidean);
+            let found: Vec<f64> = tree.knn(point, k, &distance).into_iter().map(|(_, _, d)| d).collect();
+            let mut expected: Vec<f64> = items.iter().map(|&(p, _)| distance.distance(point, p)).collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            expected.truncate(k);
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_kdtree_build_keeps_last_duplicate() {
+        let point = Vector3::new(0.5, 0.5, 0.5);
+        let tree: KdTree<usize, f64> = vec![(point, 0), (Vector3::new(0.1, 0.2, 0.3), 1), (point, 2)]
+            .into_iter()
+            .collect();
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.get(point), Some(&2));
+    }
+}
@@ -6,29 +6,88 @@
 //! - Collision detection
 //! - N-body simulations
 //!
-//! This crate will not be 1.0 until it has removed all dependencies on nightly features and const generics
-//! are available in stable to allow the abstraction over N-dimensional trees.
-#![feature(box_syntax, box_patterns)]
+//! This crate builds on stable Rust, but will not be 1.0 until const generics are available in stable to allow
+//! the abstraction over N-dimensional trees.
 //#![deny(missing_docs)]
 #![deny(clippy::all, clippy::pedantic)]
 #![allow(clippy::similar_names, clippy::module_name_repetitions)]
 
+pub mod index;
+pub mod kdtree;
 pub mod morton;
+pub mod morton2;
+pub mod mtree;
 pub mod octree;
+pub mod quadtree;
+mod queue;
+pub mod rtree;
 
+pub use index::*;
+pub use kdtree::*;
 pub use morton::*;
+pub use mtree::*;
 pub use octree::*;
+pub use quadtree::*;
+pub use rtree::*;
 
-pub trait StorageAccess<'a, T: 'a, K> {
-    type Iter: Iterator<Item=(K, &'a T)>;
-    type IterMut: Iterator<Item=(K, &'a mut T)>;
+/// A common interface to the structures that store items by key, so code can be generic over the backing structure.
+///
+/// This is implemented by `PointerOctree`, `ArenaOctree`, `LinearOctree` and `MortonMap`, which are all keyed by
+/// morton, and by `MortonRegionMap`, which is keyed by `MortonRegion`.
+///
+/// ```
+/// use space::{LinearOctree, MortonMap, PointerOctree, StorageAccess};
+///
+/// fn fill<S: StorageAccess<u64, u64>>(mut storage: S) -> S {
+///     for m in 0..100 {
+///         storage.insert(m * 3, m);
+///     }
+///     assert_eq!(storage.insert(3, 10), Some(1));
+///     *storage.get_mut(6).unwrap() += 1;
+///     assert_eq!(storage.remove(9), Some(3));
+///     storage
+/// }
+///
+/// fn total<S: StorageAccess<u64, u64>>(storage: &S) -> u64 {
+///     storage.iter().map(|(_, &n)| n).sum()
+/// }
+///
+/// let pointer = fill(PointerOctree::new());
+/// let linear = fill(LinearOctree::new());
+/// let map = fill(MortonMap::default());
+/// assert_eq!(pointer.len(), 99);
+/// assert_eq!(total(&pointer), 4957);
+/// assert_eq!(total(&linear), 4957);
+/// assert_eq!(total(&map), 4957);
+/// ```
+pub trait StorageAccess<T, K> {
+    /// Iterates over every item along with its key.
+    fn iter<'a>(&'a self) -> impl Iterator<Item = (K, &'a T)>
+    where
+        T: 'a;
 
-    fn iter(&self, ) -> Self::Iter;
-    fn iter_mut(&mut self, ) -> Self::IterMut;
+    /// Iterates over every item along with its key, with mutable access to the items.
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (K, &'a mut T)>
+    where
+        T: 'a;
 
-    fn insert(&mut self, key: K, item: T);
+    /// Inserts an item at a key and gives back the item that was there before, if any.
+    fn insert(&mut self, key: K, item: T) -> Option<T>;
 
-    fn get(&mut self, key: K, item: T);
-    fn get_mut(&mut self, key: K, item: T);
-}
+    /// Gets the item at a key.
+    fn get(&self, key: K) -> Option<&T>;
+
+    /// Gets a mutable reference to the item at a key.
+    fn get_mut(&mut self, key: K) -> Option<&mut T>;
+
+    /// Removes the item at a key and gives it back, if it was present.
+    fn remove(&mut self, key: K) -> Option<T>;
 
+    /// Returns the number of items.
+    fn len(&self) -> usize;
+
+    /// Checks if there are no items.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,174 @@
+//! Portable bit interleaving for morton codes.
+//!
+//! These use the "magic bits" approach of spreading bits out with shifts and masks. Unlike `pdep` and `pext`,
+//! this is fast on every CPU, including ones without BMI2 or with a microcoded BMI2 (such as AMD Zen 1 and 2).
+//! Enable the `bmi2` feature to use `pdep` and `pext` instead on hardware where they are fast.
+
+/// The mask of every bit in a 3d `u64` morton code that belongs to the `x` dimension.
+pub(crate) const DIM_MASK_U64: u64 = 0x1249_2492_4924_9249;
+
+/// Spreads the lower 21 bits of `n` out so that there are two `0` bits in between each bit.
+#[cfg(any(test, not(feature = "bmi2")))]
+#[inline]
+pub(crate) fn split_by_3(n: u64) -> u64 {
+    let mut n = n & 0x1f_ffff;
+    n = (n | n << 32) & 0x001f_0000_0000_ffff;
+    n = (n | n << 16) & 0x001f_0000_ff00_00ff;
+    n = (n | n << 8) & 0x100f_00f0_0f00_f00f;
+    n = (n | n << 4) & 0x10c3_0c30_c30c_30c3;
+    (n | n << 2) & DIM_MASK_U64
+}
+
+/// The inverse of `split_by_3`, which gathers every third bit (starting at bit `0`) into the lower 21 bits.
+#[cfg(any(test, not(feature = "bmi2")))]
+#[inline]
+pub(crate) fn compact_by_3(n: u64) -> u64 {
+    let mut n = n & DIM_MASK_U64;
+    n = (n ^ n >> 2) & 0x10c3_0c30_c30c_30c3;
+    n = (n ^ n >> 4) & 0x100f_00f0_0f00_f00f;
+    n = (n ^ n >> 8) & 0x001f_0000_ff00_00ff;
+    n = (n ^ n >> 16) & 0x001f_0000_0000_ffff;
+    (n ^ n >> 32) & 0x1f_ffff
+}
+
+/// Encode `x`, `y`, and `z` into a `u64` morton code without using BMI2.
+#[cfg(any(test, not(feature = "bmi2")))]
+#[inline]
+pub(crate) fn encode_u64(x: u64, y: u64, z: u64) -> u64 {
+    split_by_3(z) << 2 | split_by_3(y) << 1 | split_by_3(x)
+}
+
+/// Decode a `u64` morton code into `x`, `y`, and `z` without using BMI2.
+#[cfg(any(test, not(feature = "bmi2")))]
+#[inline]
+pub(crate) fn decode_u64(morton: u64) -> (u64, u64, u64) {
+    (
+        compact_by_3(morton),
+        compact_by_3(morton >> 1),
+        compact_by_3(morton >> 2),
+    )
+}
+
+/// The mask of every bit in a 2d `u64` morton code that belongs to the `x` dimension.
+pub(crate) const DIM2_MASK_U64: u64 = 0x5555_5555_5555_5555;
+
+/// Spreads the lower 32 bits of `n` out so that there is one `0` bit in between each bit.
+#[inline]
+pub(crate) fn split_by_2(n: u64) -> u64 {
+    let mut n = n & 0xffff_ffff;
+    n = (n | n << 16) & 0x0000_ffff_0000_ffff;
+    n = (n | n << 8) & 0x00ff_00ff_00ff_00ff;
+    n = (n | n << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+    n = (n | n << 2) & 0x3333_3333_3333_3333;
+    (n | n << 1) & DIM2_MASK_U64
+}
+
+/// The inverse of `split_by_2`, which gathers every other bit (starting at bit `0`) into the lower 32 bits.
+#[inline]
+pub(crate) fn compact_by_2(n: u64) -> u64 {
+    let mut n = n & DIM2_MASK_U64;
+    n = (n ^ n >> 1) & 0x3333_3333_3333_3333;
+    n = (n ^ n >> 2) & 0x0f0f_0f0f_0f0f_0f0f;
+    n = (n ^ n >> 4) & 0x00ff_00ff_00ff_00ff;
+    n = (n ^ n >> 8) & 0x0000_ffff_0000_ffff;
+    (n ^ n >> 16) & 0xffff_ffff
+}
+
+/// Encode `x` and `y` into a 2d `u64` morton code without using BMI2.
+#[inline]
+pub(crate) fn encode2_u64(x: u64, y: u64) -> u64 {
+    split_by_2(y) << 1 | split_by_2(x)
+}
+
+/// Decode a 2d `u64` morton code into `x` and `y` without using BMI2.
+#[inline]
+pub(crate) fn decode2_u64(morton: u64) -> (u64, u64) {
+    (compact_by_2(morton), compact_by_2(morton >> 1))
+}
+
+/// Encode `x` and `y` into a 2d `u64` morton code using `pdep`.
+#[cfg(any(test, feature = "bmi2"))]
+#[inline]
+pub(crate) fn encode2_u64_bmi2(x: u64, y: u64) -> u64 {
+    use bitintr::Pdep;
+    y.pdep(DIM2_MASK_U64 << 1) | x.pdep(DIM2_MASK_U64)
+}
+
+/// Decode a 2d `u64` morton code into `x` and `y` using `pext`.
+#[cfg(any(test, feature = "bmi2"))]
+#[inline]
+pub(crate) fn decode2_u64_bmi2(morton: u64) -> (u64, u64) {
+    use bitintr::Pext;
+    (morton.pext(DIM2_MASK_U64), morton.pext(DIM2_MASK_U64 << 1))
+}
+
+/// Encode `x`, `y`, and `z` into a `u64` morton code using `pdep`.
+#[cfg(any(test, feature = "bmi2"))]
+#[inline]
+pub(crate) fn encode_u64_bmi2(x: u64, y: u64, z: u64) -> u64 {
+    use bitintr::Pdep;
+    z.pdep(DIM_MASK_U64 << 2) | y.pdep(DIM_MASK_U64 << 1) | x.pdep(DIM_MASK_U64)
+}
+
+/// Decode a `u64` morton code into `x`, `y`, and `z` using `pext`.
+#[cfg(any(test, feature = "bmi2"))]
+#[inline]
+pub(crate) fn decode_u64_bmi2(morton: u64) -> (u64, u64, u64) {
+    use bitintr::Pext;
+    (
+        morton.pext(DIM_MASK_U64),
+        morton.pext(DIM_MASK_U64 << 1),
+        morton.pext(DIM_MASK_U64 << 2),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_encode_every_dim_value() {
+        for n in 0..1 << 21 {
+            assert_eq!(encode_u64(n, 0, 0), encode_u64_bmi2(n, 0, 0));
+            assert_eq!(encode_u64(0, n, 0), encode_u64_bmi2(0, n, 0));
+            assert_eq!(encode_u64(0, 0, n), encode_u64_bmi2(0, 0, n));
+        }
+    }
+
+    #[test]
+    fn test_decode_every_dim_value() {
+        for n in 0..1 << 21 {
+            let m = encode_u64_bmi2(n, !n, n.rotate_left(7));
+            assert_eq!(decode_u64(m), decode_u64_bmi2(m));
+        }
+    }
+
+    #[test]
+    fn test_encode2_every_dim_value() {
+        for n in 0..1 << 21 {
+            assert_eq!(encode2_u64(n, 0), encode2_u64_bmi2(n, 0));
+            assert_eq!(encode2_u64(0, n << 11), encode2_u64_bmi2(0, n << 11));
+            let m = encode2_u64_bmi2(n, !n);
+            assert_eq!(decode2_u64(m), decode2_u64_bmi2(m));
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_rand() {
+        let mut rng = SmallRng::from_seed([7; 16]);
+        for _ in 0..1 << 16 {
+            let (x, y, z) = (rng.gen(), rng.gen(), rng.gen());
+            assert_eq!(encode_u64(x, y, z), encode_u64_bmi2(x, y, z));
+            let m: u64 = rng.gen();
+            assert_eq!(decode_u64(m), decode_u64_bmi2(m));
+            assert_eq!(encode2_u64(x, y), encode2_u64_bmi2(x, y));
+            assert_eq!(decode2_u64(m), decode2_u64_bmi2(m));
+            assert_eq!(encode_u64_bmi2(x, y, z), {
+                let (x, y, z) = decode_u64(encode_u64(x, y, z));
+                encode_u64_bmi2(x, y, z)
+            });
+        }
+    }
+}
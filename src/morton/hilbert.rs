@@ -0,0 +1,242 @@
+use crate::morton::{Morton, MortonBuildHasher, MortonRegion, MortonWrapper};
+
+use nalgebra::Vector3;
+use std::hash::{Hash, Hasher};
+
+/// Use this to map voxels in Hilbert order to arbitrary objects.
+/// This uses the same hasher as `MortonMap`, which also benefits from the locality of Hilbert keys.
+pub type HilbertMap<T, M> = std::collections::HashMap<Hilbert<M>, T, MortonBuildHasher>;
+/// Use this to keep a set of voxels in Hilbert order.
+pub type HilbertSet<M> = std::collections::HashSet<Hilbert<M>, MortonBuildHasher>;
+
+/// A key on a 3d Hilbert curve, which is an alternative to the z-order curve used by `Morton`.
+///
+/// Consecutive Hilbert keys are always adjacent voxels, so sorting by this key preserves locality much better
+/// than sorting by morton code. The key uses the same bits of `M` as a morton code does, and the most
+/// significant `3 * level` bits of a key identify the cell at `level` that the voxel is in, just like a morton.
+///
+/// ```
+/// use space::Hilbert;
+/// use nalgebra::Vector3;
+///
+/// let key = Hilbert::<u64>::encode(Vector3::new(1, 2, 3));
+/// assert_eq!(key.decode(), Vector3::new(1, 2, 3));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hilbert<M>(pub M);
+
+impl<M> Hilbert<M>
+where
+    M: Morton,
+{
+    /// Encode the three dimensions (x, y, z) into a Hilbert key.
+    pub fn encode(dims: Vector3<M>) -> Self {
+        let mask = (M::one() << M::dim_bits()) - M::one();
+        let x = axes_to_transpose([dims.x & mask, dims.y & mask, dims.z & mask], M::dim_bits());
+        // The transposed form stores the most significant bit of each level in `x[0]`.
+        Hilbert(M::encode(Vector3::new(x[2], x[1], x[0])))
+    }
+
+    /// Decode the Hilbert key into the three individual dimensions (x, y, z).
+    pub fn decode(self) -> Vector3<M> {
+        let v = (self.0 & M::used_bits()).decode();
+        let [x, y, z] = transpose_to_axes([v.z, v.y, v.x], M::dim_bits());
+        Vector3::new(x, y, z)
+    }
+
+    /// Converts the cell at `level` that contains `morton` into the Hilbert key of the same cell.
+    /// The bits below `level` are set to `0`.
+    ///
+    /// ```
+    /// use space::{Hilbert, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let morton = u64::encode(Vector3::new(1, 2, 3));
+    /// let key = Hilbert::from_morton(morton, 4);
+    /// assert_eq!(key.to_morton(4), morton & !((1 << (3 * (u64::dim_bits() - 4))) - 1));
+    /// ```
+    pub fn from_morton(morton: M, level: usize) -> Self {
+        let mask = level_prefix(level);
+        Hilbert(Self::encode((morton & mask).decode()).0 & mask)
+    }
+
+    /// Converts the cell at `level` that contains this key into the morton code of the same cell.
+    /// The bits below `level` are set to `0`.
+    pub fn to_morton(self, level: usize) -> M {
+        let mask = level_prefix(level);
+        M::encode(Hilbert(self.0 & mask).decode()) & mask
+    }
+
+    /// Gets the Hilbert key of the first voxel of a region in Hilbert order.
+    ///
+    /// Sorting regions on the same level by this key puts them in Hilbert order.
+    pub fn from_region(region: MortonRegion<M>) -> Self {
+        Self::from_morton(region.morton, region.level)
+    }
+
+    /// Gets the region at `level` that contains this key.
+    pub fn to_region(self, level: usize) -> MortonRegion<M> {
+        MortonRegion {
+            morton: self.to_morton(level),
+            level,
+        }
+    }
+}
+
+impl<M> Default for Hilbert<M>
+where
+    M: Morton,
+{
+    #[inline]
+    fn default() -> Self {
+        Hilbert(M::zero())
+    }
+}
+
+#[allow(clippy::derived_hash_with_manual_eq)]
+impl<M> Hash for Hilbert<M>
+where
+    M: Morton,
+{
+    #[inline]
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: Hasher,
+    {
+        MortonWrapper(self.0).hash(state);
+    }
+}
+
+impl<M> From<MortonWrapper<M>> for Hilbert<M>
+where
+    M: Morton,
+{
+    #[inline]
+    fn from(morton: MortonWrapper<M>) -> Self {
+        Self::encode(morton.0.decode())
+    }
+}
+
+impl<M> From<Hilbert<M>> for MortonWrapper<M>
+where
+    M: Morton,
+{
+    #[inline]
+    fn from(key: Hilbert<M>) -> Self {
+        MortonWrapper(M::encode(key.decode()))
+    }
+}
+
+/// The mask of the bits used by the levels `[0, level)`.
+fn level_prefix<M>(level: usize) -> M
+where
+    M: Morton,
+{
+    M::used_bits() & !((M::one() << (3 * (M::dim_bits() - level))) - M::one())
+}
+
+/// Converts coordinates into the transposed form of a Hilbert index with `bits` bits per dimension.
+///
+/// This is from John Skilling's "Programming the Hilbert curve" (AIP Conference Proceedings 707, 2004).
+fn axes_to_transpose<M>(mut x: [M; 3], bits: usize) -> [M; 3]
+where
+    M: Morton,
+{
+    let top = M::one() << (bits - 1);
+    // Inverse undo.
+    let mut q = top;
+    while q > M::one() {
+        let p = q - M::one();
+        for i in 0..3 {
+            if x[i] & q == M::zero() {
+                let t = (x[0] ^ x[i]) & p;
+                x[0] = x[0] ^ t;
+                x[i] = x[i] ^ t;
+            } else {
+                x[0] = x[0] ^ p;
+            }
+        }
+        q = q >> 1;
+    }
+    // Gray encode.
+    x[1] = x[1] ^ x[0];
+    x[2] = x[2] ^ x[1];
+    let mut t = M::zero();
+    let mut q = top;
+    while q > M::one() {
+        if x[2] & q != M::zero() {
+            t = t ^ (q - M::one());
+        }
+        q = q >> 1;
+    }
+    [x[0] ^ t, x[1] ^ t, x[2] ^ t]
+}
+
+/// The inverse of `axes_to_transpose`.
+fn transpose_to_axes<M>(mut x: [M; 3], bits: usize) -> [M; 3]
+where
+    M: Morton,
+{
+    let end = M::one() << bits;
+    // Gray decode.
+    let t = x[2] >> 1;
+    x[2] = x[2] ^ x[1];
+    x[1] = x[1] ^ x[0];
+    x[0] = x[0] ^ t;
+    // Undo excess work.
+    let mut q = M::one() + M::one();
+    while q != end {
+        let p = q - M::one();
+        for i in (0..3).rev() {
+            if x[i] & q == M::zero() {
+                let t = (x[0] ^ x[i]) & p;
+                x[0] = x[0] ^ t;
+                x[i] = x[i] ^ t;
+            } else {
+                x[0] = x[0] ^ p;
+            }
+        }
+        q = q << 1;
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_hilbert_adjacency() {
+        // Every consecutive pair of keys must be a single step along one axis.
+        let mut last = Hilbert(0u16).decode();
+        assert_eq!(last, Vector3::zeros());
+        for key in 1..1u16 << 15 {
+            let point = Hilbert(key).decode();
+            assert_eq!(Hilbert::encode(point), Hilbert(key));
+            let steps: i32 = (0..3)
+                .map(|i| (i32::from(point[i]) - i32::from(last[i])).abs())
+                .sum();
+            assert_eq!(steps, 1, "key {key} does not neighbor the previous key");
+            last = point;
+        }
+    }
+
+    #[test]
+    fn test_hilbert_morton_levels() {
+        let mut rng = SmallRng::from_seed([5; 16]);
+        for _ in 0..10_000 {
+            let morton = rng.gen::<u64>() & u64::used_bits();
+            let key = Hilbert::from(MortonWrapper(morton));
+            assert_eq!(MortonWrapper::from(key), MortonWrapper(morton));
+            for level in 0..=u64::dim_bits() {
+                let mask = level_prefix::<u64>(level);
+                // The cell's key is the prefix of the key of every voxel in the cell.
+                assert_eq!(Hilbert::from_morton(morton, level), Hilbert(key.0 & mask));
+                assert_eq!(key.to_morton(level), morton & mask);
+                assert_eq!(key.to_region(level), MortonRegion { morton, level });
+            }
+        }
+    }
+}
@@ -1,15 +1,20 @@
 //! This module contains helpers to work with morton codes, otherwise known as a z-order curve.
 
+pub(crate) mod encoding;
+mod hilbert;
+mod range;
 mod region;
 mod wrapper;
 
+pub use self::hilbert::*;
+pub use self::range::*;
 pub use self::region::*;
 pub use self::wrapper::*;
 
-use bitintr::{Pdep, Pext};
+use crate::StorageAccess;
 use nalgebra::Vector3;
 use num_traits::{FromPrimitive, PrimInt, ToPrimitive};
-use std::hash::{Hash, Hasher};
+use std::hash::{BuildHasher, Hash, Hasher};
 
 /// Use this to map regions defined by a z-order curve on a particular level to arbitrary objects.
 /// This uses a custom hasher that is optimized for z-order data locality.
@@ -35,6 +40,7 @@ pub type MortonRegionCache<T, M> = lru_cache::LruCache<MortonRegion<M>, T, Morto
 pub type MortonCache<T, M> = lru_cache::LruCache<MortonWrapper<M>, T, MortonBuildHasher>;
 
 /// Create a `MortonRegionMap`.
+#[must_use]
 pub fn region_map<T, M>() -> MortonRegionMap<T, M>
     where
         M: Morton,
@@ -43,6 +49,7 @@ pub fn region_map<T, M>() -> MortonRegionMap<T, M>
 }
 
 /// Create a `MortonRegionSet`.
+#[must_use]
 pub fn region_set<M>() -> MortonRegionSet<M>
     where
         M: Morton,
@@ -51,6 +58,7 @@ pub fn region_set<M>() -> MortonRegionSet<M>
 }
 
 /// Create a `MortonMap`.
+#[must_use]
 pub fn morton_map<T, M>() -> MortonMap<T, M>
     where
         M: Morton,
@@ -59,6 +67,7 @@ pub fn morton_map<T, M>() -> MortonMap<T, M>
 }
 
 /// Create a `MortonSet`.
+#[must_use]
 pub fn morton_set<T, M>() -> MortonSet<M>
     where
         M: Morton,
@@ -67,6 +76,7 @@ pub fn morton_set<T, M>() -> MortonSet<M>
 }
 
 /// Create a `MortonRegionCache`.
+#[must_use]
 pub fn region_cache<T, M>(size: usize) -> MortonRegionCache<T, M>
     where
         M: Morton,
@@ -75,6 +85,7 @@ pub fn region_cache<T, M>(size: usize) -> MortonRegionCache<T, M>
 }
 
 /// Create a `MortonCache`.
+#[must_use]
 pub fn morton_cache<T, M>(size: usize) -> MortonCache<T, M>
     where
         M: Morton,
@@ -95,6 +106,7 @@ pub fn invalidate_region_cache<T, M>(morton: M, cache: &mut MortonRegionCache<T,
 }
 
 /// Visits the values representing the difference, i.e. the keys that are in `primary` but not in `secondary`.
+#[allow(clippy::implicit_hasher)]
 pub fn region_map_difference<'a, T, U, M>(
     primary: &'a MortonRegionMap<T, M>,
     secondary: &'a MortonRegionMap<U, M>,
@@ -111,6 +123,86 @@ pub fn region_map_difference<'a, T, U, M>(
     })
 }
 
+impl<T, M, S> StorageAccess<T, M> for std::collections::HashMap<MortonWrapper<M>, T, S>
+    where
+        M: Morton,
+        S: BuildHasher,
+{
+    fn iter<'a>(&'a self) -> impl Iterator<Item = (M, &'a T)>
+        where
+            T: 'a,
+    {
+        self.iter().map(|(&MortonWrapper(morton), item)| (morton, item))
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (M, &'a mut T)>
+        where
+            T: 'a,
+    {
+        self.iter_mut().map(|(&MortonWrapper(morton), item)| (morton, item))
+    }
+
+    fn insert(&mut self, morton: M, item: T) -> Option<T> {
+        self.insert(MortonWrapper(morton), item)
+    }
+
+    fn get(&self, morton: M) -> Option<&T> {
+        self.get(&MortonWrapper(morton))
+    }
+
+    fn get_mut(&mut self, morton: M) -> Option<&mut T> {
+        self.get_mut(&MortonWrapper(morton))
+    }
+
+    fn remove(&mut self, morton: M) -> Option<T> {
+        self.remove(&MortonWrapper(morton))
+    }
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T, M, S> StorageAccess<T, MortonRegion<M>> for std::collections::HashMap<MortonRegion<M>, T, S>
+    where
+        M: Morton,
+        S: BuildHasher,
+{
+    fn iter<'a>(&'a self) -> impl Iterator<Item = (MortonRegion<M>, &'a T)>
+        where
+            T: 'a,
+    {
+        self.iter().map(|(&region, item)| (region, item))
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (MortonRegion<M>, &'a mut T)>
+        where
+            T: 'a,
+    {
+        self.iter_mut().map(|(&region, item)| (region, item))
+    }
+
+    fn insert(&mut self, region: MortonRegion<M>, item: T) -> Option<T> {
+        self.insert(region, item)
+    }
+
+    fn get(&self, region: MortonRegion<M>) -> Option<&T> {
+        self.get(&region)
+    }
+
+    fn get_mut(&mut self, region: MortonRegion<M>) -> Option<&mut T> {
+        self.get_mut(&region)
+    }
+
+    fn remove(&mut self, region: MortonRegion<M>) -> Option<T> {
+        self.remove(&region)
+    }
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+}
+
 /// Also known as a Z-order encoding, this partitions a bounded space into finite, but localized,
 /// linear boxes. This morton code is always encoding 3 dimensional data.
 pub trait Morton: PrimInt + FromPrimitive + ToPrimitive + Hash + std::fmt::Debug + 'static {
@@ -124,12 +216,14 @@ pub trait Morton: PrimInt + FromPrimitive + ToPrimitive + Hash + std::fmt::Debug
 
     /// The number of bits used to represent each dimension.
     #[inline]
+    #[must_use]
     fn dim_bits() -> usize {
         Self::BITS / 3
     }
 
     /// The highest level of the morton code's bits.
     #[inline]
+    #[must_use]
     fn highest_bits() -> Self {
         Self::from_u8(0b111).unwrap() << (3 * (Self::dim_bits() - 1))
     }
@@ -139,12 +233,14 @@ pub trait Morton: PrimInt + FromPrimitive + ToPrimitive + Hash + std::fmt::Debug
     /// only has prime factors of 2, therefore regardless of the integer type there will always be 2 or 1 unsued
     /// bits that are not captured in the mask.
     #[inline]
+    #[must_use]
     fn used_bits() -> Self {
         (Self::one() << (3 * Self::dim_bits())) - Self::one()
     }
 
     /// Same as `used_bits`, but its instead the mask of the bits not in use.
     #[inline]
+    #[must_use]
     fn unused_bits() -> Self {
         !Self::used_bits()
     }
@@ -156,6 +252,7 @@ pub trait Morton: PrimInt + FromPrimitive + ToPrimitive + Hash + std::fmt::Debug
     /// This continues until the level is the same as `Self::dim_bits() - 1`. This means this can only be
     /// called when `level` is in the range `[0, Self::dim_bits())`.
     #[inline]
+    #[must_use]
     fn get_significant_bits(self, level: usize) -> Self {
         self >> (3 * (Self::dim_bits() - level - 1))
     }
@@ -172,6 +269,7 @@ pub trait Morton: PrimInt + FromPrimitive + ToPrimitive + Hash + std::fmt::Debug
 
     /// Gets the mask of a particular `level`.
     #[inline]
+    #[must_use]
     fn level_mask(level: usize) -> Self {
         Self::highest_bits() >> (3 * level)
     }
@@ -179,26 +277,26 @@ pub trait Morton: PrimInt + FromPrimitive + ToPrimitive + Hash + std::fmt::Debug
     /// This will set the `level` of a morton code. The passed val must be in the range `[0, 8)`.
     #[inline]
     fn set_level(&mut self, level: usize, val: usize) {
-        if Self::dim_bits() < level + 1 {
-            panic!(
-                "Morton::set_level: got invalid level {} (max is {})",
-                level,
-                Self::dim_bits() - 1
-            );
-        }
+        assert!(
+            level < Self::dim_bits(),
+            "Morton::set_level: got invalid level {} (max is {})",
+            level,
+            Self::dim_bits() - 1
+        );
         self.reset_level(level);
-        *self = *self | Self::from_usize(val).unwrap() << (3 * (Self::dim_bits() - level - 1))
+        *self = *self | Self::from_usize(val).unwrap() << (3 * (Self::dim_bits() - level - 1));
     }
 
     /// This sets a particular `level` in a morton code to `0`.
     #[inline]
     fn reset_level(&mut self, level: usize) {
-        *self = *self & !Self::level_mask(level)
+        *self = *self & !Self::level_mask(level);
     }
 
     /// Because the upper bits are never set in the morton code, it is possible to create a unique morton code
     /// that doesn't represent an actual place in an octree which can be used as a null morton code.
     #[inline]
+    #[must_use]
     fn null() -> Self {
         !Self::zero()
     }
@@ -208,6 +306,151 @@ pub trait Morton: PrimInt + FromPrimitive + ToPrimitive + Hash + std::fmt::Debug
     fn is_null(self) -> bool {
         self == Self::null()
     }
+
+    /// Offsets the morton code by `delta` voxels along each axis without decoding it.
+    ///
+    /// This gives back `None` if the result would be outside of the space.
+    ///
+    /// ```
+    /// use space::Morton;
+    /// use nalgebra::Vector3;
+    ///
+    /// let morton = u64::encode(Vector3::new(1, 2, 3));
+    /// assert_eq!(morton.offset(Vector3::new(4, -2, 0)), Some(u64::encode(Vector3::new(5, 0, 3))));
+    /// assert_eq!(morton.offset(Vector3::new(0, -3, 0)), None);
+    /// ```
+    #[inline]
+    fn offset(self, delta: Vector3<i64>) -> Option<Self> {
+        dilated_offset(self, delta, Self::dim_bits(), false)
+    }
+
+    /// Offsets the morton code by `delta` voxels along each axis without decoding it.
+    ///
+    /// If the result would be outside of the space, it wraps around toroidally.
+    ///
+    /// ```
+    /// use space::Morton;
+    /// use nalgebra::Vector3;
+    ///
+    /// let morton = u64::encode(Vector3::new(1, 2, 3));
+    /// let max = (1 << u64::dim_bits()) - 1;
+    /// assert_eq!(morton.wrapping_offset(Vector3::new(0, -3, 0)), u64::encode(Vector3::new(1, max, 3)));
+    /// ```
+    #[inline]
+    #[must_use]
+    fn wrapping_offset(self, delta: Vector3<i64>) -> Self {
+        dilated_offset(self, delta, Self::dim_bits(), true)
+            .expect("Morton::wrapping_offset(): wrapping offsets cannot fail")
+    }
+
+    /// Adds `delta` to the x dimension, giving back `None` if the result would be outside of the space.
+    #[inline]
+    fn add_x(self, delta: i64) -> Option<Self> {
+        self.offset(Vector3::new(delta, 0, 0))
+    }
+
+    /// Adds `delta` to the y dimension, giving back `None` if the result would be outside of the space.
+    #[inline]
+    fn add_y(self, delta: i64) -> Option<Self> {
+        self.offset(Vector3::new(0, delta, 0))
+    }
+
+    /// Adds `delta` to the z dimension, giving back `None` if the result would be outside of the space.
+    #[inline]
+    fn add_z(self, delta: i64) -> Option<Self> {
+        self.offset(Vector3::new(0, 0, delta))
+    }
+
+    /// Adds `delta` to the x dimension, wrapping around the space toroidally.
+    #[inline]
+    #[must_use]
+    fn wrapping_add_x(self, delta: i64) -> Self {
+        self.wrapping_offset(Vector3::new(delta, 0, 0))
+    }
+
+    /// Adds `delta` to the y dimension, wrapping around the space toroidally.
+    #[inline]
+    #[must_use]
+    fn wrapping_add_y(self, delta: i64) -> Self {
+        self.wrapping_offset(Vector3::new(0, delta, 0))
+    }
+
+    /// Adds `delta` to the z dimension, wrapping around the space toroidally.
+    #[inline]
+    #[must_use]
+    fn wrapping_add_z(self, delta: i64) -> Self {
+        self.wrapping_offset(Vector3::new(0, 0, delta))
+    }
+}
+
+impl Morton for u16 {
+    const BITS: usize = 16;
+
+    /// Encode a Vector3<u16> into a morton code. Only the lower 5 bits of each dimension are used.
+    ///
+    /// ```
+    /// use space::Morton;
+    /// use nalgebra::Vector3;
+    ///
+    /// let morton_code = Morton::encode(Vector3::<u16>::new(1, 2, 3));
+    /// assert_eq!(morton_code, 53);
+    /// ```
+    #[inline]
+    #[allow(clippy::cast_lossless, clippy::cast_possible_truncation)]
+    fn encode(dims: Vector3<Self>) -> Self {
+        let mask = (1 << Self::dim_bits()) - 1;
+        u64::encode(dims.map(|d| (d & mask) as u64)) as Self
+    }
+
+    /// Decode a u16 morton to its associated Vector3<u16>
+    ///
+    /// ```
+    /// use space::Morton;
+    /// use nalgebra::Vector3;
+    ///
+    /// let coordinates = Morton::decode(53);
+    /// assert_eq!(coordinates, Vector3::<u16>::new(1, 2, 3));
+    /// ```
+    #[inline]
+    #[allow(clippy::cast_lossless, clippy::cast_possible_truncation)]
+    fn decode(self) -> Vector3<Self> {
+        ((self & Self::used_bits()) as u64).decode().map(|d| d as Self)
+    }
+}
+
+impl Morton for u32 {
+    const BITS: usize = 32;
+
+    /// Encode a Vector3<u32> into a morton code. Only the lower 10 bits of each dimension are used.
+    ///
+    /// ```
+    /// use space::Morton;
+    /// use nalgebra::Vector3;
+    ///
+    /// let morton_code = Morton::encode(Vector3::<u32>::new(1, 2, 3));
+    /// assert_eq!(morton_code, 53);
+    /// ```
+    #[inline]
+    #[allow(clippy::cast_lossless, clippy::cast_possible_truncation)]
+    fn encode(dims: Vector3<Self>) -> Self {
+        let mask = (1 << Self::dim_bits()) - 1;
+        u64::encode(dims.map(|d| (d & mask) as u64)) as Self
+    }
+
+    /// Decode a u32 morton to its associated Vector3<u32>
+    ///
+    /// ```
+    /// use space::Morton;
+    /// use nalgebra::Vector3;
+    ///
+    /// let coordinates = Morton::decode(53);
+    /// assert_eq!(coordinates, Vector3::<u32>::new(1, 2, 3));
+    /// ```
+    #[inline]
+    #[allow(clippy::cast_lossless, clippy::cast_possible_truncation)]
+    fn decode(self) -> Vector3<Self> {
+        ((self & Self::used_bits()) as u64).decode().map(|d| d as Self)
+    }
 }
 
 impl Morton for u64 {
@@ -225,8 +468,11 @@ impl Morton for u64 {
     #[inline]
     fn encode(dims: Vector3<Self>) -> Self {
         let [x, y, z]: [Self; 3] = dims.into();
-        let bits = 0x1_249_249_249_249_249_u64;
-        z.pdep(bits << 2) | y.pdep(bits << 1) | x.pdep(bits)
+        #[cfg(feature = "bmi2")]
+        let morton = encoding::encode_u64_bmi2(x, y, z);
+        #[cfg(not(feature = "bmi2"))]
+        let morton = encoding::encode_u64(x, y, z);
+        morton
     }
 
     /// Decode a u64 morton to its associated Vector3<u64>
@@ -240,9 +486,11 @@ impl Morton for u64 {
     /// ```
     #[inline]
     fn decode(self) -> Vector3<Self> {
-        let bits = 0x1_249_249_249_249_249_u64;
-        let (x, y, z) = (self.pext(bits), self.pext(bits << 1), self.pext(bits << 2));
-        Vector3::new(x & Self::used_bits(), y, z)
+        #[cfg(feature = "bmi2")]
+        let (x, y, z) = encoding::decode_u64_bmi2(self);
+        #[cfg(not(feature = "bmi2"))]
+        let (x, y, z) = encoding::decode_u64(self);
+        Vector3::new(x, y, z)
     }
 }
 
@@ -297,6 +545,53 @@ impl Morton for u128 {
     }
 }
 
+/// Offsets every dimension of `morton` by `delta` using dilated integer arithmetic, which avoids decoding it.
+///
+/// The offset is applied at `level`, which means that `1` is the width of a region at `level` and the bits
+/// below `level` are left untouched. If `wrap` is `false`, this gives back `None` if the morton would leave
+/// the space, otherwise it wraps around the space toroidally.
+pub(crate) fn dilated_offset<M>(morton: M, delta: Vector3<i64>, level: usize, wrap: bool) -> Option<M>
+where
+    M: Morton,
+{
+    let shift = 3 * (M::dim_bits() - level);
+    let top = M::one() << (3 * M::dim_bits());
+    let below = (M::one() << shift) - M::one();
+    let x_mask = M::encode(Vector3::new(!M::zero(), M::zero(), M::zero()));
+    let mut morton = morton & M::used_bits();
+    for (dim, &d) in delta.iter().enumerate() {
+        if d == 0 {
+            continue;
+        }
+        let magnitude = d.unsigned_abs();
+        if magnitude >> level != 0 && !wrap {
+            return None;
+        }
+        // The mask of all the bits of this dimension that are at or above `level`.
+        let dim_mask = (x_mask << dim) & !below;
+        // Only the lower `level` bits of the magnitude survive the shift, which wraps it around the space.
+        let magnitude = M::from_u64(magnitude & ((1 << M::dim_bits()) - 1)).unwrap();
+        let dilated = (M::encode(Vector3::new(magnitude, M::zero(), M::zero())) << (shift + dim)) & dim_mask;
+        let bits = if d > 0 {
+            // Fill all the bits of the other dimensions so that carries propogate through them.
+            let sum = (morton | (M::used_bits() & !dim_mask)) + dilated;
+            if sum & top != M::zero() && !wrap {
+                return None;
+            }
+            sum
+        } else {
+            // Set a bit above all the used bits to borrow from.
+            let difference = ((morton & dim_mask) | top) - dilated;
+            if difference & top == M::zero() && !wrap {
+                return None;
+            }
+            difference
+        };
+        morton = (morton & !dim_mask) | (bits & dim_mask);
+    }
+    Some(morton)
+}
+
 /// The `BuildHasher` for `MortonHash`.
 pub type MortonBuildHasher = std::hash::BuildHasherDefault<MortonHash>;
 
@@ -308,6 +603,7 @@ pub type MortonBuildHasher = std::hash::BuildHasherDefault<MortonHash>;
 const CACHE_LOCALITY_BITS: usize = 3;
 
 /// This is not to be used with anything other than a morton code, as it depends on its unique structure.
+/// It accepts exactly one `u16`, `u32`, `u64`, or `u128` morton.
 /// It is safe to use it with other data, but it wont perform well at all and may eat tons of memory.
 /// Use at your own risk.
 #[derive(Copy, Clone, Default)]
@@ -346,26 +642,30 @@ impl Hasher for MortonHash {
         panic!("Morton hash should only be used with a single 64 bit value");
     }
 
-    ///```#[should_panic]
+    ///```
     /// use std::hash::Hasher;
     /// use space::MortonHash;
     ///
     /// let mut hash = MortonHash::default();
-    /// hash.write_u16(1);
+    /// hash.write_u16(123);
+    /// assert_eq!(hash.finish(), 12638158613253308507);
     ///```
-    fn write_u16(&mut self, _: u16) {
-        panic!("Morton hash should only be used with a single 64 bit value");
+    #[inline]
+    fn write_u16(&mut self, i: u16) {
+        self.write_u64(i as u64);
     }
 
-    ///```#[should_panic]
+    ///```
     /// use std::hash::Hasher;
     /// use space::MortonHash;
     ///
     /// let mut hash = MortonHash::default();
-    /// hash.write_u32(1);
+    /// hash.write_u32(123);
+    /// assert_eq!(hash.finish(), 12638158613253308507);
     ///```
-    fn write_u32(&mut self, _: u32) {
-        panic!("Morton hash should only be used with a single 64 bit value");
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(i as u64);
     }
 
     ///```
@@ -377,13 +677,13 @@ impl Hasher for MortonHash {
     /// assert_eq!(hash.finish(), 12638158613253308507);
     ///```
     #[inline(always)]
-    #[allow(clippy::unreadable_literal)]
+    #[allow(clippy::inline_always, clippy::unreadable_literal)]
     fn write_u64(&mut self, i: u64) {
         let bottom_mask = (1 << CACHE_LOCALITY_BITS) - 1;
         let bottom = i & bottom_mask;
         let top = (i & !bottom_mask) >> CACHE_LOCALITY_BITS;
         self.value =
-            ((top ^ 14695981039346656037).wrapping_mul(1099511628211) & !bottom_mask) + bottom;
+            ((top ^ 0xcbf2_9ce4_8422_2325).wrapping_mul(1099511628211) & !bottom_mask) + bottom;
     }
 
     ///```
@@ -395,12 +695,12 @@ impl Hasher for MortonHash {
     /// assert_eq!(hash.finish(), 12638158613253308507);
     ///```
     #[inline(always)]
-    #[allow(clippy::unreadable_literal, clippy::cast_lossless, clippy::cast_possible_truncation)]
+    #[allow(clippy::inline_always, clippy::unreadable_literal, clippy::cast_lossless, clippy::cast_possible_truncation)]
     fn write_u128(&mut self, i: u128) {
         let bottom_mask = (1 << CACHE_LOCALITY_BITS) - 1;
         let bottom = i & bottom_mask;
         let top = (i & !bottom_mask) >> CACHE_LOCALITY_BITS;
-        self.value = (((top ^ 14695981039346656037).wrapping_mul(1099511628211) & !bottom_mask)
+        self.value = (((top ^ 0xcbf2_9ce4_8422_2325).wrapping_mul(1099511628211) & !bottom_mask)
             + bottom) as u64;
     }
 
@@ -490,4 +790,61 @@ fn test_write() {
     let mut hash = MortonHash::default();
     hash.write_u64(123);
     println!("hash={}", hash.finish());
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_small_mortons_roundtrip() {
+    for m in 0..1u16 << 15 {
+        assert_eq!(u16::encode(m.decode()), m);
+        assert_eq!(u64::from(m), u64::encode(m.decode().map(u64::from)));
+    }
+    for m in (0..1u32 << 30).step_by(997) {
+        assert_eq!(u32::encode(m.decode()), m);
+        assert_eq!(u64::from(m), u64::encode(m.decode().map(u64::from)));
+    }
+}
+
+#[test]
+fn test_small_morton_maps() {
+    let mut regions = region_map::<usize, u16>();
+    let mut voxels = morton_map::<usize, u32>();
+    for (i, m) in (0..1u16 << 15).step_by(31).enumerate() {
+        for region in morton_levels(m) {
+            *regions.entry(region).or_insert(0) += 1;
+        }
+        voxels.insert(MortonWrapper(u32::from(m) << 15), i);
+    }
+    assert_eq!(regions[&MortonRegion::base()], (1 << 15) / 31 + 1);
+    assert_eq!(voxels.len(), (1 << 15) / 31 + 1);
+}
+
+#[test]
+fn test_offset_against_decode() {
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    fn check<M: Morton>(morton: M, delta: Vector3<i64>) {
+        let width = 1i128 << M::dim_bits();
+        let moved = morton.decode().map(|d| d.to_i128().unwrap()) + delta.map(i128::from);
+        let expected = if moved.iter().all(|&d| d >= 0 && d < width) {
+            Some(M::encode(moved.map(|d| M::from_i128(d).unwrap())))
+        } else {
+            None
+        };
+        assert_eq!(morton.offset(delta), expected);
+        let wrapped = M::encode(moved.map(|d| M::from_i128(d.rem_euclid(width)).unwrap()));
+        assert_eq!(morton.wrapping_offset(delta), wrapped);
+    }
+
+    let mut rng = SmallRng::from_seed([8; 16]);
+    for morton in 0..1u16 << 15 {
+        check(morton, Vector3::new(rng.gen_range(-40, 40), rng.gen_range(-3, 3), rng.gen_range(-1, 2)));
+        assert_eq!(morton.add_x(1), morton.offset(Vector3::new(1, 0, 0)));
+        assert_eq!(morton.wrapping_add_z(-1), morton.wrapping_offset(Vector3::new(0, 0, -1)));
+    }
+    for _ in 0..10_000 {
+        let delta = Vector3::new(rng.gen::<i32>().into(), rng.gen::<i16>().into(), rng.gen_range(-1, 2));
+        check(rng.gen::<u64>() & u64::used_bits(), delta);
+        check((u128::from(rng.gen::<u64>()) << 64 | u128::from(rng.gen::<u64>())) & u128::used_bits(), delta);
+    }
+}
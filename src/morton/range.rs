@@ -0,0 +1,270 @@
+use crate::morton::{Morton, MortonRegion};
+
+use nalgebra::Vector3;
+
+/// An axis-aligned box in the discretized space of a morton code, including both corners.
+///
+/// This can be used to scan a sorted array of mortons (or any z-order structure) without visiting mortons
+/// outside of the box by using `bigmin` and `litmax` (from Tropf and Herzog, "Multidimensional Range Search
+/// in Dynamically Balanced Trees", 1981) or by decomposing the box into covering regions with `regions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MortonBox<M> {
+    /// The morton of the corner with the smallest coordinates.
+    pub min: M,
+    /// The morton of the corner with the largest coordinates.
+    pub max: M,
+}
+
+impl<M> MortonBox<M>
+where
+    M: Morton,
+{
+    /// Creates the box which has the two mortons `a` and `b` as opposite corners.
+    ///
+    /// ```
+    /// use space::{Morton, MortonBox};
+    /// use nalgebra::Vector3;
+    ///
+    /// let bounds = MortonBox::new(u64::encode(Vector3::new(3, 1, 5)), u64::encode(Vector3::new(1, 4, 2)));
+    /// assert_eq!(bounds.min, u64::encode(Vector3::new(1, 1, 2)));
+    /// assert_eq!(bounds.max, u64::encode(Vector3::new(3, 4, 5)));
+    /// ```
+    pub fn new(a: M, b: M) -> Self {
+        let (a, b) = (a.decode(), b.decode());
+        Self {
+            min: M::encode(a.zip_map(&b, std::cmp::min)),
+            max: M::encode(a.zip_map(&b, std::cmp::max)),
+        }
+    }
+
+    /// Checks if a morton is inside the box.
+    pub fn contains(&self, morton: M) -> bool {
+        let (min, max, point) = (self.min.decode(), self.max.decode(), morton.decode());
+        (0..3).all(|i| min[i] <= point[i] && point[i] <= max[i])
+    }
+
+    /// Gets the smallest morton in the box which is greater than or equal to `morton`.
+    ///
+    /// This is the BIGMIN operation, which allows skipping the mortons outside of the box while scanning
+    /// upwards in z-order.
+    ///
+    /// ```
+    /// use space::{Morton, MortonBox};
+    /// use nalgebra::Vector3;
+    ///
+    /// let bounds = MortonBox::new(u64::encode(Vector3::new(1, 1, 0)), u64::encode(Vector3::new(2, 2, 0)));
+    /// // The next morton in the box after (0, 2, 0) in z-order is (1, 2, 0).
+    /// let skipped = u64::encode(Vector3::new(0, 2, 0));
+    /// assert_eq!(bounds.bigmin(skipped), Some(u64::encode(Vector3::new(1, 2, 0))));
+    /// assert_eq!(bounds.bigmin(bounds.max + 1), None);
+    /// ```
+    pub fn bigmin(&self, morton: M) -> Option<M> {
+        if self.contains(morton) {
+            return Some(morton);
+        }
+        let (mut min, mut max) = (self.min, self.max);
+        let mut bigmin = None;
+        for (bit, lower) in Self::bits() {
+            match (morton & bit != M::zero(), min & bit != M::zero(), max & bit != M::zero()) {
+                (false, false, true) => {
+                    bigmin = Some((min | bit) & !lower);
+                    max = (max & !bit) | lower;
+                }
+                (false, true, true) => return Some(min),
+                (true, false, false) => return bigmin,
+                (true, false, true) => min = (min | bit) & !lower,
+                (false, false, false) | (true, true, true) => {}
+                _ => unreachable!("MortonBox::bigmin(): the minimum corner was larger than the maximum"),
+            }
+        }
+        bigmin
+    }
+
+    /// Gets the largest morton in the box which is less than or equal to `morton`.
+    ///
+    /// This is the LITMAX operation, which allows skipping the mortons outside of the box while scanning
+    /// downwards in z-order.
+    pub fn litmax(&self, morton: M) -> Option<M> {
+        if self.contains(morton) {
+            return Some(morton);
+        }
+        let (mut min, mut max) = (self.min, self.max);
+        let mut litmax = None;
+        for (bit, lower) in Self::bits() {
+            match (morton & bit != M::zero(), min & bit != M::zero(), max & bit != M::zero()) {
+                (false, false, true) => max = (max & !bit) | lower,
+                (false, true, true) => return litmax,
+                (true, false, false) => return Some(max),
+                (true, false, true) => {
+                    litmax = Some((max & !bit) | lower);
+                    min = (min | bit) & !lower;
+                }
+                (false, false, false) | (true, true, true) => {}
+                _ => unreachable!("MortonBox::litmax(): the minimum corner was larger than the maximum"),
+            }
+        }
+        litmax
+    }
+
+    /// Decomposes the box into regions in z-order which together cover the whole box.
+    ///
+    /// Regions are subdivided no further than `max_level`, and regions stop being subdivided once doing so
+    /// would produce more than `max_regions` regions. Regions that couldn't be subdivided may extend outside of
+    /// the box, so the mortons in them still need to be checked with `contains`. If `max_level` is
+    /// `M::dim_bits()` and `max_regions` is large enough, the regions cover the box exactly.
+    ///
+    /// ```
+    /// use space::{Morton, MortonBox, MortonRegion};
+    /// use nalgebra::Vector3;
+    ///
+    /// let bounds = MortonBox::new(u64::encode(Vector3::new(1, 1, 1)), u64::encode(Vector3::new(2, 2, 2)));
+    /// let regions = bounds.regions(u64::dim_bits(), 64);
+    /// assert_eq!(regions.len(), 8);
+    /// assert!(regions.iter().all(|r| r.level == u64::dim_bits()));
+    /// // The smallest single region covering the box spans the cells `[0, 4)` on each axis.
+    /// assert_eq!(bounds.regions(u64::dim_bits(), 1), vec![MortonRegion { morton: 0, level: u64::dim_bits() - 2 }]);
+    /// ```
+    pub fn regions(&self, max_level: usize, max_regions: usize) -> Vec<MortonRegion<M>> {
+        let (min, max) = (self.min.decode(), self.max.decode());
+        let max_level = std::cmp::min(max_level, M::dim_bits());
+        // Each region is paired with whether or not it is completely inside the box.
+        let mut regions = vec![(MortonRegion::base(), Self::region_inside(MortonRegion::base(), min, max))];
+        for _ in 0..max_level {
+            let mut count = regions.len();
+            let mut subdivided = false;
+            let mut next = Vec::with_capacity(regions.len());
+            for (region, inside) in regions {
+                if inside || region.level == max_level {
+                    next.push((region, inside));
+                    continue;
+                }
+                let children: Vec<_> = (0..8)
+                    .map(|i| region.enter(i))
+                    .filter(|&child| Self::region_overlaps(child, min, max))
+                    .map(|child| (child, Self::region_inside(child, min, max)))
+                    .collect();
+                if count - 1 + children.len() <= max_regions {
+                    count = count - 1 + children.len();
+                    subdivided = true;
+                    next.extend(children);
+                } else {
+                    next.push((region, inside));
+                }
+            }
+            regions = next;
+            if !subdivided {
+                break;
+            }
+        }
+        // Subdividing in z-order keeps the regions sorted in z-order.
+        regions.into_iter().map(|(region, _)| region).collect()
+    }
+
+    /// Same as `regions`, but merges regions which are next to each other in z-order into inclusive ranges
+    /// of mortons. This is useful for scanning a sorted array of mortons.
+    ///
+    /// ```
+    /// use space::{Morton, MortonBox};
+    /// use nalgebra::Vector3;
+    ///
+    /// let bounds = MortonBox::new(u64::encode(Vector3::new(0, 0, 0)), u64::encode(Vector3::new(1, 1, 3)));
+    /// assert_eq!(bounds.ranges(u64::dim_bits(), 64), vec![(0, 7), (32, 39)]);
+    /// ```
+    pub fn ranges(&self, max_level: usize, max_regions: usize) -> Vec<(M, M)> {
+        let mut ranges: Vec<(M, M)> = Vec::new();
+        for region in self.regions(max_level, max_regions) {
+            let start = region.morton & Self::prefix(region.level);
+            let end = start | (M::used_bits() & !Self::prefix(region.level));
+            match ranges.last_mut() {
+                Some(last) if last.1 + M::one() == start => last.1 = end,
+                _ => ranges.push((start, end)),
+            }
+        }
+        ranges
+    }
+
+    /// Iterates from the most significant bit to the least significant bit, giving back each bit
+    /// and the mask of the bits below it that belong to the same dimension.
+    fn bits() -> impl Iterator<Item = (M, M)> {
+        let x_mask = M::encode(Vector3::new(!M::zero(), M::zero(), M::zero()));
+        (0..3 * M::dim_bits()).rev().map(move |i| {
+            let bit = M::one() << i;
+            (bit, (x_mask << (i % 3)) & (bit - M::one()))
+        })
+    }
+
+    /// The mask of the bits used by the levels `[0, level)`.
+    fn prefix(level: usize) -> M {
+        M::used_bits() & !((M::one() << (3 * (M::dim_bits() - level))) - M::one())
+    }
+
+    /// Gets the inclusive corners of a region in decoded space.
+    fn region_corners(region: MortonRegion<M>) -> (Vector3<M>, Vector3<M>) {
+        let start = region.morton & Self::prefix(region.level);
+        let end = start | (M::used_bits() & !Self::prefix(region.level));
+        (start.decode(), end.decode())
+    }
+
+    /// Checks if a region is completely inside of the box with decoded corners `min` and `max`.
+    pub(crate) fn region_inside(region: MortonRegion<M>, min: Vector3<M>, max: Vector3<M>) -> bool {
+        let (start, end) = Self::region_corners(region);
+        (0..3).all(|i| min[i] <= start[i] && end[i] <= max[i])
+    }
+
+    /// Checks if a region overlaps the box with decoded corners `min` and `max`.
+    pub(crate) fn region_overlaps(region: MortonRegion<M>, min: Vector3<M>, max: Vector3<M>) -> bool {
+        let (start, end) = Self::region_corners(region);
+        (0..3).all(|i| start[i] <= max[i] && min[i] <= end[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_box(rng: &mut SmallRng) -> MortonBox<u16> {
+        MortonBox::new(rng.gen::<u16>() & u16::used_bits(), rng.gen::<u16>() & u16::used_bits())
+    }
+
+    #[test]
+    fn test_bigmin_litmax_brute_force() {
+        let mut rng = SmallRng::from_seed([9; 16]);
+        for _ in 0..100 {
+            let bounds = random_box(&mut rng);
+            let inside: Vec<u16> = (0..1 << 15).filter(|&m| bounds.contains(m)).collect();
+            for morton in (0..1 << 15).step_by(13) {
+                assert_eq!(bounds.bigmin(morton), inside.iter().copied().find(|&m| m >= morton));
+                assert_eq!(bounds.litmax(morton), inside.iter().copied().rev().find(|&m| m <= morton));
+            }
+        }
+    }
+
+    #[test]
+    fn test_regions_cover_box() {
+        let mut rng = SmallRng::from_seed([10; 16]);
+        for _ in 0..100 {
+            let bounds = random_box(&mut rng);
+            let max_level = rng.gen_range(0, u16::dim_bits() + 1);
+            let max_regions = rng.gen_range(1, 200);
+            let regions = bounds.regions(max_level, max_regions);
+            assert!(regions.len() <= std::cmp::max(max_regions, 1));
+            assert!(regions.iter().all(|r| r.level <= max_level));
+            let ranges = bounds.ranges(max_level, max_regions);
+            assert!(ranges.windows(2).all(|w| w[0].1 + 1 < w[1].0));
+            for morton in 0..1 << 15 {
+                let covered = regions.iter().filter(|r| r.contains(morton)).count();
+                assert!(covered <= 1, "regions overlap");
+                assert_eq!(covered == 1, ranges.iter().any(|&(s, e)| s <= morton && morton <= e));
+                if bounds.contains(morton) {
+                    assert_eq!(covered, 1, "region does not cover morton {morton}");
+                }
+            }
+        }
+        // With no limits, the regions are exact.
+        let bounds = random_box(&mut rng);
+        let ranges = bounds.ranges(u16::dim_bits(), usize::MAX);
+        assert!((0..1 << 15).all(|m| bounds.contains(m) == ranges.iter().any(|&(s, e)| s <= m && m <= e)));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::morton::Morton;
+use crate::morton::{dilated_offset, Morton};
 
 use nalgebra::Vector3;
 use num_traits::{Float, FromPrimitive, ToPrimitive};
@@ -25,6 +25,7 @@ where
 {
     /// This gets the top level region (everything in the finite space).
     #[inline]
+    #[must_use]
     pub fn base() -> Self {
         Self {
             morton: M::zero(),
@@ -42,6 +43,7 @@ where
     ///
     /// Note that this does not mutate the region, but returns a new one. This can be reversed by calling `exit()`.
     #[inline]
+    #[must_use]
     pub fn enter(mut self, octant: usize) -> Self {
         self.morton.set_level(self.level, octant);
         self.level += 1;
@@ -81,7 +83,7 @@ where
         }
     }
 
-    /// Produces a single number that has a canonically unique mapping to every given valid MortonRegion by using
+    /// Produces a single number that has a canonically unique mapping to every given valid `MortonRegion` by using
     /// the unused bits to store the level information via shifting.
     #[inline]
     pub fn canonicalize(&self) -> M {
@@ -109,12 +111,97 @@ where
     /// let region = space::MortonRegion { morton: 0x7000_0000_0000_0000u64, level: 1 };
     /// assert!(region.contains(0x7123_1234_1234_1234));
     /// assert!(!region.contains(0x6123_1234_1234_1234));
+    /// assert!(region.contains(0x7e00_0000_0000_0000));
     /// ```
     pub fn contains(&self, morton: M) -> bool {
-        self.significant_bits() == morton.get_significant_bits(self.level)
+        // A region at `level` is only defined by the levels above it.
+        self.level == 0
+            || self.morton.get_significant_bits(self.level - 1) == morton.get_significant_bits(self.level - 1)
+    }
+
+    /// Gets the region on the same level that is offset by `delta` regions along each axis.
+    ///
+    /// This operates directly on the morton bits, so it doesn't need to decode the morton.
+    /// When the offset leaves the space, `boundary` determines whether this gives back `None` or wraps around.
+    ///
+    /// ```
+    /// use space::{Boundary, MortonRegion};
+    /// use nalgebra::Vector3;
+    ///
+    /// let region = MortonRegion::<u64>::base().enter(0);
+    /// let right = region.offset(Vector3::new(1, 0, 0), Boundary::Clamp).unwrap();
+    /// assert_eq!(right, MortonRegion::base().enter(1));
+    /// assert!(region.offset(Vector3::new(-1, 0, 0), Boundary::Clamp).is_none());
+    /// assert_eq!(region.offset(Vector3::new(-1, 0, 0), Boundary::Wrap), Some(right));
+    /// ```
+    pub fn offset(self, delta: Vector3<i64>, boundary: Boundary) -> Option<Self> {
+        if self.level == 0 {
+            // The whole space can only be its own neighbor.
+            if boundary == Boundary::Wrap || delta == Vector3::zeros() {
+                Some(self)
+            } else {
+                None
+            }
+        } else {
+            dilated_offset(self.morton, delta, self.level, boundary == Boundary::Wrap).map(|morton| Self {
+                morton,
+                level: self.level,
+            })
+        }
+    }
+
+    /// Iterates over the (up to) `6` regions on the same level that share a face with this region.
+    ///
+    /// With `Boundary::Wrap`, small levels may give back the same region more than once.
+    pub fn face_neighbors(self, boundary: Boundary) -> impl Iterator<Item = Self> {
+        self.neighbors_with_axes(boundary, 1)
+    }
+
+    /// Iterates over the (up to) `12` regions on the same level that share only an edge with this region.
+    pub fn edge_neighbors(self, boundary: Boundary) -> impl Iterator<Item = Self> {
+        self.neighbors_with_axes(boundary, 2)
+    }
+
+    /// Iterates over the (up to) `8` regions on the same level that share only a corner with this region.
+    pub fn corner_neighbors(self, boundary: Boundary) -> impl Iterator<Item = Self> {
+        self.neighbors_with_axes(boundary, 3)
+    }
+
+    /// Iterates over all of the (up to) `26` regions on the same level that touch this region.
+    ///
+    /// ```
+    /// use space::{Boundary, MortonRegion};
+    ///
+    /// let region = MortonRegion::<u64>::base().enter(0).enter(7);
+    /// assert_eq!(region.neighbors(Boundary::Clamp).count(), 26);
+    /// assert_eq!(region.enter(0).neighbors(Boundary::Clamp).count(), 26);
+    /// assert_eq!(MortonRegion::<u64>::base().enter(0).neighbors(Boundary::Clamp).count(), 7);
+    /// ```
+    pub fn neighbors(self, boundary: Boundary) -> impl Iterator<Item = Self> {
+        self.neighbors_with_axes(boundary, 0)
+    }
+
+    /// Iterates over the neighbors which are offset along exactly `axes` axes, or all neighbors if `axes` is `0`.
+    fn neighbors_with_axes(self, boundary: Boundary, axes: usize) -> impl Iterator<Item = Self> {
+        (0..27i64)
+            .map(|i| Vector3::new(i % 3 - 1, i / 3 % 3 - 1, i / 9 - 1))
+            .filter(move |delta| {
+                let moved = delta.iter().filter(|&&d| d != 0).count();
+                moved != 0 && (axes == 0 || moved == axes)
+            })
+            .filter_map(move |delta| self.offset(delta, boundary))
     }
 }
 
+/// Determines what happens when an operation steps outside of the finite space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boundary {
+    /// The space is bounded, so there is nothing outside of it.
+    Clamp,
+    /// The space wraps around toroidally, so leaving one side of the space enters the opposite side.
+    Wrap,
+}
+
 impl<M> PartialEq for MortonRegion<M>
 where
     M: Morton,
@@ -133,7 +220,7 @@ where
 {
     #[inline]
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.canonicalize().partial_cmp(&other.canonicalize())
+        Some(self.cmp(other))
     }
 
     #[inline]
@@ -177,7 +264,7 @@ where
     }
 }
 
-#[allow(clippy::derive_hash_xor_eq)]
+#[allow(clippy::derived_hash_with_manual_eq)]
 impl<M> Hash for MortonRegion<M>
 where
     M: Morton + Hash,
@@ -191,18 +278,17 @@ where
     }
 }
 
-impl<S, M> Into<Vector3<S>> for MortonRegion<M>
+impl<S, M> From<MortonRegion<M>> for Vector3<S>
 where
     S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
     M: Morton,
 {
     #[inline]
-    fn into(self) -> Vector3<S> {
-        let v = self.morton;
-        let cut = M::dim_bits() - self.level;
-        let point = (v >> (3 * cut)).decode();
+    fn from(region: MortonRegion<M>) -> Self {
+        let cut = M::dim_bits() - region.level;
+        let point = (region.morton >> (3 * cut)).decode();
         #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
-        let scale = (S::one() + S::one()).powi(-(self.level as i32));
+        let scale = (S::one() + S::one()).powi(-(region.level as i32));
 
         point.map(|d| {
             (S::from_u64(d.to_u64().unwrap()).unwrap() + S::from_f32(0.5).unwrap()) * scale
@@ -268,3 +354,60 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    // The cells are at most `2^21` wide, so they fit in both types.
+    #[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+    fn test_neighbors_against_decode() {
+        let mut rng = SmallRng::from_seed([6; 16]);
+        for _ in 0..1000 {
+            let level = rng.gen_range(1, u64::dim_bits() + 1);
+            let region = MortonRegion {
+                morton: rng.gen::<u64>() & u64::used_bits(),
+                level,
+            };
+            let cut = 3 * (u64::dim_bits() - level);
+            let cell = (region.morton >> cut).decode().map(|d| d as i64);
+            let width = 1i64 << level;
+
+            let expected = |boundary| {
+                let mut regions: Vec<_> = (0..27)
+                    .map(|i| Vector3::new(i % 3 - 1, i / 3 % 3 - 1, i / 9 - 1))
+                    .filter(|delta| *delta != Vector3::zeros())
+                    .filter_map(|delta| {
+                        let moved = cell + delta;
+                        let moved = match boundary {
+                            Boundary::Clamp if moved.iter().any(|&d| d < 0 || d >= width) => return None,
+                            Boundary::Clamp => moved,
+                            Boundary::Wrap => moved.map(|d| (d + width) % width),
+                        };
+                        Some(MortonRegion {
+                            morton: u64::encode(moved.map(|d| d as u64)) << cut,
+                            level,
+                        })
+                    })
+                    .collect();
+                regions.sort();
+                regions
+            };
+
+            for &boundary in &[Boundary::Clamp, Boundary::Wrap] {
+                let mut found: Vec<_> = region.neighbors(boundary).collect();
+                found.sort();
+                assert_eq!(found, expected(boundary));
+                assert_eq!(
+                    region.face_neighbors(boundary).count()
+                        + region.edge_neighbors(boundary).count()
+                        + region.corner_neighbors(boundary).count(),
+                    found.len()
+                );
+            }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use crate::morton::Morton;
 use nalgebra::Vector3;
-use num_traits::{Float, FromPrimitive, ToPrimitive};
+use num_traits::{Float, FromPrimitive, PrimInt, ToPrimitive};
 use std::hash::{Hash, Hasher};
 
 /// This wraps a morton to convey special external trait implementations to it that are specific to mortons.
@@ -22,17 +22,19 @@ where
     }
 }
 
-#[allow(clippy::derive_hash_xor_eq)]
+#[allow(clippy::derived_hash_with_manual_eq)]
 impl<M> Hash for MortonWrapper<M>
 where
-    M: Morton,
+    M: PrimInt + FromPrimitive,
 {
     #[inline]
     fn hash<H>(&self, state: &mut H)
     where
         H: Hasher,
     {
-        state.write_u64((self.0 & M::from_u64(!0).unwrap()).to_u64().unwrap())
+        // Mortons wider than 64 bits are truncated, while narrower ones already fit.
+        let low = M::from_u64(!0).unwrap_or_else(M::max_value);
+        state.write_u64((self.0 & low).to_u64().unwrap());
     }
 }
 
@@ -57,14 +59,14 @@ where
     }
 }
 
-impl<S, M> Into<Vector3<S>> for MortonWrapper<M>
+impl<S, M> From<MortonWrapper<M>> for Vector3<S>
 where
     M: Morton,
     S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
 {
     #[inline]
-    fn into(self) -> Vector3<S> {
-        let point = self.0.decode();
+    fn from(wrapper: MortonWrapper<M>) -> Self {
+        let point = wrapper.0.decode();
         #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
         let scale = (S::one() + S::one()).powi(-(M::dim_bits() as i32));
 
@@ -0,0 +1,285 @@
+//! This module contains helpers to work with 2d morton codes, otherwise known as a 2d z-order curve.
+//!
+//! The `Morton2` trait shares its method names with `Morton`, so it is not re-exported at the crate root.
+//! This avoids ambiguity when calling methods on primitives that implement both.
+
+mod region;
+
+pub use self::region::*;
+
+use crate::morton::{encoding, MortonBuildHasher, MortonWrapper};
+use nalgebra::Vector2;
+use num_traits::{Float, FromPrimitive, PrimInt, ToPrimitive};
+use std::hash::Hash;
+
+/// Use this to map regions defined by a 2d z-order curve on a particular level to arbitrary objects.
+/// This uses a custom hasher that is optimized for z-order data locality.
+pub type Morton2RegionMap<T, M> = std::collections::HashMap<Morton2Region<M>, T, MortonBuildHasher>;
+/// Use this to have a set of regions defined by a 2d z-order curve on a particular level.
+/// This will not exclude subset regions.
+/// This uses a custom hasher that is optimized for z-order data locality.
+pub type Morton2RegionSet<M> = std::collections::HashSet<Morton2Region<M>, MortonBuildHasher>;
+/// Use this to map regions defined by a 2d z-order curve on a particular level to arbitrary objects.
+/// This uses a custom hasher that is optimized for z-order data locality.
+/// This also uses an LRU cache under the hood so memory can be preserved.
+pub type Morton2RegionCache<T, M> = lru_cache::LruCache<Morton2Region<M>, T, MortonBuildHasher>;
+
+/// A 2d Z-order encoding, which partitions a bounded plane into finite, but localized, linear boxes.
+///
+/// This mirrors `Morton`, but each level has `4` quadrants rather than `8` octants.
+pub trait Morton2: PrimInt + FromPrimitive + ToPrimitive + Hash + std::fmt::Debug + 'static {
+    /// This is the total number of bits in the primitive.
+    const BITS: usize;
+
+    /// Encode the two dimensions (x, y) into a morton code.
+    fn encode(dims: Vector2<Self>) -> Self;
+    /// Decode the morton code into the two individual dimensions (x, y).
+    fn decode(self) -> Vector2<Self>;
+
+    /// The number of bits used to represent each dimension.
+    ///
+    /// Unlike with `Morton`, two dimensions divide the primitive evenly, so one level is left unused.
+    /// This is required so that `null` and `Morton2Region::canonicalize` have spare bits to work with.
+    #[inline]
+    #[must_use]
+    fn dim_bits() -> usize {
+        Self::BITS / 2 - 1
+    }
+
+    /// The highest level of the morton code's bits.
+    #[inline]
+    #[must_use]
+    fn highest_bits() -> Self {
+        Self::from_u8(0b11).unwrap() << (2 * (Self::dim_bits() - 1))
+    }
+
+    /// The bits in the morton that are used. The top `2` bits are always unused.
+    #[inline]
+    #[must_use]
+    fn used_bits() -> Self {
+        (Self::one() << (2 * Self::dim_bits())) - Self::one()
+    }
+
+    /// Same as `used_bits`, but its instead the mask of the bits not in use.
+    #[inline]
+    #[must_use]
+    fn unused_bits() -> Self {
+        !Self::used_bits()
+    }
+
+    /// Get the bits being used in a morton code with a particular level.
+    ///
+    /// If the level of a morton is 0, then we get only 2 bits from the "first" level.
+    /// This can only be called when `level` is in the range `[0, Self::dim_bits())`.
+    #[inline]
+    #[must_use]
+    fn get_significant_bits(self, level: usize) -> Self {
+        self >> (2 * (Self::dim_bits() - level - 1))
+    }
+
+    /// This is similar to `get_significant_bits`, but it also masks out all the levels above the specific
+    /// one chosen so that a number from `[0, 4)` is returned, which allows the choosing of a quadrant at
+    /// that `level`.
+    #[inline]
+    fn get_level(self, level: usize) -> usize {
+        (self.get_significant_bits(level) & Self::from_u8(0b11).unwrap())
+            .to_usize()
+            .unwrap()
+    }
+
+    /// Gets the mask of a particular `level`.
+    #[inline]
+    #[must_use]
+    fn level_mask(level: usize) -> Self {
+        Self::highest_bits() >> (2 * level)
+    }
+
+    /// This will set the `level` of a morton code. The passed val must be in the range `[0, 4)`.
+    #[inline]
+    fn set_level(&mut self, level: usize, val: usize) {
+        assert!(
+            level < Self::dim_bits(),
+            "Morton2::set_level: got invalid level {} (max is {})",
+            level,
+            Self::dim_bits() - 1
+        );
+        self.reset_level(level);
+        *self = *self | Self::from_usize(val).unwrap() << (2 * (Self::dim_bits() - level - 1));
+    }
+
+    /// This sets a particular `level` in a morton code to `0`.
+    #[inline]
+    fn reset_level(&mut self, level: usize) {
+        *self = *self & !Self::level_mask(level);
+    }
+
+    /// Because the upper bits are never set in the morton code, it is possible to create a unique morton code
+    /// that doesn't represent an actual place in a quadtree which can be used as a null morton code.
+    #[inline]
+    #[must_use]
+    fn null() -> Self {
+        !Self::zero()
+    }
+
+    /// This checks if a morton code is the null code obtained from `Self::null()`.
+    #[inline]
+    fn is_null(self) -> bool {
+        self == Self::null()
+    }
+}
+
+macro_rules! small_morton2 {
+    ($ty: ty, $bits: expr) => {
+        impl Morton2 for $ty {
+            const BITS: usize = $bits;
+
+            #[inline]
+            #[allow(clippy::cast_lossless, clippy::cast_possible_truncation)]
+            fn encode(dims: Vector2<Self>) -> Self {
+                let mask = (1 << Self::dim_bits()) - 1;
+                <u64 as Morton2>::encode(dims.map(|d| (d & mask) as u64)) as Self
+            }
+
+            #[inline]
+            #[allow(clippy::cast_lossless, clippy::cast_possible_truncation)]
+            fn decode(self) -> Vector2<Self> {
+                Morton2::decode((self & Self::used_bits()) as u64).map(|d| d as Self)
+            }
+        }
+    };
+}
+
+small_morton2!(u16, 16);
+small_morton2!(u32, 32);
+
+impl Morton2 for u64 {
+    const BITS: usize = 64;
+
+    /// Encode a Vector2<u64> into a morton code.
+    ///
+    /// ```
+    /// use space::morton2::Morton2;
+    /// use nalgebra::Vector2;
+    ///
+    /// let morton_code = Morton2::encode(Vector2::<u64>::new(1, 2));
+    /// assert_eq!(morton_code, 9);
+    /// ```
+    #[inline]
+    fn encode(dims: Vector2<Self>) -> Self {
+        let mask = (1 << Self::dim_bits()) - 1;
+        #[cfg(feature = "bmi2")]
+        let morton = encoding::encode2_u64_bmi2(dims.x & mask, dims.y & mask);
+        #[cfg(not(feature = "bmi2"))]
+        let morton = encoding::encode2_u64(dims.x & mask, dims.y & mask);
+        morton
+    }
+
+    /// Decode a u64 morton to its associated Vector2<u64>
+    ///
+    /// ```
+    /// use space::morton2::Morton2;
+    /// use nalgebra::Vector2;
+    ///
+    /// let coordinates = Morton2::decode(9u64);
+    /// assert_eq!(coordinates, Vector2::<u64>::new(1, 2));
+    /// ```
+    #[inline]
+    fn decode(self) -> Vector2<Self> {
+        let morton = self & Self::used_bits();
+        #[cfg(feature = "bmi2")]
+        let (x, y) = encoding::decode2_u64_bmi2(morton);
+        #[cfg(not(feature = "bmi2"))]
+        let (x, y) = encoding::decode2_u64(morton);
+        Vector2::new(x, y)
+    }
+}
+
+impl Morton2 for u128 {
+    const BITS: usize = 128;
+
+    /// Encode a Vector2<u128> into a morton code.
+    ///
+    /// ```
+    /// use space::morton2::Morton2;
+    /// use nalgebra::Vector2;
+    ///
+    /// let morton_code = Morton2::encode(Vector2::<u128>::new(1, 2));
+    /// assert_eq!(morton_code, 9);
+    /// ```
+    #[inline]
+    #[allow(clippy::cast_possible_truncation)]
+    fn encode(dims: Vector2<Self>) -> Self {
+        let [x, y]: [Self; 2] = dims.into();
+        let low_mask = (1 << 32) - 1;
+        let high_mask = (1 << (Self::dim_bits() - 32)) - 1;
+        let low = encoding::encode2_u64((x & low_mask) as u64, (y & low_mask) as u64);
+        let high = encoding::encode2_u64((x >> 32 & high_mask) as u64, (y >> 32 & high_mask) as u64);
+        Self::from(high) << 64 | Self::from(low)
+    }
+
+    /// Decode a u128 morton to its associated Vector2<u128>
+    ///
+    /// ```
+    /// use space::morton2::Morton2;
+    /// use nalgebra::Vector2;
+    ///
+    /// let coordinates = Morton2::decode(9u128);
+    /// assert_eq!(coordinates, Vector2::<u128>::new(1, 2));
+    /// ```
+    #[inline]
+    #[allow(clippy::cast_possible_truncation)]
+    fn decode(self) -> Vector2<Self> {
+        let morton = self & Self::used_bits();
+        let (lowx, lowy) = encoding::decode2_u64(morton as u64);
+        let (highx, highy) = encoding::decode2_u64((morton >> 64) as u64);
+        Vector2::new(
+            Self::from(highx) << 32 | Self::from(lowx),
+            Self::from(highy) << 32 | Self::from(lowy),
+        )
+    }
+}
+
+impl<S, M> From<Vector2<S>> for MortonWrapper<M>
+where
+    M: Morton2,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(point: Vector2<S>) -> Self {
+        let point = point.map(|d| {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+            let d = d * (S::one() + S::one()).powi(M::dim_bits() as i32);
+            M::from_u64(d.to_u64().unwrap()).unwrap()
+        });
+        Self(M::encode(point))
+    }
+}
+
+impl<S, M> From<MortonWrapper<M>> for Vector2<S>
+where
+    M: Morton2,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(wrapper: MortonWrapper<M>) -> Self {
+        let point = wrapper.0.decode();
+        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+        let scale = (S::one() + S::one()).powi(-(M::dim_bits() as i32));
+
+        point.map(|d| {
+            (S::from_u64(d.to_u64().unwrap()).unwrap() + S::from_f32(0.5).unwrap()) * scale
+        })
+    }
+}
+
+#[test]
+fn test_morton2_roundtrip() {
+    for m in 0..1u16 << 14 {
+        assert_eq!(<u16 as Morton2>::encode(m.decode()), m);
+        assert_eq!(u64::from(m), Morton2::encode(m.decode().map(u64::from)));
+    }
+    let m = u128::used_bits() / 3;
+    assert_eq!(<u128 as Morton2>::encode(m.decode()), m);
+    let v = Vector2::new(u128::used_bits() >> 64, 12345);
+    assert_eq!(Morton2::decode(<u128 as Morton2>::encode(v)), v);
+}
@@ -0,0 +1,237 @@
+use crate::morton2::Morton2;
+
+use nalgebra::Vector2;
+use num_traits::{Float, FromPrimitive, ToPrimitive};
+use std::cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd};
+use std::hash::{Hash, Hasher};
+
+/// Defines a region by dividing a finite plane into a 2d z-order curve of `level` and uses the upper bits
+/// of `morton`.
+#[derive(Debug, Clone, Copy)]
+pub struct Morton2Region<M> {
+    /// The most significant `level * 2` bits of this morton encode the cell of the z-order curve this is a part of.
+    pub morton: M,
+    /// This defines the level of the z-order curve.
+    ///
+    /// A `level` of `0` is the whole plane.
+    /// A `level` of `1` means the region is one of the 4 top level quadrants of the plane.
+    /// Level cannot exceed `M::dim_bits()` or there wont be enough bits to encode the morton.
+    pub level: usize,
+}
+
+impl<M> Morton2Region<M>
+where
+    M: Morton2,
+{
+    /// This gets the top level region (the whole plane).
+    #[inline]
+    #[must_use]
+    pub fn base() -> Self {
+        Self {
+            morton: M::zero(),
+            level: 0,
+        }
+    }
+
+    /// Get the bits that are actually used to encode different levels in the morton.
+    #[inline]
+    pub fn significant_bits(self) -> M {
+        self.morton.get_significant_bits(self.level)
+    }
+
+    /// Enter a quadrant in the region.
+    ///
+    /// Note that this does not mutate the region, but returns a new one. This can be reversed by calling `exit()`.
+    #[inline]
+    #[must_use]
+    pub fn enter(mut self, quadrant: usize) -> Self {
+        self.morton.set_level(self.level, quadrant);
+        self.level += 1;
+        self
+    }
+
+    /// Changes the region to its parent region by going up one level.
+    #[inline]
+    pub fn exit(&mut self) -> usize {
+        self.level -= 1;
+        let old = self.morton.get_level(self.level);
+        self.morton.reset_level(self.level);
+        old
+    }
+
+    /// Gets the least-significant quadrant of the region.
+    #[inline]
+    pub fn get(&self) -> usize {
+        self.morton.get_level(self.level - 1)
+    }
+
+    /// Gets the next quadrant when iterating in z-order over the least significant quadrant.
+    ///
+    /// This gives back None when it is on the last quadrant or if the level is `0`.
+    #[inline]
+    pub fn next(mut self) -> Option<Self> {
+        if self.level == 0 {
+            None
+        } else {
+            let last = self.exit();
+            if last == 3 {
+                None
+            } else {
+                Some(self.enter(last + 1))
+            }
+        }
+    }
+
+    /// Produces a single number that has a canonically unique mapping to every given valid `Morton2Region` by
+    /// using the unused bits to store the level information via shifting.
+    #[inline]
+    pub fn canonicalize(&self) -> M {
+        if self.level == 0 {
+            M::zero()
+        } else {
+            (self.morton | M::unused_bits()).get_significant_bits(self.level - 1)
+        }
+    }
+
+    /// Iterates over subregions of a region. Uses `explore` to limit the exploration space.
+    pub fn iter<E>(self, explore: E) -> Morton2RegionIterator<M, E>
+    where
+        E: FnMut(Self) -> bool,
+    {
+        Morton2RegionIterator {
+            nodes: vec![self],
+            explore,
+        }
+    }
+
+    /// Checks if a morton is contained in the region.
+    ///
+    /// ```
+    /// let region = space::morton2::Morton2Region { morton: 0x3000_0000_0000_0000u64, level: 1 };
+    /// assert!(region.contains(0x3123_1234_1234_1234));
+    /// assert!(!region.contains(0x2123_1234_1234_1234));
+    /// assert!(region.contains(0x3f00_0000_0000_0000));
+    /// ```
+    pub fn contains(&self, morton: M) -> bool {
+        // A region at `level` is only defined by the levels above it.
+        self.level == 0
+            || self.morton.get_significant_bits(self.level - 1) == morton.get_significant_bits(self.level - 1)
+    }
+}
+
+impl<M> PartialEq for Morton2Region<M>
+where
+    M: Morton2,
+{
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.canonicalize().eq(&other.canonicalize())
+    }
+}
+
+impl<M> Eq for Morton2Region<M> where M: Morton2 {}
+
+impl<M> PartialOrd for Morton2Region<M>
+where
+    M: Morton2,
+{
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<M> Ord for Morton2Region<M>
+where
+    M: Morton2,
+{
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.canonicalize().cmp(&other.canonicalize())
+    }
+}
+
+impl<M> Default for Morton2Region<M>
+where
+    M: Morton2,
+{
+    #[inline]
+    fn default() -> Self {
+        Self::base()
+    }
+}
+
+#[allow(clippy::derived_hash_with_manual_eq)]
+impl<M> Hash for Morton2Region<M>
+where
+    M: Morton2,
+{
+    #[inline]
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: Hasher,
+    {
+        self.canonicalize().hash(state);
+    }
+}
+
+impl<S, M> From<Morton2Region<M>> for Vector2<S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton2,
+{
+    #[inline]
+    fn from(region: Morton2Region<M>) -> Self {
+        let cut = M::dim_bits() - region.level;
+        let point = (region.morton >> (2 * cut)).decode();
+        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+        let scale = (S::one() + S::one()).powi(-(region.level as i32));
+
+        point.map(|d| {
+            (S::from_u64(d.to_u64().unwrap()).unwrap() + S::from_f32(0.5).unwrap()) * scale
+        })
+    }
+}
+
+/// Generates regions over every level of this morton from the root region (`level` `0`)
+/// to the least significant level (`level` `M::dim_bits()`).
+#[inline]
+pub fn morton2_levels<M>(m: M) -> impl Iterator<Item = Morton2Region<M>>
+where
+    M: Morton2,
+{
+    std::iter::once(Morton2Region::default()).chain((1..=M::dim_bits()).map(move |i| Morton2Region {
+        morton: m.get_significant_bits(i - 1) << (2 * (M::dim_bits() - i)),
+        level: i,
+    }))
+}
+
+/// An `Iterator` over a `Morton2Region` that uses a closure to limit the exploration space.
+///
+/// Produced by `Morton2Region::iter`.
+pub struct Morton2RegionIterator<M, E> {
+    nodes: Vec<Morton2Region<M>>,
+    explore: E,
+}
+
+impl<M, E> Iterator for Morton2RegionIterator<M, E>
+where
+    M: Morton2,
+    E: FnMut(Morton2Region<M>) -> bool,
+{
+    type Item = Morton2Region<M>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.nodes.pop().map(|region| {
+            if let Some(next) = region.next() {
+                self.nodes.push(next);
+            }
+
+            if region.level < M::dim_bits() && (self.explore)(region) {
+                self.nodes.push(region.enter(0));
+            }
+            region
+        })
+    }
+}
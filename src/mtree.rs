@@ -0,0 +1,607 @@
+//! An M-tree over points in any metric space.
+
+use crate::queue::Queued;
+
+use num_traits::Float;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// The most entries that a node can have before it is split.
+const MAX_ENTRIES: usize = 16;
+
+/// A distance function between points of any type, which an `MTree` is searched with.
+///
+/// Unlike `Distance`, which works on coordinates in 3d space, the points can be anything, such as binary feature
+/// descriptors or high dimensional vectors. The tree only relies on the distance being a metric, so it must be zero
+/// only between equal points, symmetric and obey the triangle inequality. A function that is not a metric, such as
+/// the cosine distance `1 - cos`, makes the tree miss points that it should find.
+pub trait Metric<P, S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// Gets the distance between two points.
+    fn distance(&self, a: &P, b: &P) -> S;
+}
+
+/// The number of bits that differ between two strings of bytes of the same length, such as binary feature
+/// descriptors.
+///
+/// ```
+/// use space::{Hamming, Metric};
+///
+/// let distance: f32 = Hamming.distance(&[0b1010u8, 0xff], &[0b0011, 0xff]);
+/// assert_eq!(distance, 2.0);
+/// ```
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Hamming;
+
+impl<P, S> Metric<P, S> for Hamming
+where
+    P: AsRef<[u8]>,
+    S: Float + std::fmt::Debug + 'static,
+{
+    fn distance(&self, a: &P, b: &P) -> S {
+        let (a, b) = (a.as_ref(), b.as_ref());
+        assert_eq!(a.len(), b.len(), "space::Hamming::distance(): the points must have the same length");
+        let bits: u32 = a.iter().zip(b).map(|(a, b)| (a ^ b).count_ones()).sum();
+        S::from(bits).unwrap()
+    }
+}
+
+/// The angle in radians between two vectors of the same length, which are usually high dimensional embeddings.
+///
+/// This finds the same nearest neighbors as cosine similarity, but unlike the cosine distance it is a metric. A zero
+/// vector is at a right angle to every other vector.
+///
+/// ```
+/// use space::{Angular, Metric};
+///
+/// let distance: f64 = Angular.distance(&[1.0, 0.0], &[1.0, 1.0]);
+/// assert!((distance - std::f64::consts::FRAC_PI_4).abs() < 1e-12);
+/// ```
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Angular;
+
+impl<P, S> Metric<P, S> for Angular
+where
+    P: AsRef<[S]>,
+    S: Float + std::fmt::Debug + 'static,
+{
+    fn distance(&self, a: &P, b: &P) -> S {
+        let (a, b) = (a.as_ref(), b.as_ref());
+        assert_eq!(a.len(), b.len(), "space::Angular::distance(): the points must have the same length");
+        let dot = |a: &[S], b: &[S]| a.iter().zip(b).fold(S::zero(), |sum, (&a, &b)| sum + a * b);
+        let norms = (dot(a, a) * dot(b, b)).sqrt();
+        if norms == S::zero() {
+            let right_angle = S::from(std::f64::consts::FRAC_PI_2).unwrap();
+            return if dot(a, a) == dot(b, b) { S::zero() } else { right_angle };
+        }
+        (dot(a, b) / norms).max(-S::one()).min(S::one()).acos()
+    }
+}
+
+/// An M-tree, which holds items at points in a metric space and finds the points within a distance of or nearest to
+/// another point.
+///
+/// The tree never looks inside of the points. It only measures distances between them with `M`, so it works for
+/// data where mortons and coordinates make no sense, such as binary descriptors with the `Hamming` metric or
+/// embeddings with the `Angular` metric. Each node covers a ball around one of its points, and the distances that were
+/// measured while building the tree are kept to rule out points during searches without measuring them again. Any
+/// number of items can be at the same point.
+///
+/// ```
+/// use space::{Hamming, MTree};
+///
+/// let mut tree = MTree::new(Hamming);
+/// tree.insert([0b0000_1111u8], "a");
+/// tree.insert([0b0000_0111u8], "b");
+/// tree.insert([0b1111_0000u8], "c");
+///
+/// let nearest: Vec<(&str, f32)> = tree
+///     .knn(&[0b0000_0011], 2)
+///     .into_iter()
+///     .map(|(_, &name, distance)| (name, distance))
+///     .collect();
+/// assert_eq!(nearest, vec![("b", 1.0), ("a", 2.0)]);
+/// ```
+pub struct MTree<P, T, S, M>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    root: Node<P, T, S>,
+    metric: M,
+    len: usize,
+}
+
+impl<P, T, S, M> MTree<P, T, S, M>
+where
+    P: Clone,
+    S: Float + std::fmt::Debug + 'static,
+    M: Metric<P, S>,
+{
+    /// Creates an empty tree which measures distances with `metric`.
+    pub fn new(metric: M) -> Self {
+        Self {
+            root: Node::Leaf(Vec::new()),
+            metric,
+            len: 0,
+        }
+    }
+
+    /// Gets the metric that the tree measures distances with.
+    pub fn metric(&self) -> &M {
+        &self.metric
+    }
+
+    /// Inserts an item at a point. Items that are already at the same point are kept.
+    pub fn insert(&mut self, point: P, item: T) {
+        let leaf = LeafEntry {
+            point,
+            item,
+            parent_distance: S::zero(),
+        };
+        if let Some((a, b)) = insert_into(&mut self.root, None, leaf, &self.metric) {
+            self.root = Node::Internal(vec![a, b]);
+        }
+        self.len += 1;
+    }
+
+    /// Finds every item within `radius` of `point` (inclusive), along with its point and distance. The items are not
+    /// given back in any particular order.
+    ///
+    /// ```
+    /// use space::{Hamming, MTree};
+    ///
+    /// let tree: MTree<_, _, f64, _> = MTree::from_iter(Hamming, (0..=255u8).map(|n| ([n], n)));
+    /// let found = tree.within(&[0b1111_1111], 1.0);
+    /// // The point itself and the 8 points with a single bit flipped.
+    /// assert_eq!(found.len(), 9);
+    /// assert!(found.iter().all(|&(_, &n, _)| n.count_zeros() <= 1));
+    /// ```
+    pub fn within(&self, point: &P, radius: S) -> Vec<(&P, &T, S)> {
+        let mut found = Vec::new();
+        // Each node is paired with the distance from `point` to the center of its ball, if it has one.
+        let mut nodes = vec![(&self.root, None)];
+        while let Some((node, center_distance)) = nodes.pop() {
+            match node {
+                Node::Leaf(entries) => {
+                    for entry in entries {
+                        if lower_bound(center_distance, entry.parent_distance, S::zero()) > radius {
+                            continue;
+                        }
+                        let distance = self.metric.distance(point, &entry.point);
+                        if distance <= radius {
+                            found.push((&entry.point, &entry.item, distance));
+                        }
+                    }
+                }
+                Node::Internal(entries) => {
+                    for entry in entries {
+                        if lower_bound(center_distance, entry.parent_distance, entry.radius) > radius {
+                            continue;
+                        }
+                        let distance = self.metric.distance(point, &entry.point);
+                        if distance <= radius + entry.radius {
+                            nodes.push((&entry.child, Some(distance)));
+                        }
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Finds the `k` items closest to `point`, sorted from closest to farthest, along with their points and distances.
+    ///
+    /// Entries are visited in order of the smallest distance that they could be from `point`, and the distance to an
+    /// entry is only measured once it is the closest candidate left.
+    pub fn knn(&self, point: &P, k: usize) -> Vec<(&P, &T, S)> {
+        let mut found = Vec::with_capacity(k);
+        if k == 0 {
+            return found;
+        }
+        let mut queue = BinaryHeap::new();
+        queue.push(Queued {
+            distance: S::zero(),
+            candidate: Candidate::Node(&self.root, None),
+        });
+        while let Some(Queued { distance: d, candidate }) = queue.pop() {
+            match candidate {
+                Candidate::Item(entry) => {
+                    // Nothing left in the queue can be closer than this item.
+                    found.push((&entry.point, &entry.item, d));
+                    if found.len() == k {
+                        break;
+                    }
+                }
+                Candidate::Leaf(entry) => queue.push(Queued {
+                    distance: self.metric.distance(point, &entry.point),
+                    candidate: Candidate::Item(entry),
+                }),
+                Candidate::Routing(entry) => {
+                    let distance = self.metric.distance(point, &entry.point);
+                    queue.push(Queued {
+                        distance: (distance - entry.radius).max(S::zero()),
+                        candidate: Candidate::Node(&entry.child, Some(distance)),
+                    });
+                }
+                Candidate::Node(Node::Leaf(entries), center_distance) => {
+                    queue.extend(entries.iter().map(|entry| Queued {
+                        distance: lower_bound(center_distance, entry.parent_distance, S::zero()),
+                        candidate: Candidate::Leaf(entry),
+                    }));
+                }
+                Candidate::Node(Node::Internal(entries), center_distance) => {
+                    queue.extend(entries.iter().map(|entry| Queued {
+                        distance: lower_bound(center_distance, entry.parent_distance, entry.radius),
+                        candidate: Candidate::Routing(entry),
+                    }));
+                }
+            }
+        }
+        found
+    }
+
+    /// Finds the item closest to `point`. See `knn`.
+    pub fn nearest(&self, point: &P) -> Option<(&P, &T, S)> {
+        self.knn(point, 1).pop()
+    }
+
+    /// Iterates over every item and its point, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&P, &T)> {
+        let mut items = Vec::with_capacity(self.len);
+        let mut nodes = vec![&self.root];
+        while let Some(node) = nodes.pop() {
+            match node {
+                Node::Leaf(entries) => items.extend(entries.iter().map(|entry| (&entry.point, &entry.item))),
+                Node::Internal(entries) => nodes.extend(entries.iter().map(|entry| &entry.child)),
+            }
+        }
+        items.into_iter()
+    }
+
+    /// Removes every item from the tree.
+    pub fn clear(&mut self) {
+        self.root = Node::Leaf(Vec::new());
+        self.len = 0;
+    }
+
+    /// Returns the number of items in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Checks if the tree is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Creates a tree which measures distances with `metric` and inserts every item into it.
+    pub fn from_iter<I>(metric: M, it: I) -> Self
+    where
+        I: IntoIterator<Item = (P, T)>,
+    {
+        let mut tree = Self::new(metric);
+        tree.extend(it);
+        tree
+    }
+}
+
+impl<P, T, S, M> Default for MTree<P, T, S, M>
+where
+    P: Clone,
+    S: Float + std::fmt::Debug + 'static,
+    M: Metric<P, S> + Default,
+{
+    /// Create an empty tree with the default metric.
+    fn default() -> Self {
+        Self::new(M::default())
+    }
+}
+
+impl<P, T, S, M> Extend<(P, T)> for MTree<P, T, S, M>
+where
+    P: Clone,
+    S: Float + std::fmt::Debug + 'static,
+    M: Metric<P, S>,
+{
+    fn extend<I>(&mut self, it: I)
+    where
+        I: IntoIterator<Item = (P, T)>,
+    {
+        for (point, item) in it {
+            self.insert(point, item);
+        }
+    }
+}
+
+enum Node<P, T, S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    Leaf(Vec<LeafEntry<P, T, S>>),
+    Internal(Vec<RoutingEntry<P, T, S>>),
+}
+
+struct LeafEntry<P, T, S> {
+    point: P,
+    item: T,
+    /// The distance from `point` to the center of the ball of the node that holds this entry.
+    parent_distance: S,
+}
+
+struct RoutingEntry<P, T, S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// The center of the ball that covers every point under `child`.
+    point: P,
+    radius: S,
+    /// The distance from `point` to the center of the ball of the node that holds this entry.
+    parent_distance: S,
+    child: Node<P, T, S>,
+}
+
+/// An entry of either kind of node, which can be split into groups.
+trait Entry<P, S> {
+    fn point(&self) -> &P;
+
+    /// Gets the radius of the ball around `point` that the entry covers.
+    fn radius(&self) -> S;
+
+    fn set_parent_distance(&mut self, distance: S);
+}
+
+impl<P, T, S> Entry<P, S> for LeafEntry<P, T, S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    fn point(&self) -> &P {
+        &self.point
+    }
+
+    fn radius(&self) -> S {
+        S::zero()
+    }
+
+    fn set_parent_distance(&mut self, distance: S) {
+        self.parent_distance = distance;
+    }
+}
+
+impl<P, T, S> Entry<P, S> for RoutingEntry<P, T, S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    fn point(&self) -> &P {
+        &self.point
+    }
+
+    fn radius(&self) -> S {
+        self.radius
+    }
+
+    fn set_parent_distance(&mut self, distance: S) {
+        self.parent_distance = distance;
+    }
+}
+
+/// The entries of the two nodes that a node was split into.
+type Split<P, T, S> = (RoutingEntry<P, T, S>, RoutingEntry<P, T, S>);
+
+/// Inserts `leaf` under `node`, where `center` is the center of the ball of `node`, if it has one, and the
+/// `parent_distance` of `leaf` is its distance from `center`.
+///
+/// When `node` gets too many entries, it is split and the entries for the two new nodes are given back to replace
+/// the entry of `node` in its parent, which has to set their `parent_distance`.
+fn insert_into<P, T, S, M>(
+    node: &mut Node<P, T, S>,
+    center: Option<&P>,
+    mut leaf: LeafEntry<P, T, S>,
+    metric: &M,
+) -> Option<Split<P, T, S>>
+where
+    P: Clone,
+    S: Float + std::fmt::Debug + 'static,
+    M: Metric<P, S>,
+{
+    match node {
+        Node::Leaf(entries) => {
+            entries.push(leaf);
+            if entries.len() > MAX_ENTRIES {
+                return Some(split_node(std::mem::take(entries), Node::Leaf, metric));
+            }
+        }
+        Node::Internal(entries) => {
+            let distances: Vec<S> = entries.iter().map(|entry| metric.distance(&leaf.point, &entry.point)).collect();
+            // Prefer the closest ball that already covers the point, otherwise the ball that has to grow the least.
+            let ix = (0..entries.len())
+                .min_by(|&a, &b| {
+                    let key = |ix: usize| {
+                        let growth = distances[ix] - entries[ix].radius;
+                        if growth > S::zero() {
+                            (true, growth)
+                        } else {
+                            (false, distances[ix])
+                        }
+                    };
+                    key(a).partial_cmp(&key(b)).unwrap_or(Ordering::Equal)
+                })
+                .expect("space::MTree::insert(): an internal node has no entries");
+            leaf.parent_distance = distances[ix];
+            let entry = &mut entries[ix];
+            entry.radius = entry.radius.max(distances[ix]);
+            if let Some((a, b)) = insert_into(&mut entry.child, Some(&entry.point), leaf, metric) {
+                entries.swap_remove(ix);
+                for mut new in IntoIterator::into_iter([a, b]) {
+                    new.parent_distance = center.map_or(S::zero(), |center| metric.distance(center, &new.point));
+                    entries.push(new);
+                }
+                if entries.len() > MAX_ENTRIES {
+                    return Some(split_node(std::mem::take(entries), Node::Internal, metric));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Splits entries into two new nodes with `node` and gives back the entries for the new nodes.
+///
+/// Every pair of entries is tried as the centers of the new balls, with each entry going to the closer center, and
+/// the pair whose larger ball is the smallest is used.
+fn split_node<P, T, S, M, E, F>(entries: Vec<E>, node: F, metric: &M) -> Split<P, T, S>
+where
+    P: Clone,
+    S: Float + std::fmt::Debug + 'static,
+    M: Metric<P, S>,
+    E: Entry<P, S>,
+    F: Fn(Vec<E>) -> Node<P, T, S>,
+{
+    let n = entries.len();
+    let mut distances = vec![S::zero(); n * n];
+    for i in 0..n {
+        for j in i + 1..n {
+            let distance = metric.distance(entries[i].point(), entries[j].point());
+            distances[i * n + j] = distance;
+            distances[j * n + i] = distance;
+        }
+    }
+    let distance = |i: usize, j: usize| distances[i * n + j];
+    // Gives back which entries go to `a` rather than `b` along with the radii of both balls.
+    let partition = |a: usize, b: usize| {
+        let mut to_a = vec![false; n];
+        let mut counts = (0, 0);
+        let mut radii = (S::zero(), S::zero());
+        for ix in 0..n {
+            let (da, db) = (distance(a, ix), distance(b, ix));
+            // Ties go to the smaller group, so that equal points are spread out.
+            let closer_to_a = ix == a || ix != b && (da < db || da == db && counts.0 <= counts.1);
+            if closer_to_a {
+                counts.0 += 1;
+                radii.0 = radii.0.max(da + entries[ix].radius());
+            } else {
+                counts.1 += 1;
+                radii.1 = radii.1.max(db + entries[ix].radius());
+            }
+            to_a[ix] = closer_to_a;
+        }
+        (to_a, radii)
+    };
+    let mut best = ((0, 1), S::infinity());
+    for a in 0..n {
+        for b in a + 1..n {
+            let (_, radii) = partition(a, b);
+            let largest = radii.0.max(radii.1);
+            if largest < best.1 {
+                best = ((a, b), largest);
+            }
+        }
+    }
+    let (a, b) = best.0;
+    let (to_a, radii) = partition(a, b);
+    let centers = (entries[a].point().clone(), entries[b].point().clone());
+    let mut groups = (Vec::new(), Vec::new());
+    for (ix, mut entry) in entries.into_iter().enumerate() {
+        if to_a[ix] {
+            entry.set_parent_distance(distance(a, ix));
+            groups.0.push(entry);
+        } else {
+            entry.set_parent_distance(distance(b, ix));
+            groups.1.push(entry);
+        }
+    }
+    let routing = |point: P, radius: S, group: Vec<E>| RoutingEntry {
+        point,
+        radius,
+        parent_distance: S::zero(),
+        child: node(group),
+    };
+    (
+        routing(centers.0, radii.0, groups.0),
+        routing(centers.1, radii.1, groups.1),
+    )
+}
+
+/// Gets the smallest distance that a point could be from an entry with `radius`, using only the distance from the
+/// point to the center of the ball that holds the entry, if it has one, and the distance from the entry to that center.
+fn lower_bound<S>(center_distance: Option<S>, parent_distance: S, radius: S) -> S
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    center_distance.map_or(S::zero(), |center_distance| {
+        ((center_distance - parent_distance).abs() - radius).max(S::zero())
+    })
+}
+
+/// Something the nearest neighbor search still has to look at.
+///
+/// Entries start out with only a lower bound on their distance, which is replaced with their actual distance once
+/// they are the closest candidate.
+enum Candidate<'a, P, T, S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// A node along with the distance to the center of its ball, if it has one.
+    Node(&'a Node<P, T, S>, Option<S>),
+    Routing(&'a RoutingEntry<P, T, S>),
+    Leaf(&'a LeafEntry<P, T, S>),
+    Item(&'a LeafEntry<P, T, S>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Checks the tree against searching every point, with `point` making random points.
+    fn check_against_brute_force<P, M, F>(metric: &M, mut point: F)
+    where
+        P: Clone,
+        M: Metric<P, f64> + Clone,
+        F: FnMut(&mut SmallRng) -> P,
+    {
+        let mut rng = SmallRng::from_seed([25; 16]);
+        let points: Vec<P> = (0..2000).map(|_| point(&mut rng)).collect();
+        let tree = MTree::from_iter(metric.clone(), points.iter().cloned().zip(0..));
+        assert_eq!(tree.len(), points.len());
+        let mut all: Vec<usize> = tree.iter().map(|(_, &n)| n).collect();
+        all.sort_unstable();
+        assert_eq!(all, (0..points.len()).collect::<Vec<_>>());
+        for _ in 0..50 {
+            let query = point(&mut rng);
+            let distances: Vec<f64> = points.iter().map(|p| metric.distance(&query, p)).collect();
+
+            let k = rng.gen_range(1, 30);
+            let found: Vec<f64> = tree.knn(&query, k).into_iter().map(|(_, _, d)| d).collect();
+            let mut expected = distances.clone();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let radius = expected[k];
+            expected.truncate(k);
+            assert_eq!(found, expected);
+
+            let mut found: Vec<usize> = tree.within(&query, radius).into_iter().map(|(_, &n, _)| n).collect();
+            found.sort_unstable();
+            let expected: Vec<usize> = (0..points.len()).filter(|&n| distances[n] <= radius).collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_mtree_hamming_matches_brute_force() {
+        // Few bits means that many points are at the same distance or are the same point.
+        check_against_brute_force(&Hamming, |rng| [rng.gen::<u8>(), rng.gen::<u8>() & 0b11]);
+    }
+
+    #[test]
+    fn test_mtree_angular_matches_brute_force() {
+        check_against_brute_force(&Angular, |rng| {
+            let mut v = [0.0; 8];
+            for n in &mut v {
+                *n = rng.gen_range(-1.0, 1.0);
+            }
+            v
+        });
+    }
+}
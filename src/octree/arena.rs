@@ -0,0 +1,1362 @@
+use crate::morton::{Morton, MortonBox, MortonRegion, MortonRegionCache};
+use crate::octree::knn::{self, Candidate};
+use crate::octree::radius;
+use crate::octree::{Ball, Distance, Folder};
+use crate::StorageAccess;
+
+use nalgebra::Vector3;
+use num_traits::{Float, FromPrimitive, ToPrimitive};
+use rand::distributions::{Distribution, Standard};
+use rand::Rng;
+use std::collections::BTreeMap;
+
+/// An octree with the same shape as a `PointerOctree`, but which keeps all of its internal nodes in one `Vec`.
+///
+/// Children refer to internal nodes by their `u32` index in the arena rather than through a `Box`, so inserting
+/// doesn't need a heap allocation for every new node. Nodes that are freed by removals are kept on a free list and
+/// reused by later insertions, so the arena only shrinks when the tree is cleared.
+///
+/// This supports the same operations as `PointerOctree`.
+///
+/// ```
+/// use space::{ArenaOctree, Morton};
+/// use nalgebra::Vector3;
+///
+/// let mut tree = ArenaOctree::<&str, u64>::new();
+/// let m = Morton::encode(Vector3::new(1, 2, 3));
+/// tree.insert(m, "hello");
+/// assert_eq!(tree.get(m), Some(&"hello"));
+/// assert_eq!(tree.remove(m), Some("hello"));
+/// assert!(tree.is_empty());
+/// ```
+pub struct ArenaOctree<T, M> {
+    root: Slot<T, M>,
+    nodes: Vec<[Slot<T, M>; 8]>,
+    free: Vec<u32>,
+    count: usize,
+}
+
+impl<T, M> Default for ArenaOctree<T, M> {
+    /// Create an empty octree.
+    fn default() -> Self {
+        Self {
+            root: Slot::None,
+            nodes: Vec::new(),
+            free: Vec::new(),
+            count: 0,
+        }
+    }
+}
+
+impl<T, M> ArenaOctree<T, M>
+where
+    M: Morton,
+{
+    /// Create an empty octree. Calls Default impl.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty octree with room for `nodes` internal nodes before the arena needs to grow.
+    #[must_use]
+    pub fn with_capacity(nodes: usize) -> Self {
+        Self {
+            nodes: Vec::with_capacity(nodes),
+            ..Self::default()
+        }
+    }
+
+    /// Fetches an immutable reference to the value of a specific coordinate in the octree.
+    pub fn get(&self, morton: M) -> Option<&T> {
+        match self.slot(self.find(morton).0) {
+            Slot::Leaf(ref item, dest_morton) if *dest_morton == morton => Some(item),
+            _ => None,
+        }
+    }
+
+    /// Fetches a mutable reference to the value of a specific coordinate in the octree.
+    pub fn get_mut(&mut self, morton: M) -> Option<&mut T> {
+        match self.slot_mut(self.find(morton).0) {
+            Slot::Leaf(ref mut item, dest_morton) if *dest_morton == morton => Some(item),
+            _ => None,
+        }
+    }
+
+    /// Insert an item with a point and replace the existing item if they would both occupy the same space.
+    ///
+    /// ```
+    /// use space::{ArenaOctree, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let mut tree = ArenaOctree::<usize, u64>::new();
+    /// tree.extend((0..8).map(|x| (Morton::encode(Vector3::new(x, 0, 0)), x as usize)));
+    /// tree.insert(Morton::encode(Vector3::new(3, 0, 0)), 30);
+    /// assert_eq!(tree.len(), 8);
+    /// assert_eq!(tree.get(Morton::encode(Vector3::new(3, 0, 0))), Some(&30));
+    /// ```
+    pub fn insert(&mut self, morton: M, item: T) {
+        let (loc, level) = self.find(morton);
+        match self.slot_mut(loc) {
+            Slot::Leaf(ref mut leaf_item, dest_morton) if *dest_morton == morton => {
+                // If they have the same code then replace it.
+                // Don't increase the count here because we replaced only.
+                *leaf_item = item;
+            }
+            _ => {
+                self.place(loc, level, morton, item);
+                self.count += 1;
+            }
+        }
+    }
+
+    /// Gets the entry for a morton in the octree for in-place manipulation.
+    ///
+    /// ```
+    /// use space::{ArenaOctree, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let mut tree = ArenaOctree::<usize, u64>::new();
+    /// let m = Morton::encode(Vector3::new(1, 2, 3));
+    /// *tree.entry(m).or_insert(0) += 1;
+    /// tree.entry(m).and_modify(|n| *n += 1).or_insert(0);
+    /// assert_eq!(tree.get(m), Some(&2));
+    /// ```
+    pub fn entry(&mut self, morton: M) -> ArenaEntry<'_, T, M> {
+        let (loc, level) = self.find(morton);
+        if matches!(self.slot(loc), Slot::Leaf(_, dest_morton) if *dest_morton == morton) {
+            ArenaEntry::Occupied(ArenaOccupiedEntry {
+                tree: self,
+                loc,
+                morton,
+            })
+        } else {
+            ArenaEntry::Vacant(ArenaVacantEntry {
+                tree: self,
+                loc,
+                level,
+                morton,
+            })
+        }
+    }
+
+    /// Removes the item at a morton from the octree and gives it back, if it was present.
+    ///
+    /// Nodes that are left with a single leaf or no leaves are collapsed and put on the free list, so the tree has
+    /// the same shape as if the removed item was never inserted.
+    pub fn remove(&mut self, morton: M) -> Option<T> {
+        // Remember the nodes above the leaf so they can be collapsed from the bottom up.
+        let mut path = Vec::with_capacity(M::dim_bits());
+        let mut loc = Loc::Root;
+        while let Slot::Node(node) = *self.slot(loc) {
+            path.push((loc, node));
+            loc = Loc::Child(node, morton.get_level(path.len() - 1));
+        }
+        if !matches!(self.slot(loc), Slot::Leaf(_, dest_morton) if *dest_morton == morton) {
+            return None;
+        }
+        if let Slot::Leaf(item, _) = std::mem::replace(self.slot_mut(loc), Slot::None) {
+            self.count -= 1;
+            // A node that keeps its shape doesn't change its parent, so nothing above it can collapse either.
+            for &(loc, node) in path.iter().rev() {
+                if !self.collapse(loc, node) {
+                    break;
+                }
+            }
+            Some(item)
+        } else {
+            unreachable!("space::ArenaOctree::remove(): can only get a Leaf in this code area")
+        }
+    }
+
+    /// Iterate over all octree nodes and their morton codes in z-order.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (M, &'a T)>
+    where
+        T: 'a,
+    {
+        ArenaIter {
+            nodes: &self.nodes,
+            stack: vec![std::slice::from_ref(&self.root).iter()],
+        }
+    }
+
+    /// Iterate over all octree nodes and their morton codes in z-order, with mutable access to the items.
+    ///
+    /// ```
+    /// use space::{ArenaOctree, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let mut tree = ArenaOctree::<u64, u64>::new();
+    /// tree.extend((0..10).map(|x| (Morton::encode(Vector3::new(x, 0, 0)), x)));
+    /// for (_, item) in tree.iter_mut() {
+    ///     *item *= 2;
+    /// }
+    /// assert!(tree.iter().all(|(m, &item)| item == 2 * m.decode().x));
+    /// ```
+    pub fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (M, &'a mut T)>
+    where
+        T: 'a,
+    {
+        ArenaIterMut {
+            nodes: SplitNodes::new(&mut self.nodes),
+            stack: vec![std::slice::from_mut(&mut self.root).iter_mut()],
+        }
+    }
+
+    /// Iterate over all octree nodes, but stop at `depth` to randomly sample a point. See `PointerOctree::iter_rand`.
+    pub fn iter_rand<'a, R: Rng>(
+        &'a self,
+        depth: usize,
+        rng: &'a mut R,
+    ) -> impl Iterator<Item = (M, &'a T)> + 'a {
+        self.root.iter_rand(&self.nodes, depth, rng)
+    }
+
+    /// Folds every region of the tree that `explore` is never stopped at. See `PointerOctree::iter_fold`.
+    pub fn iter_fold<'a, F>(
+        &'a self,
+        folder: F,
+        cache: MortonRegionCache<F::Sum, M>,
+    ) -> ArenaFoldIter<'a, T, M, impl FnMut(MortonRegion<M>) -> bool + 'a, F, rand::ThreadRng>
+    where
+        F: Folder<T, M> + 'a,
+        F::Sum: Clone,
+        Standard: Distribution<M>,
+    {
+        // This uses `dim_bits` to avoid ever needing to use the rng (we cant go lower than that).
+        self.iter_fold_random(M::dim_bits(), |_| true, folder, rand::thread_rng(), cache)
+    }
+
+    /// Same as `iter_fold`, but randomly samples once starting at `depth` below the regions that `explore` stops at.
+    /// See `PointerOctree::iter_fold_random`.
+    pub fn iter_fold_random<'a, E, F, R>(
+        &'a self,
+        depth: usize,
+        explore: E,
+        folder: F,
+        rng: R,
+        cache: MortonRegionCache<F::Sum, M>,
+    ) -> ArenaFoldIter<'a, T, M, E, F, R>
+    where
+        R: Rng + 'a,
+        E: FnMut(MortonRegion<M>) -> bool + 'a,
+        F: Folder<T, M> + 'a,
+        F::Sum: Clone,
+        Standard: Distribution<M>,
+    {
+        ArenaFoldIter {
+            nodes: &self.nodes,
+            stack: vec![(&self.root, MortonRegion::base())],
+            explore,
+            folder,
+            depth,
+            rng,
+            cache,
+        }
+    }
+
+    /// Gives back the first leaf in z-order of every region that `explore` stops at.
+    /// See `PointerOctree::iter_explore_simple`.
+    pub fn iter_explore_simple<'a, E>(&'a self, explore: E) -> ArenaSimpleExploreIter<'a, T, M, E>
+    where
+        E: FnMut(MortonRegion<M>) -> bool + 'a,
+    {
+        ArenaSimpleExploreIter {
+            nodes: &self.nodes,
+            stack: vec![(&self.root, MortonRegion::base())],
+            explore,
+        }
+    }
+
+    /// Iterates over all leaves inside of the axis-aligned box which has the mortons `min` and `max` as opposite
+    /// corners (both inclusive). Leaves are given back in z-order. See `PointerOctree::query_aabb`.
+    pub fn query_aabb(&self, min: M, max: M) -> impl Iterator<Item = (M, &T)> {
+        let bounds = MortonBox::new(min, max);
+        let (min, max) = (bounds.min.decode(), bounds.max.decode());
+        let region = MortonRegion::base();
+        ArenaAabbIter {
+            nodes: &self.nodes,
+            stack: vec![(&self.root, region, MortonBox::region_inside(region, min, max))],
+            bounds,
+            min,
+            max,
+        }
+    }
+
+    /// Same as `query_aabb`, but gives back mutable references to the items.
+    pub fn query_aabb_mut(&mut self, min: M, max: M) -> impl Iterator<Item = (M, &mut T)> {
+        let bounds = MortonBox::new(min, max);
+        let (min, max) = (bounds.min.decode(), bounds.max.decode());
+        let region = MortonRegion::base();
+        ArenaAabbIterMut {
+            nodes: SplitNodes::new(&mut self.nodes),
+            stack: vec![(&mut self.root, region, MortonBox::region_inside(region, min, max))],
+            bounds,
+            min,
+            max,
+        }
+    }
+
+    /// Finds the `k` leaves closest to `point` according to `distance`, sorted from closest to farthest.
+    /// See `PointerOctree::knn`.
+    pub fn knn<S, D>(&self, point: Vector3<S>, k: usize, distance: &D) -> Vec<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        D: Distance<S>,
+    {
+        let nodes = &self.nodes;
+        knn::search(point, k, distance, &self.root, |region, slot, candidates| {
+            slot.candidates(nodes, region, candidates);
+        })
+    }
+
+    /// Finds the leaf closest to `point` according to `distance`. See `knn`.
+    pub fn nearest<S, D>(&self, point: Vector3<S>, distance: &D) -> Option<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        D: Distance<S>,
+    {
+        self.knn(point, 1, distance).pop()
+    }
+
+    /// Finds every leaf within the Euclidean distance `ball.radius` of `ball.center`, along with its distance.
+    /// See `PointerOctree::within_radius`.
+    pub fn within_radius<S>(&self, ball: &Ball<S>) -> Vec<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let nodes = &self.nodes;
+        let mut found = Vec::new();
+        radius::search(
+            ball,
+            &self.root,
+            |region, slot, candidates| slot.candidates(nodes, region, candidates),
+            |morton, item, distance: S| found.push((morton, item, distance.sqrt())),
+        );
+        found
+    }
+
+    /// Same as `within_radius`, but the leaves are sorted from closest to farthest.
+    pub fn within_radius_sorted<S>(&self, ball: &Ball<S>) -> Vec<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut found = self.within_radius(ball);
+        found.sort_unstable_by(|a, b| a.2.partial_cmp(&b.2).unwrap_or(std::cmp::Ordering::Equal));
+        found
+    }
+
+    /// Counts the leaves that `within_radius` would find without collecting them.
+    pub fn count_within_radius<S>(&self, ball: &Ball<S>) -> usize
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let nodes = &self.nodes;
+        let mut count = 0;
+        radius::search(
+            ball,
+            &self.root,
+            |region, slot, candidates| slot.candidates(nodes, region, candidates),
+            |_, _: &T, _| count += 1,
+        );
+        count
+    }
+
+    /// This gathers the tree into a linear hashed octree map. This map contains every internal and leaf node
+    /// as the sum type that the `folder` produces.
+    pub fn collect_fold<E, F>(&self, folder: &F) -> E
+    where
+        F: Folder<T, M>,
+        F::Sum: Clone,
+        E: Extend<(MortonRegion<M>, F::Sum)> + Default,
+    {
+        let mut map = E::default();
+        self.root
+            .collect_fold(&self.nodes, MortonRegion::base(), folder, &mut map);
+        map
+    }
+
+    /// Removes every item from the octree and frees the whole arena.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Returns the number of leaves in the tree.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Checks if the octree is empty.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Gets the slot at a location.
+    pub(crate) fn slot(&self, loc: Loc) -> &Slot<T, M> {
+        match loc {
+            Loc::Root => &self.root,
+            Loc::Child(node, ix) => &self.nodes[node as usize][ix],
+        }
+    }
+
+    /// Gets the children of a node in the arena.
+    pub(crate) fn children(&self, node: u32) -> &[Slot<T, M>; 8] {
+        &self.nodes[node as usize]
+    }
+
+    /// Gets the slot at a location mutably.
+    fn slot_mut(&mut self, loc: Loc) -> &mut Slot<T, M> {
+        match loc {
+            Loc::Root => &mut self.root,
+            Loc::Child(node, ix) => &mut self.nodes[node as usize][ix],
+        }
+    }
+
+    /// Follows `morton` down from the root until it reaches a slot that isn't a node, giving back its location
+    /// and its depth in the tree.
+    pub(crate) fn find(&self, morton: M) -> (Loc, usize) {
+        let mut loc = Loc::Root;
+        let mut level = 0;
+        while let Slot::Node(node) = *self.slot(loc) {
+            loc = Loc::Child(node, morton.get_level(level));
+            level += 1;
+        }
+        (loc, level)
+    }
+
+    /// Places a new leaf at `loc`, which is at depth `level` in the tree and is either `None` or a leaf with a
+    /// different morton. Nodes are created until the two leaves are in different octants.
+    fn place(&mut self, mut loc: Loc, level: usize, morton: M, item: T) -> &mut T {
+        if let Slot::Leaf(dest_item, dest_morton) = std::mem::replace(self.slot_mut(loc), Slot::None) {
+            // Create deeper nodes till they differ at some level.
+            for i in level..M::dim_bits() {
+                let node = self.alloc();
+                *self.slot_mut(loc) = Slot::Node(node);
+                loc = Loc::Child(node, morton.get_level(i));
+                if morton.get_level(i) != dest_morton.get_level(i) {
+                    // We reached the end where they differ, so put the old leaf into the node.
+                    self.nodes[node as usize][dest_morton.get_level(i)] = Slot::Leaf(dest_item, dest_morton);
+                    break;
+                }
+            }
+        }
+        let slot = self.slot_mut(loc);
+        *slot = Slot::Leaf(item, morton);
+        match slot {
+            Slot::Leaf(ref mut item, _) => item,
+            _ => unreachable!("space::ArenaOctree::place(): cant get a non-leaf in this section"),
+        }
+    }
+
+    /// Gets the item of the leaf at `loc`.
+    pub(crate) fn leaf(&self, loc: Loc) -> &T {
+        match self.slot(loc) {
+            Slot::Leaf(ref item, _) => item,
+            _ => unreachable!("space::ArenaOctree::leaf(): the leaf must exist"),
+        }
+    }
+
+    /// Same as `leaf`, but gives back a mutable reference.
+    pub(crate) fn leaf_mut(&mut self, loc: Loc) -> &mut T {
+        match self.slot_mut(loc) {
+            Slot::Leaf(ref mut item, _) => item,
+            _ => unreachable!("space::ArenaOctree::leaf_mut(): the leaf must exist"),
+        }
+    }
+
+    /// Gets an empty node from the free list, or adds a new one to the end of the arena.
+    fn alloc(&mut self) -> u32 {
+        if let Some(node) = self.free.pop() {
+            return node;
+        }
+        let node = self.nodes.len();
+        assert!(
+            node < u32::MAX as usize,
+            "space::ArenaOctree::alloc(): the arena can't index more than u32::MAX nodes"
+        );
+        self.nodes.push(Slot::empty_children());
+        #[allow(clippy::cast_possible_truncation)]
+        let node = node as u32;
+        node
+    }
+
+    /// Replaces `node`, which is at `loc`, with `None` if it has no leaves, or with its leaf if it has a single leaf
+    /// child. The node is put on the free list with all of its children empty.
+    ///
+    /// Gives back `true` if the node was replaced.
+    fn collapse(&mut self, loc: Loc, node: u32) -> bool {
+        let mut occupied = self.nodes[node as usize]
+            .iter_mut()
+            .filter(|child| !matches!(child, Slot::None));
+        let replacement = match (occupied.next(), occupied.next()) {
+            (None, _) => Slot::None,
+            (Some(child), None) if matches!(child, Slot::Leaf(_, _)) => std::mem::replace(child, Slot::None),
+            _ => return false,
+        };
+        *self.slot_mut(loc) = replacement;
+        self.free.push(node);
+        true
+    }
+}
+
+impl<T, M> Extend<(M, T)> for ArenaOctree<T, M>
+where
+    M: Morton,
+{
+    fn extend<I>(&mut self, it: I)
+    where
+        I: IntoIterator<Item = (M, T)>,
+    {
+        for (m, item) in it {
+            self.insert(m, item);
+        }
+    }
+}
+
+impl<T, M> StorageAccess<T, M> for ArenaOctree<T, M>
+where
+    M: Morton,
+{
+    fn iter<'a>(&'a self) -> impl Iterator<Item = (M, &'a T)>
+    where
+        T: 'a,
+    {
+        ArenaOctree::iter(self)
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (M, &'a mut T)>
+    where
+        T: 'a,
+    {
+        ArenaOctree::iter_mut(self)
+    }
+
+    fn insert(&mut self, morton: M, item: T) -> Option<T> {
+        match self.entry(morton) {
+            ArenaEntry::Occupied(mut entry) => Some(entry.insert(item)),
+            ArenaEntry::Vacant(entry) => {
+                entry.insert(item);
+                None
+            }
+        }
+    }
+
+    fn get(&self, morton: M) -> Option<&T> {
+        ArenaOctree::get(self, morton)
+    }
+
+    fn get_mut(&mut self, morton: M) -> Option<&mut T> {
+        ArenaOctree::get_mut(self, morton)
+    }
+
+    fn remove(&mut self, morton: M) -> Option<T> {
+        ArenaOctree::remove(self, morton)
+    }
+
+    fn len(&self) -> usize {
+        ArenaOctree::len(self)
+    }
+}
+
+/// A view into a single morton in an `ArenaOctree`, which may either be vacant or occupied.
+///
+/// Produced by `ArenaOctree::entry`.
+pub enum ArenaEntry<'a, T, M> {
+    /// The morton has an item in the octree.
+    Occupied(ArenaOccupiedEntry<'a, T, M>),
+    /// The morton has no item in the octree.
+    Vacant(ArenaVacantEntry<'a, T, M>),
+}
+
+impl<'a, T, M> ArenaEntry<'a, T, M>
+where
+    M: Morton,
+{
+    /// Gets the morton of the entry.
+    pub fn key(&self) -> M {
+        match self {
+            ArenaEntry::Occupied(entry) => entry.key(),
+            ArenaEntry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Inserts `default` if the entry is vacant and gives back a mutable reference to the item.
+    pub fn or_insert(self, default: T) -> &'a mut T {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of `default` if the entry is vacant and gives back a mutable reference to the item.
+    pub fn or_insert_with<F>(self, default: F) -> &'a mut T
+    where
+        F: FnOnce() -> T,
+    {
+        match self {
+            ArenaEntry::Occupied(entry) => entry.into_mut(),
+            ArenaEntry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Inserts `T::default()` if the entry is vacant and gives back a mutable reference to the item.
+    pub fn or_default(self) -> &'a mut T
+    where
+        T: Default,
+    {
+        self.or_insert_with(T::default)
+    }
+
+    /// Calls `f` on the item if the entry is occupied.
+    #[must_use]
+    pub fn and_modify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut T),
+    {
+        if let ArenaEntry::Occupied(ref mut entry) = self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+/// An occupied entry of an `ArenaOctree`.
+pub struct ArenaOccupiedEntry<'a, T, M> {
+    tree: &'a mut ArenaOctree<T, M>,
+    /// The location of the leaf.
+    loc: Loc,
+    morton: M,
+}
+
+impl<'a, T, M> ArenaOccupiedEntry<'a, T, M>
+where
+    M: Morton,
+{
+    /// Gets the morton of the entry.
+    pub fn key(&self) -> M {
+        self.morton
+    }
+
+    /// Gets a reference to the item.
+    pub fn get(&self) -> &T {
+        self.tree.leaf(self.loc)
+    }
+
+    /// Gets a mutable reference to the item.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.tree.leaf_mut(self.loc)
+    }
+
+    /// Converts the entry into a mutable reference to the item that lives as long as the octree borrow.
+    pub fn into_mut(self) -> &'a mut T {
+        self.tree.leaf_mut(self.loc)
+    }
+
+    /// Replaces the item and gives back the old item.
+    pub fn insert(&mut self, item: T) -> T {
+        std::mem::replace(self.get_mut(), item)
+    }
+
+    /// Removes the item from the octree and gives it back.
+    pub fn remove(self) -> T {
+        self.remove_entry().1
+    }
+
+    /// Removes the item from the octree and gives back its morton and the item.
+    ///
+    /// This collapses nodes in the same way as `ArenaOctree::remove`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the leaf of the entry is missing, which can't happen while the entry borrows the octree.
+    pub fn remove_entry(self) -> (M, T) {
+        let item = self
+            .tree
+            .remove(self.morton)
+            .expect("space::ArenaOccupiedEntry::remove_entry(): the entry must be occupied");
+        (self.morton, item)
+    }
+}
+
+/// A vacant entry of an `ArenaOctree`.
+pub struct ArenaVacantEntry<'a, T, M> {
+    tree: &'a mut ArenaOctree<T, M>,
+    /// The location where the leaf needs to be placed, which is either `None` or a leaf with a different morton.
+    loc: Loc,
+    /// The depth of `loc` in the tree.
+    level: usize,
+    morton: M,
+}
+
+impl<'a, T, M> ArenaVacantEntry<'a, T, M>
+where
+    M: Morton,
+{
+    /// Gets the morton of the entry.
+    pub fn key(&self) -> M {
+        self.morton
+    }
+
+    /// Inserts the item at the morton of the entry and gives back a mutable reference to it.
+    pub fn insert(self, item: T) -> &'a mut T {
+        self.tree.count += 1;
+        self.tree.place(self.loc, self.level, self.morton, item)
+    }
+}
+
+/// Where a slot is in an `ArenaOctree`.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum Loc {
+    /// The root of the tree, which isn't stored in the arena.
+    Root,
+    /// The child at an index of a node in the arena.
+    Child(u32, usize),
+}
+
+/// A child of a node in an `ArenaOctree`.
+#[derive(Clone, Debug)]
+pub(crate) enum Slot<T, M> {
+    /// An internal node at this index in the arena.
+    Node(u32),
+    Leaf(T, M),
+    None,
+}
+
+impl<T, M> Slot<T, M>
+where
+    M: Morton,
+{
+    /// Gives back the children of a node with no leaves.
+    fn empty_children() -> [Self; 8] {
+        use self::Slot::None;
+        [None, None, None, None, None, None, None, None]
+    }
+
+    fn collect_fold<E, F>(
+        &self,
+        nodes: &[[Self; 8]],
+        region: MortonRegion<M>,
+        folder: &F,
+        map: &mut E,
+    ) -> Option<F::Sum>
+    where
+        F: Folder<T, M>,
+        F::Sum: Clone,
+        E: Extend<(MortonRegion<M>, F::Sum)> + Default,
+    {
+        match self {
+            Slot::Node(node) => {
+                let children = &nodes[*node as usize];
+                let sum = folder.fold(
+                    (0..8).filter_map(|i| children[i].collect_fold(nodes, region.enter(i), folder, map)),
+                );
+                map.extend(std::iter::once((region, sum.clone())));
+                Some(sum)
+            }
+            Slot::Leaf(ref item, morton) => {
+                let sum = folder.gather(*morton, item);
+                map.extend(std::iter::once((region, sum.clone())));
+                Some(sum)
+            }
+            Slot::None => None,
+        }
+    }
+
+    /// Gets the first non-empty child of a node at or after `choice`, wrapping around in z-order.
+    fn occupied_from(children: &[Self; 8], mut choice: usize) -> usize {
+        // Iterate until we find the first non-empty spot.
+        // This technically results in not completely random behavior
+        // since an octant that comes after more empty octants is more likely to be chosen.
+        while let Slot::None = children[choice] {
+            choice += 1;
+            choice %= 8;
+        }
+        choice
+    }
+
+    /// Iterates over the leaves below this slot, but stops at `depth` to randomly sample a point.
+    fn iter_rand<'a, R: Rng>(
+        &'a self,
+        nodes: &'a [[Self; 8]],
+        depth: usize,
+        rng: &'a mut R,
+    ) -> impl Iterator<Item = (M, &'a T)> + 'a {
+        use either::Either::{Left, Right};
+        match self {
+            Slot::Node(node) => {
+                let children = &nodes[*node as usize];
+                let choice = if depth == 0 {
+                    Self::occupied_from(children, rng.gen_range(0, 8))
+                } else {
+                    0
+                };
+                Left(ArenaRandIter {
+                    nodes,
+                    stack: vec![(children, choice, 1)],
+                    depth,
+                    rng,
+                })
+            }
+            Slot::Leaf(ref item, morton) => Right(std::iter::once((*morton, item))),
+            Slot::None => Left(ArenaRandIter {
+                nodes,
+                stack: vec![],
+                depth,
+                rng,
+            }),
+        }
+    }
+
+    /// Get a single random leaf sample from this slot (cant be none).
+    fn sample<'a>(&'a self, nodes: &'a [[Self; 8]], morton: M) -> (M, &'a T) {
+        match self {
+            Slot::Node(node) => {
+                let children = &nodes[*node as usize];
+                children[Self::occupied_from(children, morton.get_level(0))].sample(nodes, morton << 3)
+            }
+            Slot::Leaf(ref item, morton) => (*morton, item),
+            Slot::None => unreachable!("space::ArenaOctree::sample(): can't sample a none slot"),
+        }
+    }
+
+    /// Folds the region of this slot, but randomly samples a single leaf for each region `depth` levels below it.
+    fn fold_rand<F, R>(
+        &self,
+        nodes: &[[Self; 8]],
+        region: MortonRegion<M>,
+        depth: usize,
+        folder: &F,
+        cache: &mut MortonRegionCache<F::Sum, M>,
+        rng: &mut R,
+    ) -> Option<F::Sum>
+    where
+        F: Folder<T, M>,
+        F::Sum: Clone,
+        R: Rng,
+        Standard: Distribution<M>,
+    {
+        if let Some(sum) = cache.get_mut(&region).cloned() {
+            return Some(sum);
+        }
+        let sum = match self {
+            Slot::Node(_) if depth == 0 => {
+                let (morton, item) = self.sample(nodes, rng.gen());
+                folder.gather(morton, item)
+            }
+            Slot::Node(node) => folder.fold(
+                nodes[*node as usize]
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(ix, child)| child.fold_rand(nodes, region.enter(ix), depth - 1, folder, cache, rng)),
+            ),
+            Slot::Leaf(ref item, morton) => folder.gather(*morton, item),
+            Slot::None => return None,
+        };
+        cache.insert(region, sum.clone());
+        Some(sum)
+    }
+
+    /// Adds every non-empty child of this node to `candidates` for a search, or this slot itself if it is a leaf.
+    fn candidates<'a>(
+        &'a self,
+        nodes: &'a [[Self; 8]],
+        region: MortonRegion<M>,
+        candidates: &mut Vec<Candidate<M, &'a Self, &'a T>>,
+    ) {
+        match self {
+            Slot::Node(node) => {
+                for (ix, child) in nodes[*node as usize].iter().enumerate() {
+                    match child {
+                        Slot::Node(_) => candidates.push(Candidate::Region(region.enter(ix), child)),
+                        Slot::Leaf(ref item, morton) => candidates.push(Candidate::Leaf(*morton, item)),
+                        Slot::None => {}
+                    }
+                }
+            }
+            Slot::Leaf(ref item, morton) => candidates.push(Candidate::Leaf(*morton, item)),
+            Slot::None => {}
+        }
+    }
+}
+
+/// The nodes of an arena split into disjoint mutable borrows, so that a mutable iterator can borrow the children
+/// of each node it visits while it still holds the children of the nodes above it.
+///
+/// Every node in the tree is visited at most once, so each one is split off when it is visited. The rest of the
+/// arena is kept as the parts between the nodes that have been split off, so the cost only depends on the number
+/// of nodes that are visited rather than the size of the arena.
+struct SplitNodes<'a, T, M> {
+    /// The parts of the arena that haven't been split off yet, by the index of their first node.
+    parts: BTreeMap<usize, &'a mut [[Slot<T, M>; 8]]>,
+}
+
+impl<'a, T, M> SplitNodes<'a, T, M> {
+    fn new(nodes: &'a mut [[Slot<T, M>; 8]]) -> Self {
+        let mut parts = BTreeMap::new();
+        if !nodes.is_empty() {
+            parts.insert(0, nodes);
+        }
+        SplitNodes { parts }
+    }
+
+    /// Splits off the children of `node`, which must not have been split off before.
+    fn take(&mut self, node: u32) -> &'a mut [Slot<T, M>; 8] {
+        let node = node as usize;
+        let start = match self.parts.range(..=node).next_back() {
+            Some((&start, part)) if node - start < part.len() => start,
+            _ => panic!("space::ArenaOctree::SplitNodes::take(): a node was visited twice"),
+        };
+        let part = self.parts.remove(&start).expect("space::ArenaOctree::SplitNodes::take(): the part must exist");
+        let (before, rest) = part.split_at_mut(node - start);
+        let (children, after) = rest
+            .split_first_mut()
+            .expect("space::ArenaOctree::SplitNodes::take(): the part must contain the node");
+        if !before.is_empty() {
+            self.parts.insert(start, before);
+        }
+        if !after.is_empty() {
+            self.parts.insert(node + 1, after);
+        }
+        children
+    }
+}
+
+struct ArenaIter<'a, T, M> {
+    nodes: &'a [[Slot<T, M>; 8]],
+    stack: Vec<std::slice::Iter<'a, Slot<T, M>>>,
+}
+
+impl<'a, T, M> Iterator for ArenaIter<'a, T, M>
+where
+    M: Morton,
+{
+    type Item = (M, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(children) = self.stack.last_mut() {
+            match children.next() {
+                Some(Slot::Node(node)) => self.stack.push(self.nodes[*node as usize].iter()),
+                Some(Slot::Leaf(ref item, morton)) => return Some((*morton, item)),
+                Some(Slot::None) => {}
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+        None
+    }
+}
+
+struct ArenaIterMut<'a, T, M> {
+    nodes: SplitNodes<'a, T, M>,
+    stack: Vec<std::slice::IterMut<'a, Slot<T, M>>>,
+}
+
+impl<'a, T, M> Iterator for ArenaIterMut<'a, T, M>
+where
+    M: Morton,
+{
+    type Item = (M, &'a mut T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(children) = self.stack.last_mut() {
+            match children.next() {
+                Some(Slot::Node(node)) => {
+                    let children = self.nodes.take(*node);
+                    self.stack.push(children.iter_mut());
+                }
+                Some(Slot::Leaf(ref mut item, morton)) => return Some((*morton, item)),
+                Some(Slot::None) => {}
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The children of a node, along with the index of the next child to visit and the level of the children.
+type ChildrenIndexLevel<'a, T, M> = (&'a [Slot<T, M>; 8], usize, usize);
+
+struct ArenaRandIter<'a, T, M, R> {
+    nodes: &'a [[Slot<T, M>; 8]],
+    stack: Vec<ChildrenIndexLevel<'a, T, M>>,
+    depth: usize,
+    rng: &'a mut R,
+}
+
+impl<'a, T, M, R> Iterator for ArenaRandIter<'a, T, M, R>
+where
+    M: Morton,
+    R: Rng,
+{
+    type Item = (M, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((children, ix, level)) = self.stack.pop() {
+            if level <= self.depth && ix != 7 {
+                self.stack.push((children, ix + 1, level));
+            }
+            match children[ix] {
+                Slot::Node(node) => {
+                    let children = &self.nodes[node as usize];
+                    let choice = if level >= self.depth {
+                        Slot::occupied_from(children, self.rng.gen_range(0, 8))
+                    } else {
+                        0
+                    };
+                    self.stack.push((children, choice, level + 1));
+                }
+                Slot::Leaf(ref item, morton) => return Some((morton, item)),
+                Slot::None => {}
+            }
+        }
+        None
+    }
+}
+
+/// An iterator over the folded regions of an `ArenaOctree`.
+///
+/// Produced by `ArenaOctree::iter_fold` and `ArenaOctree::iter_fold_random`.
+pub struct ArenaFoldIter<'a, T, M, E, F, R>
+where
+    F: Folder<T, M>,
+    R: Rng,
+    M: Morton,
+{
+    nodes: &'a [[Slot<T, M>; 8]],
+    stack: Vec<(&'a Slot<T, M>, MortonRegion<M>)>,
+    explore: E,
+    folder: F,
+    depth: usize,
+    rng: R,
+    cache: MortonRegionCache<F::Sum, M>,
+}
+
+impl<T, M, E, F, R> Iterator for ArenaFoldIter<'_, T, M, E, F, R>
+where
+    M: Morton,
+    E: FnMut(MortonRegion<M>) -> bool,
+    F: Folder<T, M>,
+    F::Sum: Clone,
+    R: Rng,
+    Standard: Distribution<M>,
+{
+    type Item = (MortonRegion<M>, F::Sum);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((slot, region)) = self.stack.pop() {
+            if (self.explore)(region) {
+                match slot {
+                    Slot::Node(node) => {
+                        for (ix, child) in self.nodes[*node as usize].iter().enumerate() {
+                            self.stack.push((child, region.enter(ix)));
+                        }
+                    }
+                    Slot::Leaf(..) => {
+                        let sum = slot.fold_rand(self.nodes, region, 0, &self.folder, &mut self.cache, &mut self.rng);
+                        return sum.map(|sum| (region, sum));
+                    }
+                    Slot::None => {}
+                }
+            } else if let Some(sum) =
+                slot.fold_rand(self.nodes, region, self.depth, &self.folder, &mut self.cache, &mut self.rng)
+            {
+                // If we shouldn't go further into the region, then its time to do a random sample starting here.
+                return Some((region, sum));
+            }
+        }
+        None
+    }
+}
+
+impl<'a, T, M, E, F, R> From<ArenaFoldIter<'a, T, M, E, F, R>> for MortonRegionCache<F::Sum, M>
+where
+    F: Folder<T, M>,
+    R: Rng,
+    M: Morton,
+{
+    fn from(iter: ArenaFoldIter<'a, T, M, E, F, R>) -> Self {
+        iter.cache
+    }
+}
+
+/// An iterator over the first leaf of every region of an `ArenaOctree` that exploration stops at.
+///
+/// Produced by `ArenaOctree::iter_explore_simple`.
+pub struct ArenaSimpleExploreIter<'a, T, M, E> {
+    nodes: &'a [[Slot<T, M>; 8]],
+    stack: Vec<(&'a Slot<T, M>, MortonRegion<M>)>,
+    explore: E,
+}
+
+impl<'a, T, M, E> Iterator for ArenaSimpleExploreIter<'a, T, M, E>
+where
+    M: Morton,
+    E: FnMut(MortonRegion<M>) -> bool,
+{
+    type Item = (MortonRegion<M>, M, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((slot, region)) = self.stack.pop() {
+            match slot {
+                Slot::Node(node) => {
+                    if (self.explore)(region) {
+                        for (ix, child) in self.nodes[*node as usize].iter().enumerate() {
+                            self.stack.push((child, region.enter(ix)));
+                        }
+                    } else {
+                        let mut leaves = ArenaIter {
+                            nodes: self.nodes,
+                            stack: vec![std::slice::from_ref(slot).iter()],
+                        };
+                        let (morton, item) = leaves
+                            .next()
+                            .expect("space::ArenaSimpleExploreIter::next(): internal node had no leaves");
+                        return Some((region, morton, item));
+                    }
+                }
+                Slot::Leaf(ref item, morton) => return Some((region, *morton, item)),
+                Slot::None => {}
+            }
+        }
+        None
+    }
+}
+
+struct ArenaAabbIter<'a, T, M>
+where
+    M: Morton,
+{
+    nodes: &'a [[Slot<T, M>; 8]],
+    /// The slots that still need to be visited, along with their region and whether or not the region is
+    /// completely inside the box.
+    stack: Vec<(&'a Slot<T, M>, MortonRegion<M>, bool)>,
+    bounds: MortonBox<M>,
+    min: Vector3<M>,
+    max: Vector3<M>,
+}
+
+impl<'a, T, M> Iterator for ArenaAabbIter<'a, T, M>
+where
+    M: Morton,
+{
+    type Item = (M, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((slot, region, inside)) = self.stack.pop() {
+            match slot {
+                Slot::Node(node) => {
+                    // Push the children in reverse so that they come back out in z-order.
+                    for (ix, child) in self.nodes[*node as usize].iter().enumerate().rev() {
+                        let child_region = region.enter(ix);
+                        if inside {
+                            self.stack.push((child, child_region, true));
+                        } else if MortonBox::region_overlaps(child_region, self.min, self.max) {
+                            let child_inside = MortonBox::region_inside(child_region, self.min, self.max);
+                            self.stack.push((child, child_region, child_inside));
+                        }
+                    }
+                }
+                Slot::Leaf(ref item, morton) => {
+                    if inside || self.bounds.contains(*morton) {
+                        return Some((*morton, item));
+                    }
+                }
+                Slot::None => {}
+            }
+        }
+        None
+    }
+}
+
+struct ArenaAabbIterMut<'a, T, M>
+where
+    M: Morton,
+{
+    nodes: SplitNodes<'a, T, M>,
+    stack: Vec<(&'a mut Slot<T, M>, MortonRegion<M>, bool)>,
+    bounds: MortonBox<M>,
+    min: Vector3<M>,
+    max: Vector3<M>,
+}
+
+impl<'a, T, M> Iterator for ArenaAabbIterMut<'a, T, M>
+where
+    M: Morton,
+{
+    type Item = (M, &'a mut T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((slot, region, inside)) = self.stack.pop() {
+            match slot {
+                Slot::Node(node) => {
+                    let children = self.nodes.take(*node);
+                    // Push the children in reverse so that they come back out in z-order.
+                    for (ix, child) in children.iter_mut().enumerate().rev() {
+                        let child_region = region.enter(ix);
+                        if inside {
+                            self.stack.push((child, child_region, true));
+                        } else if MortonBox::region_overlaps(child_region, self.min, self.max) {
+                            let child_inside = MortonBox::region_inside(child_region, self.min, self.max);
+                            self.stack.push((child, child_region, child_inside));
+                        }
+                    }
+                }
+                Slot::Leaf(ref mut item, morton) => {
+                    if inside || self.bounds.contains(*morton) {
+                        return Some((*morton, item));
+                    }
+                }
+                Slot::None => {}
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::{Euclidean, PointerOctree};
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+
+    /// Counts the nodes which are reachable from the root.
+    fn live_nodes<T, M: Morton>(tree: &ArenaOctree<T, M>) -> usize {
+        tree.nodes.len() - tree.free.len()
+    }
+
+    #[test]
+    fn test_arena_matches_pointer() {
+        let mut rng = SmallRng::from_seed([14; 16]);
+        for round in 0..10 {
+            // Use a small space in some rounds so that leaves share deep nodes.
+            let bound = if round % 2 == 0 { 8 } else { 1 << 10 };
+            let mut arena = ArenaOctree::<usize, u32>::new();
+            let mut expected = BTreeMap::new();
+            for i in 0..2000 {
+                let v = Vector3::new(rng.gen_range(0, bound), rng.gen_range(0, bound), rng.gen_range(0, bound));
+                let m = u32::encode(v);
+                match rng.gen_range(0, 4) {
+                    0 => assert_eq!(arena.remove(m), expected.remove(&m)),
+                    1 => {
+                        if let ArenaEntry::Occupied(entry) = arena.entry(m) {
+                            assert_eq!(entry.remove_entry(), (m, expected.remove(&m).unwrap()));
+                        } else {
+                            assert!(!expected.contains_key(&m));
+                        }
+                    }
+                    _ => {
+                        arena.insert(m, i);
+                        expected.insert(m, i);
+                    }
+                }
+                assert_eq!(arena.len(), expected.len());
+            }
+            assert!(arena.iter().map(|(m, &i)| (m, i)).eq(expected.iter().map(|(&m, &i)| (m, i))));
+            assert!(arena.iter_mut().map(|(m, &mut i)| (m, i)).eq(expected.iter().map(|(&m, &i)| (m, i))));
+
+            let mut pointer = PointerOctree::new();
+            pointer.extend(expected.iter().map(|(&m, &i)| (m, i)));
+            let mut rebuilt = ArenaOctree::new();
+            rebuilt.extend(expected.iter().map(|(&m, &i)| (m, i)));
+            // Removal keeps the tree in the same shape as a freshly built tree.
+            assert_eq!(live_nodes(&arena), live_nodes(&rebuilt));
+
+            for _ in 0..20 {
+                let a = u32::encode(Vector3::new(rng.gen_range(0, bound), rng.gen_range(0, bound), 0));
+                let b = u32::encode(Vector3::new(rng.gen_range(0, bound), rng.gen_range(0, bound), bound - 1));
+                let found: Vec<(u32, usize)> = arena.query_aabb(a, b).map(|(m, &i)| (m, i)).collect();
+                let found_mut: Vec<(u32, usize)> = arena.query_aabb_mut(b, a).map(|(m, &mut i)| (m, i)).collect();
+                assert!(pointer.query_aabb(a, b).map(|(m, &i)| (m, i)).eq(found.iter().copied()));
+                assert_eq!(found, found_mut);
+
+                let point = Vector3::new(rng.gen(), rng.gen(), rng.gen());
+                let arena_found: Vec<f64> = arena.knn(point, 10, &Euclidean).into_iter().map(|n| n.2).collect();
+                let pointer_found: Vec<f64> = pointer.knn(point, 10, &Euclidean).into_iter().map(|n| n.2).collect();
+                assert_eq!(arena_found, pointer_found);
+                let ball = crate::octree::Ball::new(point, rng.gen_range(0.0, 0.1));
+                assert_eq!(arena.count_within_radius(&ball), pointer.count_within_radius(&ball));
+            }
+        }
+    }
+
+    /// Sums the items of the leaves in a region.
+    struct SumItems;
+
+    impl Folder<usize, u32> for SumItems {
+        type Sum = usize;
+
+        fn gather(&self, _: u32, item: &usize) -> usize {
+            *item
+        }
+
+        fn fold<I>(&self, it: I) -> usize
+        where
+            I: Iterator<Item = usize>,
+        {
+            it.sum()
+        }
+    }
+
+    #[test]
+    fn test_arena_folds_match_pointer() {
+        let mut rng = SmallRng::from_seed([16; 16]);
+        let items: Vec<(u32, usize)> =
+            (0..1000).map(|i| (u32::encode(Vector3::new(rng.gen(), rng.gen(), rng.gen())), i)).collect();
+        let mut arena = ArenaOctree::new();
+        arena.extend(items.iter().copied());
+        let mut pointer = PointerOctree::new();
+        pointer.extend(items.iter().copied());
+        let explore = |region: MortonRegion<u32>| region.level < 3;
+
+        let arena_folded: Vec<_> = arena.iter_fold(SumItems, crate::morton::region_cache(4096)).collect();
+        let pointer_folded: Vec<_> = pointer.iter_fold(SumItems, crate::morton::region_cache(4096)).collect();
+        assert_eq!(arena_folded, pointer_folded);
+        assert_eq!(arena_folded.iter().map(|&(_, sum)| sum).sum::<usize>(), (0..1000).sum());
+
+        let arena_sampled: Vec<_> = arena
+            .iter_fold_random(2, explore, SumItems, SmallRng::from_seed([17; 16]), crate::morton::region_cache(4096))
+            .collect();
+        let pointer_sampled: Vec<_> = pointer
+            .iter_fold_random(2, explore, SumItems, SmallRng::from_seed([17; 16]), crate::morton::region_cache(4096))
+            .collect();
+        assert_eq!(arena_sampled, pointer_sampled);
+
+        assert!(arena.iter_explore_simple(explore).eq(pointer.iter_explore_simple(explore)));
+
+        let mut arena_rng = SmallRng::from_seed([18; 16]);
+        let mut pointer_rng = SmallRng::from_seed([18; 16]);
+        assert!(arena.iter_rand(2, &mut arena_rng).eq(pointer.iter_rand(2, &mut pointer_rng)));
+        assert!(arena.iter_rand(u32::dim_bits(), &mut arena_rng).eq(arena.iter()));
+    }
+
+    #[test]
+    fn test_arena_reuses_freed_nodes() {
+        let mut rng = SmallRng::from_seed([15; 16]);
+        let mortons: Vec<u64> = (0..1000).map(|_| rng.gen::<u64>() & u64::used_bits()).collect();
+        let mut arena = ArenaOctree::new();
+        arena.extend(mortons.iter().map(|&m| (m, m)));
+        let allocated = arena.nodes.len();
+        for &m in &mortons {
+            arena.remove(m);
+        }
+        assert!(arena.is_empty());
+        assert!(matches!(arena.root, Slot::None));
+        assert_eq!(live_nodes(&arena), 0);
+
+        // Inserting the same mortons again only uses nodes from the free list.
+        arena.extend(mortons.iter().map(|&m| (m, m)));
+        assert_eq!(arena.nodes.len(), allocated);
+        assert!(mortons.iter().all(|&m| arena.get(m) == Some(&m)));
+        arena.clear();
+        assert!(arena.nodes.is_empty() && arena.iter().next().is_none());
+    }
+}
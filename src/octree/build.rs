@@ -0,0 +1,298 @@
+use crate::morton::{Morton, MortonRegion};
+
+/// Below this many leaves, a parallel build does the rest of the subtree on the current thread.
+const PARALLEL_THRESHOLD: usize = 1 << 12;
+
+/// Sorts items by morton with a stable LSD radix sort, which takes linear time for a given morton type.
+///
+/// Items with the same morton stay in the order they were given in.
+pub(crate) fn radix_sort<M, T>(items: Vec<(M, T)>) -> Vec<(M, T)>
+where
+    M: Morton,
+{
+    let mut keys: Vec<(M, usize)> = items.iter().enumerate().map(|(ix, &(m, _))| (m, ix)).collect();
+    let mut scratch = keys.clone();
+    let byte = M::from_u8(0xff).unwrap();
+    for shift in (0..3 * M::dim_bits()).step_by(8) {
+        let digit = |m: M| ((m >> shift) & byte).to_usize().unwrap();
+        let mut offsets = [0; 257];
+        for &(m, _) in &keys {
+            offsets[digit(m) + 1] += 1;
+        }
+        // Every key has the same digit, so this pass wouldn't move anything.
+        if offsets.contains(&keys.len()) {
+            continue;
+        }
+        for i in 0..256 {
+            offsets[i + 1] += offsets[i];
+        }
+        for &(m, ix) in &keys {
+            let d = digit(m);
+            scratch[offsets[d]] = (m, ix);
+            offsets[d] += 1;
+        }
+        std::mem::swap(&mut keys, &mut scratch);
+    }
+    let mut items: Vec<Option<(M, T)>> = items.into_iter().map(Some).collect();
+    keys.into_iter()
+        .map(|(_, ix)| items[ix].take().expect("space::octree::radix_sort(): each item is moved once"))
+        .collect()
+}
+
+/// Builds an octree bottom-up in a single pass over leaves sorted by morton and gives back the root.
+///
+/// A region of the tree is an internal node exactly when it holds at least two leaves, and each leaf is a child of
+/// the deepest internal node that holds it, which is the shape that inserting the leaves one at a time produces.
+/// `leaf` creates a leaf and `node` creates an internal node from its region and the children in it. When a morton
+/// appears more than once, only its last item is kept. Gives back `None` if there are no leaves.
+///
+/// Panics if the mortons are not sorted.
+pub(crate) fn build_sorted<M, T, N, I, L, F>(items: I, mut leaf: L, mut node: F) -> Option<N>
+where
+    M: Morton,
+    I: IntoIterator<Item = (M, T)>,
+    L: FnMut(M, T) -> N,
+    F: FnMut(MortonRegion<M>, [Option<N>; 8]) -> N,
+{
+    build_subtree(items, &mut leaf, &mut node).map(|subtree| subtree.chain(0, &mut node))
+}
+
+/// Same as `build_sorted`, but sorts the leaves itself and builds separate octants of the tree on separate threads.
+///
+/// The leaves are split by the octant they are in at each of the top levels of the tree until there are few enough
+/// leaves left in an octant, and each octant is sorted and built on its own thread.
+pub(crate) fn build_unsorted_parallel<M, T, N, L, F>(items: Vec<(M, T)>, leaf: &L, node: &F) -> Option<N>
+where
+    M: Morton + Send,
+    T: Send,
+    N: Send,
+    L: Fn(M, T) -> N + Sync,
+    F: Fn(MortonRegion<M>, [Option<N>; 8]) -> N + Sync,
+{
+    let threads = std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get);
+    // Each split multiplies the number of threads by 8.
+    let splits = if threads > 8 { 2 } else { 1 };
+    build_parallel(items, 0, splits, leaf, node).map(|subtree| subtree.chain(0, &mut |r, c| node(r, c)))
+}
+
+fn build_parallel<M, T, N, L, F>(
+    items: Vec<(M, T)>,
+    level: usize,
+    splits: usize,
+    leaf: &L,
+    node: &F,
+) -> Option<Subtree<M, N>>
+where
+    M: Morton + Send,
+    T: Send,
+    N: Send,
+    L: Fn(M, T) -> N + Sync,
+    F: Fn(MortonRegion<M>, [Option<N>; 8]) -> N + Sync,
+{
+    if splits == 0 || level == M::dim_bits() || items.len() < PARALLEL_THRESHOLD {
+        return build_subtree(radix_sort(items), &mut |m, t| leaf(m, t), &mut |r, c| node(r, c));
+    }
+    // Splitting is stable, so the last item of each morton is still the last one in its octant.
+    let mut octants: [Vec<(M, T)>; 8] = Default::default();
+    for (m, item) in items {
+        octants[m.get_level(level)].push((m, item));
+    }
+    let subtrees: Vec<Option<Subtree<M, N>>> = std::thread::scope(|scope| {
+        // This is edition 2018, so `octants.into_iter()` would iterate over references.
+        let handles: Vec<_> = IntoIterator::into_iter(octants)
+            .map(|octant| scope.spawn(move || build_parallel(octant, level + 1, splits - 1, leaf, node)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    });
+
+    // The octants are built independently, so they still need to be joined under a node at this level.
+    let mut subtrees: Vec<Subtree<M, N>> = subtrees.into_iter().flatten().collect();
+    if subtrees.len() < 2 {
+        // A single occupied octant doesn't need a node here, since the region only has one child.
+        return subtrees.pop();
+    }
+    let morton = subtrees[0].morton;
+    let mut open = Open::new(region_at(morton, level));
+    for subtree in subtrees {
+        open.attach(subtree, &mut |r, c| node(r, c));
+    }
+    Some(Subtree {
+        root: node(open.region, open.children),
+        level: Some(level),
+        morton,
+    })
+}
+
+/// Builds the part of a tree holding a sorted run of leaves, without the nodes above the shallowest node that holds
+/// all of them.
+fn build_subtree<M, T, N, I, L, F>(items: I, leaf: &mut L, node: &mut F) -> Option<Subtree<M, N>>
+where
+    M: Morton,
+    I: IntoIterator<Item = (M, T)>,
+    L: FnMut(M, T) -> N,
+    F: FnMut(MortonRegion<M>, [Option<N>; 8]) -> N,
+{
+    // The open nodes are the ancestors of the last leaf, from shallowest to deepest, which can still get children.
+    let mut stack: Vec<Open<M, N>> = Vec::with_capacity(M::dim_bits());
+    let mut items = items.into_iter();
+    let (mut morton, mut item) = items.next()?;
+    for (next_morton, next_item) in items {
+        assert!(next_morton >= morton, "space::octree::build_sorted(): the mortons must be sorted");
+        if next_morton == morton {
+            item = next_item;
+            continue;
+        }
+        // The deepest region that holds both leaves is a node, and its level is the number of levels they share.
+        let shared = common_levels(morton, next_morton);
+        let mut child = Subtree {
+            root: leaf(morton, item),
+            level: None,
+            morton,
+        };
+        // Nodes deeper than the shared region can't get any more leaves, so they are finished.
+        while matches!(stack.last(), Some(open) if open.region.level > shared) {
+            let mut open = stack.pop().unwrap();
+            open.attach(child, node);
+            child = Subtree {
+                root: node(open.region, open.children),
+                level: Some(open.region.level),
+                morton,
+            };
+        }
+        if !matches!(stack.last(), Some(open) if open.region.level >= shared) {
+            stack.push(Open::new(region_at(morton, shared)));
+        }
+        stack.last_mut().unwrap().attach(child, node);
+        morton = next_morton;
+        item = next_item;
+    }
+    let mut child = Subtree {
+        root: leaf(morton, item),
+        level: None,
+        morton,
+    };
+    while let Some(mut open) = stack.pop() {
+        open.attach(child, node);
+        child = Subtree {
+            root: node(open.region, open.children),
+            level: Some(open.region.level),
+            morton,
+        };
+    }
+    Some(child)
+}
+
+/// A finished part of a tree.
+struct Subtree<M, N> {
+    root: N,
+    /// The level of the region of `root` if it is a node, or `None` if it is a leaf.
+    level: Option<usize>,
+    /// Any morton inside of the subtree.
+    morton: M,
+}
+
+impl<M, N> Subtree<M, N>
+where
+    M: Morton,
+{
+    /// Adds the nodes with a single child from the level `top` down to the root of the subtree, since every region
+    /// above a node also holds at least two leaves. Leaves are given back as they are.
+    fn chain<F>(self, top: usize, node: &mut F) -> N
+    where
+        F: FnMut(MortonRegion<M>, [Option<N>; 8]) -> N,
+    {
+        let mut root = self.root;
+        for level in (top..self.level.unwrap_or(top)).rev() {
+            let mut children: [Option<N>; 8] = Default::default();
+            children[self.morton.get_level(level)] = Some(root);
+            root = node(region_at(self.morton, level), children);
+        }
+        root
+    }
+}
+
+/// A node which can still get more children.
+struct Open<M, N> {
+    region: MortonRegion<M>,
+    children: [Option<N>; 8],
+}
+
+impl<M, N> Open<M, N>
+where
+    M: Morton,
+{
+    fn new(region: MortonRegion<M>) -> Self {
+        Self {
+            region,
+            children: Default::default(),
+        }
+    }
+
+    fn attach<F>(&mut self, child: Subtree<M, N>, node: &mut F)
+    where
+        F: FnMut(MortonRegion<M>, [Option<N>; 8]) -> N,
+    {
+        let ix = child.morton.get_level(self.region.level);
+        self.children[ix] = Some(child.chain(self.region.level + 1, node));
+    }
+}
+
+/// Gets the region at `level` which holds `morton`.
+fn region_at<M>(morton: M, level: usize) -> MortonRegion<M>
+where
+    M: Morton,
+{
+    if level == 0 {
+        MortonRegion::base()
+    } else {
+        MortonRegion {
+            morton: morton.get_significant_bits(level - 1) << (3 * (M::dim_bits() - level)),
+            level,
+        }
+    }
+}
+
+/// Gets the number of levels, starting from the top, that two different mortons have in common.
+fn common_levels<M>(a: M, b: M) -> usize
+where
+    M: Morton,
+{
+    let unused = M::BITS - 3 * M::dim_bits();
+    ((a ^ b).leading_zeros() as usize - unused) / 3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::PointerOctree;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_radix_sort_is_stable() {
+        let mut rng = SmallRng::from_seed([17; 16]);
+        let items: Vec<(u64, usize)> = (0..5000)
+            .map(|i| (rng.gen::<u64>() & u64::used_bits() & !0xff_ff00, i))
+            .collect();
+        let mut expected = items.clone();
+        expected.sort_by_key(|&(m, _)| m);
+        assert_eq!(radix_sort(items), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "the mortons must be sorted")]
+    fn test_build_unsorted_panics() {
+        PointerOctree::from_sorted(vec![(2u64, ()), (1, ())]);
+    }
+
+    #[test]
+    fn test_common_levels() {
+        assert_eq!(common_levels(0u64, 1), u64::dim_bits() - 1);
+        assert_eq!(common_levels(0u64, u64::highest_bits()), 0);
+        assert_eq!(common_levels(0u16, 0b1000), u16::dim_bits() - 2);
+        assert_eq!(region_at(0b101_111u16, 4).canonicalize(), MortonRegion { morton: 0b101_111u16, level: 4 }.canonicalize());
+    }
+}
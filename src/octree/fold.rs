@@ -0,0 +1,394 @@
+use crate::morton::{Morton, MortonRegion};
+use crate::octree::arena::{Loc, Slot};
+use crate::octree::{ArenaOctree, Folder};
+
+use std::ops::{Deref, DerefMut};
+
+/// An octree that keeps the tree fold of every internal node up to date as leaves change.
+///
+/// `PointerOctree::iter_fold` and `collect_fold` fold the tree from the leaves every time, so any change means
+/// invalidating a cache or folding the whole tree again. This instead stores the `Folder::Sum` of every internal
+/// node next to the node, and when a leaf is inserted, removed or changed through `get_mut`, only the nodes on the
+/// path from that leaf to the root are folded again. A change costs `O(depth)` `fold` operations, so things like the
+/// centers of mass of a Barnes-Hut simulation are always current.
+///
+/// The leaves are kept in an `ArenaOctree`, which `tree` gives access to for searches. The sums of leaves are not
+/// stored, since `gather` is called on them again whenever they are needed.
+///
+/// ```
+/// use space::{FoldOctree, Folder, Morton};
+/// use nalgebra::Vector3;
+///
+/// struct Mass;
+///
+/// impl<M> Folder<u32, M> for Mass {
+///     type Sum = u32;
+///
+///     fn gather<'a>(&self, _: M, mass: &'a u32) -> u32 {
+///         *mass
+///     }
+///
+///     fn fold<I>(&self, it: I) -> u32
+///     where
+///         I: Iterator<Item = u32>,
+///     {
+///         it.sum()
+///     }
+/// }
+///
+/// let mut tree = FoldOctree::<u32, u64, _>::new(Mass);
+/// let a = Morton::encode(Vector3::new(1, 2, 3));
+/// let b = Morton::encode(Vector3::new(3, 2, 1));
+/// tree.insert(a, 5);
+/// tree.insert(b, 7);
+/// assert_eq!(tree.sum(), Some(12));
+/// *tree.get_mut(a).unwrap() += 1;
+/// assert_eq!(tree.sum(), Some(13));
+/// tree.remove(b);
+/// assert_eq!(tree.sum(), Some(6));
+/// ```
+pub struct FoldOctree<T, M, F>
+where
+    F: Folder<T, M>,
+{
+    tree: ArenaOctree<T, M>,
+    /// The sum of every node in the arena, at the same index. Nodes on the free list keep their old sum until they
+    /// are reused, at which point they are folded again.
+    sums: Vec<Option<F::Sum>>,
+    folder: F,
+}
+
+impl<T, M, F> FoldOctree<T, M, F>
+where
+    M: Morton,
+    F: Folder<T, M>,
+    F::Sum: Clone,
+{
+    /// Create an empty octree that folds with `folder`.
+    pub fn new(folder: F) -> Self {
+        Self {
+            tree: ArenaOctree::new(),
+            sums: Vec::new(),
+            folder,
+        }
+    }
+
+    /// Gets the folder that the sums are made with.
+    pub fn folder(&self) -> &F {
+        &self.folder
+    }
+
+    /// Gets the octree of leaves underneath, which can be used for searches.
+    pub fn tree(&self) -> &ArenaOctree<T, M> {
+        &self.tree
+    }
+
+    /// Fetches an immutable reference to the value of a specific coordinate in the octree.
+    pub fn get(&self, morton: M) -> Option<&T> {
+        self.tree.get(morton)
+    }
+
+    /// Fetches a mutable reference to the value of a specific coordinate in the octree.
+    ///
+    /// The sums above the leaf are folded again when the reference is dropped.
+    pub fn get_mut(&mut self, morton: M) -> Option<FoldRefMut<'_, T, M, F>> {
+        let (loc, _) = self.tree.find(morton);
+        match self.tree.slot(loc) {
+            Slot::Leaf(_, dest_morton) if *dest_morton == morton => Some(FoldRefMut {
+                tree: self,
+                loc,
+                morton,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Insert an item with a point and replace the existing item if they would both occupy the same space.
+    pub fn insert(&mut self, morton: M, item: T) {
+        self.tree.insert(morton, item);
+        self.refold(morton);
+    }
+
+    /// Removes the item at a morton from the octree and gives it back, if it was present.
+    pub fn remove(&mut self, morton: M) -> Option<T> {
+        let item = self.tree.remove(morton)?;
+        self.refold(morton);
+        Some(item)
+    }
+
+    /// Gets the sum of the whole tree, or `None` if the tree is empty.
+    pub fn sum(&self) -> Option<F::Sum> {
+        self.region_sum(MortonRegion::base())
+    }
+
+    /// Gets the sum of the leaves inside of `region`, or `None` if there are none.
+    ///
+    /// This follows the region down from the root, so it takes `O(region.level)` time.
+    pub fn region_sum(&self, region: MortonRegion<M>) -> Option<F::Sum> {
+        let mut loc = Loc::Root;
+        let mut level = 0;
+        loop {
+            match *self.tree.slot(loc) {
+                Slot::Node(node) if level == region.level => return Some(self.node_sum(node)),
+                Slot::Node(node) => {
+                    loc = Loc::Child(node, region.morton.get_level(level));
+                    level += 1;
+                }
+                Slot::Leaf(ref item, morton) if region.contains(morton) => {
+                    return Some(self.folder.gather(morton, item));
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// Iterates over the octree and, for every internal node in the tree, runs `explore` to check if it should
+    /// continue down to the leaves or stop at this node. If it stops at an internal node, it gives back the stored
+    /// sum of the node. If it reaches a leaf, it gives back the leaf passed to `folder.gather()`.
+    ///
+    /// Unlike `PointerOctree::iter_fold`, this never needs to call `fold` or use a cache.
+    pub fn iter_fold<E>(&self, explore: E) -> FoldOctreeIter<'_, T, M, F, E>
+    where
+        E: FnMut(MortonRegion<M>) -> bool,
+    {
+        FoldOctreeIter {
+            tree: self,
+            stack: vec![(Loc::Root, MortonRegion::base())],
+            explore,
+        }
+    }
+
+    /// Iterate over all octree nodes and their morton codes in z-order.
+    pub fn iter(&self) -> impl Iterator<Item = (M, &T)> {
+        self.tree.iter()
+    }
+
+    /// Removes every leaf from the tree.
+    pub fn clear(&mut self) {
+        self.tree.clear();
+        self.sums.clear();
+    }
+
+    /// Returns the number of leaves in the tree.
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Checks if the octree is empty.
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Gets the stored sum of a node.
+    fn node_sum(&self, node: u32) -> F::Sum {
+        self.sums[node as usize]
+            .clone()
+            .expect("space::FoldOctree::node_sum(): every node in the tree has a sum")
+    }
+
+    /// Folds every node on the path from the root to `morton` again, from the bottom up.
+    ///
+    /// Every node whose leaves changed when the leaf at `morton` changed is still on this path, and nodes that
+    /// were created by an insertion are only on this path.
+    fn refold(&mut self, morton: M) {
+        let mut path = Vec::with_capacity(M::dim_bits());
+        let mut loc = Loc::Root;
+        while let Slot::Node(node) = *self.tree.slot(loc) {
+            path.push(node);
+            loc = Loc::Child(node, morton.get_level(path.len() - 1));
+        }
+        if let Some(&last) = path.iter().max() {
+            if last as usize >= self.sums.len() {
+                self.sums.resize(last as usize + 1, None);
+            }
+        }
+        for &node in path.iter().rev() {
+            let sums = &self.sums;
+            let folder = &self.folder;
+            let sum = folder.fold(self.tree.children(node).iter().filter_map(|child| match *child {
+                Slot::Node(child) => sums[child as usize].clone(),
+                Slot::Leaf(ref item, morton) => Some(folder.gather(morton, item)),
+                Slot::None => None,
+            }));
+            self.sums[node as usize] = Some(sum);
+        }
+    }
+}
+
+impl<T, M, F> Extend<(M, T)> for FoldOctree<T, M, F>
+where
+    M: Morton,
+    F: Folder<T, M>,
+    F::Sum: Clone,
+{
+    fn extend<I>(&mut self, it: I)
+    where
+        I: IntoIterator<Item = (M, T)>,
+    {
+        for (morton, item) in it {
+            self.insert(morton, item);
+        }
+    }
+}
+
+/// A mutable reference to a leaf of a `FoldOctree`, which folds the path above the leaf again when it is dropped.
+pub struct FoldRefMut<'a, T, M, F>
+where
+    M: Morton,
+    F: Folder<T, M>,
+    F::Sum: Clone,
+{
+    tree: &'a mut FoldOctree<T, M, F>,
+    loc: Loc,
+    morton: M,
+}
+
+impl<T, M, F> FoldRefMut<'_, T, M, F>
+where
+    M: Morton,
+    F: Folder<T, M>,
+    F::Sum: Clone,
+{
+    /// Gets the morton of the leaf.
+    pub fn key(&self) -> M {
+        self.morton
+    }
+}
+
+impl<T, M, F> Deref for FoldRefMut<'_, T, M, F>
+where
+    M: Morton,
+    F: Folder<T, M>,
+    F::Sum: Clone,
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.tree.tree.leaf(self.loc)
+    }
+}
+
+impl<T, M, F> DerefMut for FoldRefMut<'_, T, M, F>
+where
+    M: Morton,
+    F: Folder<T, M>,
+    F::Sum: Clone,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        self.tree.tree.leaf_mut(self.loc)
+    }
+}
+
+impl<T, M, F> Drop for FoldRefMut<'_, T, M, F>
+where
+    M: Morton,
+    F: Folder<T, M>,
+    F::Sum: Clone,
+{
+    fn drop(&mut self) {
+        self.tree.refold(self.morton);
+    }
+}
+
+/// Iterates over the sums of the regions of a `FoldOctree` that `explore` stops at. See `FoldOctree::iter_fold`.
+pub struct FoldOctreeIter<'a, T, M, F, E>
+where
+    F: Folder<T, M>,
+{
+    tree: &'a FoldOctree<T, M, F>,
+    stack: Vec<(Loc, MortonRegion<M>)>,
+    explore: E,
+}
+
+impl<T, M, F, E> Iterator for FoldOctreeIter<'_, T, M, F, E>
+where
+    M: Morton,
+    F: Folder<T, M>,
+    F::Sum: Clone,
+    E: FnMut(MortonRegion<M>) -> bool,
+{
+    type Item = (MortonRegion<M>, F::Sum);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((loc, region)) = self.stack.pop() {
+            match *self.tree.tree.slot(loc) {
+                Slot::Node(node) => {
+                    if (self.explore)(region) {
+                        // Push the children in reverse so that they are visited in z-order.
+                        for ix in (0..8).rev() {
+                            self.stack.push((Loc::Child(node, ix), region.enter(ix)));
+                        }
+                    } else {
+                        return Some((region, self.tree.node_sum(node)));
+                    }
+                }
+                Slot::Leaf(ref item, morton) => return Some((region, self.tree.folder.gather(morton, item))),
+                Slot::None => {}
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::morton::MortonRegionMap;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Counts the leaves and adds up their items.
+    struct CountSum;
+
+    impl<M> Folder<u64, M> for CountSum {
+        type Sum = (usize, u64);
+
+        fn gather(&self, _: M, item: &u64) -> Self::Sum {
+            (1, *item)
+        }
+
+        fn fold<I>(&self, it: I) -> Self::Sum
+        where
+            I: Iterator<Item = Self::Sum>,
+        {
+            it.fold((0, 0), |a, b| (a.0 + b.0, a.1 + b.1))
+        }
+    }
+
+    #[test]
+    fn test_fold_octree_matches_collect_fold() {
+        let mut rng = SmallRng::from_seed([20; 16]);
+        let mut tree = FoldOctree::<u64, u16, _>::new(CountSum);
+        for round in 0..2000 {
+            // Only a few mortons are used, so there are many replacements and removals of existing leaves.
+            let morton = rng.gen::<u16>() & u16::used_bits() & 0b111_001_011;
+            match rng.gen_range(0, 3) {
+                0 => tree.insert(morton, rng.gen_range(0, 100)),
+                1 => {
+                    tree.remove(morton);
+                }
+                _ => {
+                    if let Some(mut item) = tree.get_mut(morton) {
+                        *item += 1;
+                    }
+                }
+            }
+            if round % 50 != 0 {
+                continue;
+            }
+            let expected: MortonRegionMap<(usize, u64), u16> = tree.tree().collect_fold(&CountSum);
+            for (&region, &sum) in &expected {
+                assert_eq!(tree.region_sum(region), Some(sum));
+            }
+            assert_eq!(tree.sum(), expected.get(&MortonRegion::base()).copied());
+            let shallow: Vec<_> = tree.iter_fold(|region| region.level < 2).collect();
+            assert_eq!(shallow.iter().map(|&(_, (count, _))| count).sum::<usize>(), tree.len());
+            for (region, sum) in shallow {
+                assert_eq!(expected[&region], sum);
+            }
+        }
+        tree.clear();
+        assert_eq!(tree.sum(), None);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::morton::{Morton, MortonRegion, MortonWrapper};
+use crate::queue::Queued;
+
+use nalgebra::Vector3;
+use num_traits::{Float, FromPrimitive, ToPrimitive};
+use std::collections::BinaryHeap;
+
+/// A distance function used to find the nearest neighbors in an octree.
+///
+/// Points are in the normalized space of the octree, where every axis is in `[0, 1)`. This is the space that
+/// `MortonWrapper` converts to and from. Leaves are located at the center of their voxel.
+pub trait Distance<S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// Gets the distance between two points.
+    fn distance(&self, a: Vector3<S>, b: Vector3<S>) -> S;
+
+    /// Gets the distance from `point` to the closest point in the box spanning from `min` to `max`.
+    ///
+    /// This must never be larger than `distance` from `point` to any point in the box, otherwise the
+    /// search will give back incorrect results.
+    fn box_distance(&self, point: Vector3<S>, min: Vector3<S>, max: Vector3<S>) -> S;
+}
+
+/// The standard straight line distance between points.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Euclidean;
+
+impl<S> Distance<S> for Euclidean
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    #[inline]
+    fn distance(&self, a: Vector3<S>, b: Vector3<S>) -> S {
+        SquaredEuclidean.distance(a, b).sqrt()
+    }
+
+    #[inline]
+    fn box_distance(&self, point: Vector3<S>, min: Vector3<S>, max: Vector3<S>) -> S {
+        SquaredEuclidean.box_distance(point, min, max).sqrt()
+    }
+}
+
+/// The square of the `Euclidean` distance. This gives back the same neighbors without computing any square roots.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SquaredEuclidean;
+
+impl<S> Distance<S> for SquaredEuclidean
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    #[inline]
+    fn distance(&self, a: Vector3<S>, b: Vector3<S>) -> S {
+        (0..3).fold(S::zero(), |sum, i| sum + (a[i] - b[i]).powi(2))
+    }
+
+    #[inline]
+    fn box_distance(&self, point: Vector3<S>, min: Vector3<S>, max: Vector3<S>) -> S {
+        (0..3).fold(S::zero(), |sum, i| {
+            let delta = (min[i] - point[i]).max(point[i] - max[i]).max(S::zero());
+            sum + delta.powi(2)
+        })
+    }
+}
+
+/// Wraps another distance so that the space wraps around on every axis, like the surface of a torus.
+///
+/// This is useful for simulations with periodic boundaries. The inner distance must only grow as the
+/// distance along each axis grows, which is the case for `Euclidean` and `SquaredEuclidean`.
+///
+/// ```
+/// use space::{Distance, Euclidean, Toroidal};
+/// use nalgebra::Vector3;
+///
+/// let a = Vector3::new(0.05, 0.5, 0.5);
+/// let b = Vector3::new(0.95, 0.5, 0.5);
+/// assert!((Toroidal(Euclidean).distance(a, b) - 0.1f64).abs() < 1e-9);
+/// ```
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Toroidal<D>(pub D);
+
+impl<S, D> Distance<S> for Toroidal<D>
+where
+    S: Float + std::fmt::Debug + 'static,
+    D: Distance<S>,
+{
+    #[inline]
+    fn distance(&self, a: Vector3<S>, b: Vector3<S>) -> S {
+        // Move `b` to its closest image around `a`.
+        let b = Vector3::from_fn(|i, _| {
+            let delta = b[i] - a[i];
+            a[i] + delta - delta.round()
+        });
+        self.0.distance(a, b)
+    }
+
+    #[inline]
+    fn box_distance(&self, point: Vector3<S>, min: Vector3<S>, max: Vector3<S>) -> S {
+        // Each axis is independent, so the image of the box can be chosen per axis.
+        let shifts: Vector3<S> = Vector3::from_fn(|i, _| {
+            let gap = |shift: S| (min[i] + shift - point[i]).max(point[i] - max[i] - shift).max(S::zero());
+            [-S::one(), S::one()]
+                .iter()
+                .fold(S::zero(), |best, &shift| if gap(shift) < gap(best) { shift } else { best })
+        });
+        let shift = |corner: Vector3<S>| corner.zip_map(&shifts, |d, shift| d + shift);
+        self.0.box_distance(point, shift(min), shift(max))
+    }
+}
+
+/// Something the nearest neighbor search still has to look at.
+pub(crate) enum Candidate<M, N, L> {
+    /// A region of the tree that can be expanded using the node `N`.
+    Region(MortonRegion<M>, N),
+    /// A leaf of the tree.
+    Leaf(M, L),
+}
+
+/// Gets the corners of a region in the normalized space.
+pub(crate) fn region_bounds<S, M>(region: MortonRegion<M>) -> (Vector3<S>, Vector3<S>)
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton,
+{
+    let center: Vector3<S> = region.into();
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let half = (S::one() + S::one()).powi(-(region.level as i32 + 1));
+    (center.map(|d| d - half), center.map(|d| d + half))
+}
+
+/// Performs a best-first search for the `k` closest leaves to `point`, starting from the whole space.
+///
+/// `expand` is given a region and its node and must add every non-empty child of the node to the `Vec`.
+/// If the node itself is a leaf, it must add itself instead. The leaves are given back from closest to farthest.
+pub(crate) fn search<S, M, D, N, L, F>(
+    point: Vector3<S>,
+    k: usize,
+    distance: &D,
+    root: N,
+    mut expand: F,
+) -> Vec<(M, L, S)>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton,
+    D: Distance<S>,
+    F: FnMut(MortonRegion<M>, N, &mut Vec<Candidate<M, N, L>>),
+{
+    let mut found = Vec::with_capacity(k);
+    if k == 0 {
+        return found;
+    }
+    let mut queue = BinaryHeap::new();
+    queue.push(Queued {
+        distance: S::zero(),
+        candidate: Candidate::Region(MortonRegion::base(), root),
+    });
+    let mut children = Vec::with_capacity(8);
+    while let Some(Queued { distance: d, candidate }) = queue.pop() {
+        match candidate {
+            Candidate::Leaf(morton, item) => {
+                // Nothing left in the queue can be closer than this leaf.
+                found.push((morton, item, d));
+                if found.len() == k {
+                    break;
+                }
+            }
+            Candidate::Region(region, node) => {
+                expand(region, node, &mut children);
+                queue.extend(children.drain(..).map(|candidate| {
+                    let distance = match candidate {
+                        Candidate::Region(region, _) => {
+                            let (min, max) = region_bounds(region);
+                            distance.box_distance(point, min, max)
+                        }
+                        Candidate::Leaf(morton, _) => distance.distance(point, MortonWrapper(morton).into()),
+                    };
+                    Queued { distance, candidate }
+                }));
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::{LinearOctree, PointerOctree};
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    fn brute_force<D: Distance<f64>>(points: &[u32], point: Vector3<f64>, k: usize, distance: &D) -> Vec<f64> {
+        let mut distances: Vec<f64> = points
+            .iter()
+            .map(|&m| distance.distance(point, MortonWrapper(m).into()))
+            .collect();
+        distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        distances.truncate(k);
+        distances
+    }
+
+    fn check<D: Distance<f64>>(distance: &D) {
+        let mut rng = SmallRng::from_seed([7; 16]);
+        let mut points: Vec<u32> = (0..500).map(|_| rng.gen::<u32>() & u32::used_bits()).collect();
+        points.sort_unstable();
+        points.dedup();
+        let mut pointer = PointerOctree::new();
+        pointer.extend(points.iter().map(|&m| (m, m)));
+        let mut linear = LinearOctree::new();
+        linear.extend(points.iter().map(|&m| (m, m)));
+
+        for _ in 0..50 {
+            let point = Vector3::new(rng.gen(), rng.gen(), rng.gen());
+            let k = rng.gen_range(1, 20);
+            let expected = brute_force(&points, point, k, distance);
+            let pointer_found = pointer.knn(point, k, distance);
+            let linear_found = linear.knn(point, k, distance);
+            for found in &[pointer_found, linear_found] {
+                assert!(found.iter().all(|&(m, &item, _)| m == item));
+                assert!(found
+                    .iter()
+                    .all(|&(m, _, d)| (d - distance.distance(point, MortonWrapper(m).into())).abs() < 1e-12));
+                let distances: Vec<f64> = found.iter().map(|&(_, _, d)| d).collect();
+                assert_eq!(distances, expected);
+            }
+            assert_eq!(pointer.nearest(point, distance).map(|n| n.2), expected.first().copied());
+            assert_eq!(linear.nearest(point, distance).map(|n| n.2), expected.first().copied());
+        }
+    }
+
+    #[test]
+    fn test_knn_brute_force() {
+        check(&Euclidean);
+        check(&SquaredEuclidean);
+        check(&Toroidal(Euclidean));
+        check(&Toroidal(SquaredEuclidean));
+    }
+
+    #[test]
+    fn test_knn_small_trees() {
+        let point = Vector3::new(0.5, 0.5, 0.5);
+        let mut pointer = PointerOctree::<(), u64>::new();
+        let mut linear = LinearOctree::<(), u64>::new();
+        assert!(pointer.nearest(point, &Euclidean).is_none());
+        assert!(linear.nearest(point, &Euclidean).is_none());
+        // A tree with a single leaf stores it at the root.
+        pointer.insert(5, ());
+        linear.insert(5, ());
+        assert_eq!(pointer.knn(point, 3, &Euclidean).len(), 1);
+        assert_eq!(linear.knn(point, 3, &Euclidean).len(), 1);
+        assert!(pointer.knn(point, 0, &Euclidean).is_empty());
+    }
+}
@@ -1,7 +1,18 @@
-use crate::{
-    morton::{Morton, MortonMap, MortonRegionMap, MortonRegion, MortonWrapper, morton_levels},
-    octree::Folder,
-};
+use crate::index::{self, SpatialIndex, SpatialKey};
+use crate::morton::{morton_levels, Hilbert, Morton, MortonBox, MortonMap, MortonRegion, MortonRegionMap, MortonWrapper};
+use crate::octree::build;
+use crate::octree::knn::{self, Candidate};
+use crate::octree::radius;
+#[cfg(feature = "rayon")]
+use crate::octree::PARALLEL_FOLD_LEVELS;
+use crate::octree::{Ball, Distance, Folder};
+use crate::StorageAccess;
+
+use nalgebra::Vector3;
+use num_traits::{Float, FromPrimitive, ToPrimitive};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::collections::hash_map;
 
 /// A linear hashed octree. This has constant time lookup for a given region or morton code.
 ///
@@ -65,11 +76,118 @@ impl<T, M> LinearOctree<T, M>
     /// use space::LinearOctree;
     /// let tree = LinearOctree::<String, u64>::new();
     /// ```
+    #[must_use]
     pub fn new() -> Self {
         Self::default()
     }
 
-    /// Get iterator to the underlying MortonMap
+    /// Builds an octree from items sorted by morton in a single pass from the leaves up, which is much faster than
+    /// inserting them one at a time. When a morton appears more than once, its last item is kept like with `insert`.
+    ///
+    /// Panics if the mortons are not sorted. See `from_unsorted` for items in any order.
+    ///
+    /// ```
+    /// use space::{LinearOctree, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let mut items: Vec<(u64, u64)> = (0..100).map(|x| (Morton::encode(Vector3::new(x, 0, x)), x)).collect();
+    /// items.sort();
+    /// let tree = LinearOctree::from_sorted(items);
+    /// assert_eq!(tree.len(), 100);
+    /// assert_eq!(tree.get(Morton::encode(Vector3::new(7, 0, 7))), Some(&7));
+    /// ```
+    pub fn from_sorted<I>(items: I) -> Self
+    where
+        I: IntoIterator<Item = (M, T)>,
+    {
+        let mut leaves = MortonMap::<_, M>::default();
+        let mut internals = MortonRegionMap::default();
+        // Each part of the tree is given back as `Some` morton for a leaf or `None` for a node. An empty region is
+        // stored as a null morton, a leaf as its morton, and a node isn't stored at all.
+        let root = build::build_sorted(
+            items,
+            |morton, item| {
+                leaves.insert(MortonWrapper(morton), item);
+                Some(morton)
+            },
+            |region: MortonRegion<M>, children: [Option<Option<M>>; 8]| {
+                for (i, child) in children.iter().enumerate() {
+                    if let Some(value) = child.unwrap_or_else(|| Some(M::null())) {
+                        internals.insert(region.enter(i), value);
+                    }
+                }
+                None
+            },
+        );
+        if let Some(value) = root.unwrap_or_else(|| Some(M::null())) {
+            internals.insert(MortonRegion::base(), value);
+        }
+        Self { leaves, internals }
+    }
+
+    /// Same as `from_sorted`, but the items can be in any order. They are sorted with a radix sort first, which
+    /// takes linear time.
+    #[must_use]
+    pub fn from_unsorted(items: Vec<(M, T)>) -> Self {
+        Self::from_sorted(build::radix_sort(items))
+    }
+
+    /// Same as `from_unsorted`, but the octants at the top of the tree are sorted and built on separate threads.
+    ///
+    /// ```
+    /// use space::{LinearOctree, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let items: Vec<(u64, u64)> = (0..10000).map(|x| (Morton::encode(Vector3::new(x, x % 7, x % 13)), x)).collect();
+    /// let tree = LinearOctree::par_from_unsorted(items);
+    /// assert_eq!(tree.len(), 10000);
+    /// ```
+    #[must_use]
+    pub fn par_from_unsorted(items: Vec<(M, T)>) -> Self
+        where
+            M: Send,
+            T: Send,
+    {
+        // The octants are built on separate threads, so each part of the tree carries the leaves and internal nodes
+        // under it until it is joined into its parent. Values are stored the same way as in `from_sorted`.
+        let root = build::build_unsorted_parallel(
+            items,
+            &|morton, item| BuiltPart {
+                value: Some(morton),
+                leaves: vec![(morton, item)],
+                internals: Vec::new(),
+            },
+            &|region: MortonRegion<M>, children: [Option<BuiltPart<T, M>>; 8]| {
+                let mut joined = BuiltPart::node();
+                for (i, child) in IntoIterator::into_iter(children).enumerate() {
+                    match child {
+                        Some(child) => {
+                            if let Some(value) = child.value {
+                                joined.internals.push((region.enter(i), value));
+                            }
+                            joined.join(child);
+                        }
+                        None => joined.internals.push((region.enter(i), M::null())),
+                    }
+                }
+                joined
+            },
+        );
+        let mut root = root.unwrap_or(BuiltPart {
+            value: Some(M::null()),
+            leaves: Vec::new(),
+            internals: Vec::new(),
+        });
+        if let Some(value) = root.value {
+            root.internals.push((MortonRegion::base(), value));
+        }
+        Self {
+            leaves: root.leaves.into_iter().map(|(morton, item)| (MortonWrapper(morton), item)).collect(),
+            internals: root.internals.into_iter().collect(),
+        }
+    }
+
+    /// Get iterator to the underlying `MortonMap`
     /// ```
     /// use space::{MortonWrapper, LinearOctree};
     /// let mut tree = LinearOctree::<String, u64>::new();
@@ -88,7 +206,7 @@ impl<T, M> LinearOctree<T, M>
         self.leaves.iter()
     }
 
-    /// Get mutable iterator to the underlying MortonMap
+    /// Get mutable iterator to the underlying `MortonMap`
     /// ```
     /// use space::{MortonWrapper, LinearOctree};
     /// let mut tree = LinearOctree::<String, u64>::new();
@@ -111,6 +229,24 @@ impl<T, M> LinearOctree<T, M>
         self.leaves.iter_mut()
     }
 
+    /// Get an iterator over the leaves sorted in Hilbert order, which keeps consecutive leaves spatially close.
+    ///
+    /// This has to sort the leaves, so it takes O(n log n) time.
+    ///
+    /// ```
+    /// use space::{Hilbert, LinearOctree};
+    /// let mut tree = LinearOctree::<(), u64>::new();
+    /// tree.extend((0..100u64).map(|m| (m * 12345, ())));
+    ///
+    /// let keys: Vec<_> = tree.iter_hilbert().map(|(m, _)| Hilbert::from(*m)).collect();
+    /// assert_eq!(keys.len(), 100);
+    /// assert!(keys.windows(2).all(|w| w[0] < w[1]));
+    /// ```
+    pub fn iter_hilbert(&self) -> impl Iterator<Item = (&MortonWrapper<M>, &T)> {
+        let mut leaves: Vec<_> = self.leaves.iter().map(|(m, t)| (Hilbert::from(*m), m, t)).collect();
+        leaves.sort_unstable_by_key(|&(key, _, _)| key);
+        leaves.into_iter().map(|(_, m, t)| (m, t))
+    }
 
     /// Inserts the item into the octree.
     ///
@@ -125,66 +261,115 @@ impl<T, M> LinearOctree<T, M>
     ///
     /// ```
     pub fn insert(&mut self, morton: M, item: T) {
-        use std::collections::hash_map::Entry::*;
-        // First we must insert the node into the leaves.
-        match self.leaves.entry(MortonWrapper(morton)) {
-            Occupied(mut o) => {
-                o.insert(item);
+        match self.entry(morton) {
+            LinearEntry::Occupied(mut entry) => {
+                entry.insert(item);
             }
-            Vacant(v) => {
-                v.insert(item);
-
-                // Because it was vacant, we need to adjust the tree's internal nodes.
-                for mut region in morton_levels(morton) {
-                    // Check if the region is in the map.
-                    if let Occupied(mut o) = self.internals.entry(region) {
-                        // It was in the map. Check if it was null or not.
-                        if o.get().is_null() {
-                            // It was null, so just replace the null with the leaf.
-                            *o.get_mut() = morton;
-                            // Now return because we are done.
-                            return;
-                        } else {
-                            // It was not null, so it is a leaf.
-                            // This means that we need to move the leaf to its sub-region.
-                            // We also need to populate the other 6 null nodes created by this operation.
-                            let leaf = o.remove_entry().1;
-                            // Keep making the tree deeper until both leaves differ.
-                            // TODO: Some bittwiddling with mortons might be able to get the number of traversals.
-                            for level in region.level..M::dim_bits() {
-                                let leaf_level = leaf.get_level(level);
-                                let item_level = morton.get_level(level);
-                                if leaf_level == item_level {
-                                    // They were the same so set every other region to null.
-                                    for i in 0..8 {
-                                        if i != leaf_level {
-                                            self.internals.insert(region.enter(i), M::null());
-                                        }
-                                    }
-                                    region = region.enter(leaf_level);
-                                } else {
-                                    // They were different, so set the other 6 regions null and make 2 leaves.
-                                    for i in 0..8 {
-                                        if i == leaf_level {
-                                            self.internals.insert(region.enter(i), leaf);
-                                        } else if i == item_level {
-                                            self.internals.insert(region.enter(i), morton);
-                                        } else {
-                                            self.internals.insert(region.enter(i), M::null());
-                                        }
-                                    }
-                                    // Now we must return as we have added the leaves.
-                                    return;
-                                }
-                            }
-                            unreachable!();
-                        }
-                    }
-                }
+            LinearEntry::Vacant(entry) => {
+                entry.insert(item);
             }
         }
     }
 
+    /// Gets the entry for a morton in the octree for in-place manipulation, which only does one hash lookup.
+    ///
+    /// ```
+    /// use space::{LinearOctree, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let mut tree = LinearOctree::<usize, u64>::new();
+    /// let m = Morton::encode(Vector3::new(1, 2, 3));
+    /// *tree.entry(m).or_insert(0) += 1;
+    /// tree.entry(m).and_modify(|n| *n += 1).or_insert(0);
+    /// assert_eq!(tree.get(m), Some(&2));
+    /// ```
+    pub fn entry(&mut self, morton: M) -> LinearEntry<'_, T, M> {
+        use std::collections::hash_map::Entry::{Occupied, Vacant};
+        let internals = &mut self.internals;
+        match self.leaves.entry(MortonWrapper(morton)) {
+            Occupied(entry) => LinearEntry::Occupied(LinearOccupiedEntry { entry, internals }),
+            Vacant(entry) => LinearEntry::Vacant(LinearVacantEntry { entry, internals }),
+        }
+    }
+
+    /// Removes the item at a morton from the octree and gives it back, if it was present.
+    ///
+    /// Internal nodes that are left with a single leaf or no leaves are collapsed, so the octree has the same
+    /// internal nodes as if the removed item was never inserted.
+    ///
+    /// ```
+    /// use space::{LinearOctree, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let mut tree = LinearOctree::<String, u64>::new();
+    /// let m = Morton::encode(Vector3::new(1, 2, 3));
+    /// assert!(tree.remove(m).is_none());
+    ///
+    /// tree.insert(m, "hello".to_owned());
+    /// assert_eq!(tree.remove(m), Some("hello".to_owned()));
+    /// assert!(tree.is_empty());
+    /// ```
+    pub fn remove(&mut self, morton: M) -> Option<T> {
+        match self.entry(morton) {
+            LinearEntry::Occupied(entry) => Some(entry.remove()),
+            LinearEntry::Vacant(_) => None,
+        }
+    }
+
+    /// Returns the number of leaves in the tree.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Checks if the octree is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Removes every leaf from the octree.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Keeps only the leaves for which `f` gives back `true`, removing the rest.
+    ///
+    /// ```
+    /// use space::LinearOctree;
+    ///
+    /// let mut tree = LinearOctree::<u64, u64>::new();
+    /// tree.extend((0..100u64).map(|m| (m * 12345, m)));
+    /// tree.retain(|_, &mut n| n % 2 == 0);
+    /// assert_eq!(tree.len(), 50);
+    /// assert!(tree.iter().all(|(_, &n)| n % 2 == 0));
+    /// ```
+    pub fn retain<F>(&mut self, mut f: F)
+        where
+            F: FnMut(M, &mut T) -> bool,
+    {
+        let mut removed = Vec::new();
+        self.leaves.retain(|&MortonWrapper(morton), item| {
+            let keep = f(morton, item);
+            if !keep {
+                removed.push(morton);
+            }
+            keep
+        });
+        for morton in removed {
+            remove_internal(&mut self.internals, morton);
+        }
+    }
+
+    /// Removes every leaf from the octree and gives back an iterator over them in arbitrary order.
+    ///
+    /// The octree is empty after this is called, even if the iterator is not consumed.
+    pub fn drain(&mut self) -> impl Iterator<Item = (M, T)> + '_ {
+        self.internals.clear();
+        self.internals.insert(MortonRegion::default(), M::null());
+        self.leaves.drain().map(|(MortonWrapper(morton), item)| (morton, item))
+    }
+
     /// Fetches an immutable reference to the value of a specific coordinate in the octree
     ///
     /// ```
@@ -205,6 +390,147 @@ impl<T, M> LinearOctree<T, M>
         self.leaves.get_mut(&MortonWrapper(morton))
     }
 
+    /// Iterates over all leaves inside of the axis-aligned box which has the mortons `min` and `max` as opposite
+    /// corners (both inclusive). Leaves are given back in z-order. See `PointerOctree::query_aabb`.
+    ///
+    /// ```
+    /// use space::{LinearOctree, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let mut tree = LinearOctree::<usize, u64>::new();
+    /// for (i, &x) in [0, 1, 2, 3].iter().enumerate() {
+    ///     tree.insert(Morton::encode(Vector3::new(x, 1, 1)), i);
+    /// }
+    /// let min = Morton::encode(Vector3::new(1, 0, 0));
+    /// let max = Morton::encode(Vector3::new(2, 1, 1));
+    /// let found: Vec<usize> = tree.query_aabb(min, max).map(|(_, &i)| i).collect();
+    /// assert_eq!(found, vec![1, 2]);
+    /// ```
+    pub fn query_aabb(&self, min: M, max: M) -> impl Iterator<Item = (M, &T)> {
+        let bounds = MortonBox::new(min, max);
+        let (min, max) = (bounds.min.decode(), bounds.max.decode());
+        let region = MortonRegion::base();
+        LinearAabbIter {
+            tree: self,
+            regions: vec![(region, MortonBox::region_inside(region, min, max))],
+            bounds,
+            min,
+            max,
+        }
+    }
+
+    /// Finds the `k` leaves closest to `point` according to `distance`, sorted from closest to farthest.
+    ///
+    /// Each leaf is given back along with its distance. The `point` is in the normalized space of the octree
+    /// (see `Distance`), and the search visits regions in order of their distance to the `point`.
+    ///
+    /// ```
+    /// use space::{LinearOctree, MortonWrapper, SquaredEuclidean};
+    /// use nalgebra::Vector3;
+    ///
+    /// let mut tree = LinearOctree::<&str, u64>::new();
+    /// for &(name, x) in &[("a", 0.1), ("b", 0.4), ("c", 0.9)] {
+    ///     let MortonWrapper(m) = Vector3::new(x, 0.5, 0.5).into();
+    ///     tree.insert(m, name);
+    /// }
+    /// let nearest: Vec<&str> = tree.knn(Vector3::new(0.8, 0.5, 0.5), 2, &SquaredEuclidean).into_iter().map(|(_, &n, _)| n).collect();
+    /// assert_eq!(nearest, vec!["c", "b"]);
+    /// ```
+    pub fn knn<S, D>(&self, point: Vector3<S>, k: usize, distance: &D) -> Vec<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        D: Distance<S>,
+    {
+        knn::search(point, k, distance, (), |region, (), candidates| self.candidates(region, candidates))
+    }
+
+    /// Finds the leaf closest to `point` according to `distance`. See `knn`.
+    pub fn nearest<S, D>(&self, point: Vector3<S>, distance: &D) -> Option<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        D: Distance<S>,
+    {
+        self.knn(point, 1, distance).pop()
+    }
+
+    /// Finds every leaf within the Euclidean distance `ball.radius` of `ball.center`, along with its distance.
+    ///
+    /// The leaves are not given back in any particular order. See `within_radius_sorted` to sort them by distance.
+    ///
+    /// ```
+    /// use space::{LinearOctree, LeveledRegion};
+    /// use nalgebra::Vector3;
+    ///
+    /// let region = LeveledRegion(0);
+    /// let mut tree = LinearOctree::<&str, u64>::new();
+    /// for &(name, x) in &[("a", -0.5), ("b", 0.0), ("c", 0.5)] {
+    ///     tree.insert(region.discretize(Vector3::new(x, 0.0, 0.0)).unwrap(), name);
+    /// }
+    /// let ball = region.ball(Vector3::new(0.3, 0.0, 0.0), 0.35);
+    /// let mut found: Vec<&str> = tree.within_radius(&ball).into_iter().map(|(_, &n, _)| n).collect();
+    /// found.sort();
+    /// assert_eq!(found, vec!["b", "c"]);
+    /// assert_eq!(tree.count_within_radius(&ball), 2);
+    /// ```
+    pub fn within_radius<S>(&self, ball: &Ball<S>) -> Vec<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut found = Vec::new();
+        radius::search(
+            ball,
+            (),
+            |region, (), candidates| self.candidates(region, candidates),
+            |morton, item, distance: S| found.push((morton, item, distance.sqrt())),
+        );
+        found
+    }
+
+    /// Same as `within_radius`, but the leaves are sorted from closest to farthest.
+    pub fn within_radius_sorted<S>(&self, ball: &Ball<S>) -> Vec<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut found = self.within_radius(ball);
+        found.sort_unstable_by(|a, b| a.2.partial_cmp(&b.2).unwrap_or(std::cmp::Ordering::Equal));
+        found
+    }
+
+    /// Counts the leaves that `within_radius` would find without collecting them.
+    pub fn count_within_radius<S>(&self, ball: &Ball<S>) -> usize
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut count = 0;
+        radius::search(
+            ball,
+            (),
+            |region, (), candidates| self.candidates(region, candidates),
+            |_, _: &T, _| count += 1,
+        );
+        count
+    }
+
+    /// Adds every non-empty child of `region` to `candidates` for a search, or the leaf in `region` if it has one.
+    fn candidates<'a>(&'a self, region: MortonRegion<M>, candidates: &mut Vec<Candidate<M, (), &'a T>>) {
+        match self.internals.get(&region) {
+            Some(m) if !m.is_null() => candidates.push(Candidate::Leaf(*m, &self.leaves[&MortonWrapper(*m)])),
+            None => {
+                // This needs to be traversed deeper, so look at the children.
+                for child in (0..8).map(|i| region.enter(i)) {
+                    match self.internals.get(&child) {
+                        Some(m) if !m.is_null() => {
+                            candidates.push(Candidate::Leaf(*m, &self.leaves[&MortonWrapper(*m)]));
+                        }
+                        None => candidates.push(Candidate::Region(child, ())),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
     /// This gathers the octree in a tree fold by gathering leaves with `gatherer` and folding with `folder`.
     /// This allows information to be folded up the tree so it doesn't have to be computed multiple times.
     /// This has O(n) (exactly `n`) `gather` operations and O(n) (approximately `8/7 * n`) `fold` operations,
@@ -251,6 +577,70 @@ impl<T, M> LinearOctree<T, M>
             _ => None,
         }
     }
+
+    /// Same as `collect_fold`, but folds the subtrees under the top levels of the tree in parallel with `rayon`.
+    ///
+    /// Each subtree is folded into its own map, and the maps are merged at the end, so this gives back the same
+    /// map as `collect_fold`.
+    #[cfg(feature = "rayon")]
+    pub fn par_collect_fold<F>(&self, folder: &F) -> MortonRegionMap<F::Sum, M>
+        where
+            T: Sync,
+            M: Send + Sync,
+            F: Folder<T, M> + Sync,
+            F::Sum: Clone + Send,
+    {
+        let mut maps = Vec::new();
+        self.par_collect_fold_region(MortonRegion::base(), folder, &mut maps);
+        let mut map = MortonRegionMap::default();
+        map.reserve(maps.iter().map(std::collections::HashMap::len).sum());
+        for part in maps {
+            map.extend(part);
+        }
+        map
+    }
+
+    /// Adds a map for every subtree under the parallel levels of the tree and for every node in them to `maps`.
+    #[cfg(feature = "rayon")]
+    fn par_collect_fold_region<F>(
+        &self,
+        region: MortonRegion<M>,
+        folder: &F,
+        maps: &mut Vec<MortonRegionMap<F::Sum, M>>,
+    ) -> Option<F::Sum>
+        where
+            T: Sync,
+            M: Send + Sync,
+            F: Folder<T, M> + Sync,
+            F::Sum: Clone + Send,
+    {
+        if region.level < PARALLEL_FOLD_LEVELS && !self.internals.contains_key(&region) {
+            // This is an internal node, so its children can be folded in parallel.
+            let children: Vec<_> = (0..8)
+                .into_par_iter()
+                .map(|i| {
+                    let mut maps = Vec::new();
+                    let sum = self.par_collect_fold_region(region.enter(i), folder, &mut maps);
+                    (sum, maps)
+                })
+                .collect();
+            let mut sums = Vec::with_capacity(8);
+            for (sum, mut child_maps) in children {
+                sums.extend(sum);
+                maps.append(&mut child_maps);
+            }
+            let sum = folder.fold(sums.into_iter());
+            let mut map = MortonRegionMap::default();
+            map.insert(region, sum.clone());
+            maps.push(map);
+            Some(sum)
+        } else {
+            let mut map = MortonRegionMap::default();
+            let sum = self.collect_fold_region(region, folder, &mut map);
+            maps.push(map);
+            sum
+        }
+    }
 }
 
 impl<T, M> Extend<(M, T)> for LinearOctree<T, M>
@@ -266,3 +656,568 @@ impl<T, M> Extend<(M, T)> for LinearOctree<T, M>
         }
     }
 }
+
+impl<T, M> StorageAccess<T, M> for LinearOctree<T, M>
+    where
+        M: Morton,
+{
+    fn iter<'a>(&'a self) -> impl Iterator<Item = (M, &'a T)>
+        where
+            T: 'a,
+    {
+        LinearOctree::iter(self).map(|(&MortonWrapper(morton), item)| (morton, item))
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (M, &'a mut T)>
+        where
+            T: 'a,
+    {
+        LinearOctree::iter_mut(self).map(|(&MortonWrapper(morton), item)| (morton, item))
+    }
+
+    fn insert(&mut self, morton: M, item: T) -> Option<T> {
+        match self.entry(morton) {
+            LinearEntry::Occupied(mut entry) => Some(entry.insert(item)),
+            LinearEntry::Vacant(entry) => {
+                entry.insert(item);
+                None
+            }
+        }
+    }
+
+    fn get(&self, morton: M) -> Option<&T> {
+        LinearOctree::get(self, morton)
+    }
+
+    fn get_mut(&mut self, morton: M) -> Option<&mut T> {
+        LinearOctree::get_mut(self, morton)
+    }
+
+    fn remove(&mut self, morton: M) -> Option<T> {
+        LinearOctree::remove(self, morton)
+    }
+
+    fn len(&self) -> usize {
+        LinearOctree::len(self)
+    }
+}
+
+impl<T, M, S> SpatialIndex<T, S> for LinearOctree<T, M>
+    where
+        M: Morton,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    type Key = M;
+
+    fn insert(&mut self, morton: M, item: T) -> Option<T> {
+        StorageAccess::insert(self, morton, item)
+    }
+
+    fn remove(&mut self, morton: M) -> Option<T> {
+        LinearOctree::remove(self, morton)
+    }
+
+    fn knn<D>(&self, point: Vector3<S>, k: usize, distance: &D) -> Vec<(M, &T, S)>
+        where
+            D: Distance<S>,
+    {
+        LinearOctree::knn(self, point, k, distance)
+    }
+
+    fn within_radius(&self, ball: &Ball<S>) -> Vec<(M, &T, S)> {
+        LinearOctree::within_radius(self, ball)
+    }
+
+    fn query_aabb(&self, min: Vector3<S>, max: Vector3<S>) -> Vec<(M, &T)> {
+        // The voxels at the corners can stick out of the box, so the leaves near the edges are checked again.
+        let (min_morton, max_morton) = index::aabb_mortons(min, max);
+        LinearOctree::query_aabb(self, min_morton, max_morton)
+            .filter(|(morton, _)| index::aabb_contains(min, max, morton.position()))
+            .collect()
+    }
+}
+
+/// A view into a single morton in a `LinearOctree`, which may either be vacant or occupied.
+///
+/// Produced by `LinearOctree::entry`.
+pub enum LinearEntry<'a, T, M> {
+    /// The morton has an item in the octree.
+    Occupied(LinearOccupiedEntry<'a, T, M>),
+    /// The morton has no item in the octree.
+    Vacant(LinearVacantEntry<'a, T, M>),
+}
+
+impl<'a, T, M> LinearEntry<'a, T, M>
+    where
+        M: Morton,
+{
+    /// Gets the morton of the entry.
+    pub fn key(&self) -> M {
+        match self {
+            LinearEntry::Occupied(entry) => entry.key(),
+            LinearEntry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Inserts `default` if the entry is vacant and gives back a mutable reference to the item.
+    pub fn or_insert(self, default: T) -> &'a mut T {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of `default` if the entry is vacant and gives back a mutable reference to the item.
+    pub fn or_insert_with<F>(self, default: F) -> &'a mut T
+        where
+            F: FnOnce() -> T,
+    {
+        match self {
+            LinearEntry::Occupied(entry) => entry.into_mut(),
+            LinearEntry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Inserts `T::default()` if the entry is vacant and gives back a mutable reference to the item.
+    pub fn or_default(self) -> &'a mut T
+        where
+            T: Default,
+    {
+        self.or_insert_with(T::default)
+    }
+
+    /// Calls `f` on the item if the entry is occupied.
+    #[must_use]
+    pub fn and_modify<F>(mut self, f: F) -> Self
+        where
+            F: FnOnce(&mut T),
+    {
+        if let LinearEntry::Occupied(ref mut entry) = self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+/// An occupied entry of a `LinearOctree`.
+pub struct LinearOccupiedEntry<'a, T, M> {
+    entry: hash_map::OccupiedEntry<'a, MortonWrapper<M>, T>,
+    internals: &'a mut MortonRegionMap<M, M>,
+}
+
+impl<'a, T, M> LinearOccupiedEntry<'a, T, M>
+    where
+        M: Morton,
+{
+    /// Gets the morton of the entry.
+    #[must_use]
+    pub fn key(&self) -> M {
+        self.entry.key().0
+    }
+
+    /// Gets a reference to the item.
+    #[must_use]
+    pub fn get(&self) -> &T {
+        self.entry.get()
+    }
+
+    /// Gets a mutable reference to the item.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.entry.get_mut()
+    }
+
+    /// Converts the entry into a mutable reference to the item that lives as long as the octree borrow.
+    #[must_use]
+    pub fn into_mut(self) -> &'a mut T {
+        self.entry.into_mut()
+    }
+
+    /// Replaces the item and gives back the old item.
+    pub fn insert(&mut self, item: T) -> T {
+        self.entry.insert(item)
+    }
+
+    /// Removes the item from the octree and gives it back.
+    // The item is often dropped, since removing it is the point.
+    #[allow(clippy::must_use_candidate)]
+    pub fn remove(self) -> T {
+        self.remove_entry().1
+    }
+
+    /// Removes the item from the octree and gives back its morton and the item.
+    ///
+    /// Internal nodes that are left with a single leaf or no leaves are collapsed.
+    #[allow(clippy::must_use_candidate)]
+    pub fn remove_entry(self) -> (M, T) {
+        let (MortonWrapper(morton), item) = self.entry.remove_entry();
+        remove_internal(self.internals, morton);
+        (morton, item)
+    }
+}
+
+/// A vacant entry of a `LinearOctree`.
+pub struct LinearVacantEntry<'a, T, M> {
+    entry: hash_map::VacantEntry<'a, MortonWrapper<M>, T>,
+    internals: &'a mut MortonRegionMap<M, M>,
+}
+
+impl<'a, T, M> LinearVacantEntry<'a, T, M>
+    where
+        M: Morton,
+{
+    /// Gets the morton of the entry.
+    pub fn key(&self) -> M {
+        self.entry.key().0
+    }
+
+    /// Inserts the item at the morton of the entry and gives back a mutable reference to it.
+    pub fn insert(self, item: T) -> &'a mut T {
+        insert_internal(self.internals, self.entry.key().0);
+        self.entry.insert(item)
+    }
+}
+
+/// A part of a `LinearOctree` that was built on its own thread by `par_from_unsorted`.
+struct BuiltPart<T, M> {
+    /// The value that the parent stores for this part, or `None` if it is an internal node.
+    value: Option<M>,
+    leaves: Vec<(M, T)>,
+    internals: Vec<(MortonRegion<M>, M)>,
+}
+
+impl<T, M> BuiltPart<T, M> {
+    /// Creates an internal node with nothing under it yet.
+    fn node() -> Self {
+        BuiltPart {
+            value: None,
+            leaves: Vec::new(),
+            internals: Vec::new(),
+        }
+    }
+
+    /// Moves everything under `other` into this part.
+    fn join(&mut self, mut other: Self) {
+        // Appending to the larger list means that every item is only moved a logarithmic number of times.
+        if other.leaves.len() > self.leaves.len() {
+            std::mem::swap(&mut self.leaves, &mut other.leaves);
+        }
+        if other.internals.len() > self.internals.len() {
+            std::mem::swap(&mut self.internals, &mut other.internals);
+        }
+        self.leaves.append(&mut other.leaves);
+        self.internals.append(&mut other.internals);
+    }
+}
+
+struct LinearAabbIter<'a, T, M>
+    where
+        M: Morton,
+{
+    tree: &'a LinearOctree<T, M>,
+    /// The regions that still need to be visited, along with whether or not they are completely inside the box.
+    regions: Vec<(MortonRegion<M>, bool)>,
+    bounds: MortonBox<M>,
+    min: Vector3<M>,
+    max: Vector3<M>,
+}
+
+impl<'a, T, M> Iterator for LinearAabbIter<'a, T, M>
+    where
+        M: Morton,
+{
+    type Item = (M, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((region, inside)) = self.regions.pop() {
+            match self.tree.internals.get(&region) {
+                Some(m) if !m.is_null() && (inside || self.bounds.contains(*m)) => {
+                    return Some((*m, &self.tree.leaves[&MortonWrapper(*m)]));
+                }
+                None => {
+                    // Push the children in reverse so that they come back out in z-order.
+                    for ix in (0..8).rev() {
+                        let child = region.enter(ix);
+                        if inside {
+                            self.regions.push((child, true));
+                        } else if MortonBox::region_overlaps(child, self.min, self.max) {
+                            self.regions.push((child, MortonBox::region_inside(child, self.min, self.max)));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// Adjusts the internal nodes of the octree to add a leaf at `morton`.
+fn insert_internal<M>(internals: &mut MortonRegionMap<M, M>, morton: M)
+    where
+        M: Morton,
+{
+    use std::collections::hash_map::Entry::Occupied;
+    for mut region in morton_levels(morton) {
+        // Check if the region is in the map.
+        if let Occupied(mut o) = internals.entry(region) {
+            // It was in the map. Check if it was null or not.
+            if o.get().is_null() {
+                // It was null, so just replace the null with the leaf.
+                *o.get_mut() = morton;
+                // Now return because we are done.
+                return;
+            }
+            // It was not null, so it is a leaf.
+            // This means that we need to move the leaf to its sub-region.
+            // We also need to populate the other 6 null nodes created by this operation.
+            let leaf = o.remove_entry().1;
+            // Keep making the tree deeper until both leaves differ.
+            // TODO: Some bittwiddling with mortons might be able to get the number of traversals.
+            for level in region.level..M::dim_bits() {
+                let leaf_level = leaf.get_level(level);
+                let item_level = morton.get_level(level);
+                if leaf_level == item_level {
+                    // They were the same so set every other region to null.
+                    for i in 0..8 {
+                        if i != leaf_level {
+                            internals.insert(region.enter(i), M::null());
+                        }
+                    }
+                    region = region.enter(leaf_level);
+                } else {
+                    // They were different, so set the other 6 regions null and make 2 leaves.
+                    for i in 0..8 {
+                        if i == leaf_level {
+                            internals.insert(region.enter(i), leaf);
+                        } else if i == item_level {
+                            internals.insert(region.enter(i), morton);
+                        } else {
+                            internals.insert(region.enter(i), M::null());
+                        }
+                    }
+                    // Now we must return as we have added the leaves.
+                    return;
+                }
+            }
+            unreachable!();
+        }
+    }
+}
+
+/// Adjusts the internal nodes of the octree to remove the leaf at `morton`.
+fn remove_internal<M>(internals: &mut MortonRegionMap<M, M>, morton: M)
+    where
+        M: Morton,
+{
+    // Only the region that holds the leaf points to it.
+    let mut region = morton_levels(morton)
+        .find(|region| internals.get(region) == Some(&morton))
+        .expect("space::LinearOctree: the removed leaf must be in the internal nodes");
+    internals.insert(region, M::null());
+    // Collapse parent regions that are left with a single leaf or no leaves.
+    while region.level != 0 {
+        region.exit();
+        let mut leaf = M::null();
+        for i in 0..8 {
+            match internals.get(&region.enter(i)) {
+                // A child that needs to be traversed deeper has at least two leaves.
+                None => return,
+                Some(m) if !m.is_null() => {
+                    if !leaf.is_null() {
+                        return;
+                    }
+                    leaf = *m;
+                }
+                _ => {}
+            }
+        }
+        for i in 0..8 {
+            internals.remove(&region.enter(i));
+        }
+        internals.insert(region, leaf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Count;
+
+    impl<M> Folder<u16, M> for Count {
+        type Sum = usize;
+
+        fn gather(&self, _: M, _: &u16) -> Self::Sum {
+            1
+        }
+
+        fn fold<I>(&self, it: I) -> Self::Sum
+        where
+            I: Iterator<Item = Self::Sum>,
+        {
+            it.sum()
+        }
+    }
+
+    #[test]
+    fn test_linear_octree_u16() {
+        let mut tree = LinearOctree::<u16, u16>::new();
+        tree.extend((0..1u16 << 15).step_by(7).map(|m| (m, m)));
+
+        assert_eq!(tree.iter().count(), (1 << 15) / 7 + 1);
+        assert!(tree.iter().all(|(m, &item)| m.0 == item));
+        let folds = tree.collect_fold(&Count);
+        assert_eq!(folds[&MortonRegion::base()], (1 << 15) / 7 + 1);
+    }
+
+    #[test]
+    fn test_linear_octree_entry() {
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+        use nalgebra::Vector3;
+
+        let mut rng = SmallRng::from_seed([14; 16]);
+        for round in 0..20 {
+            let bound = if round % 2 == 0 { 4 } else { 1 << 4 };
+            let mut tree = LinearOctree::<u16, u16>::new();
+            let mut expected = std::collections::BTreeMap::new();
+            for i in 0..300 {
+                let v = Vector3::new(rng.gen_range(0, bound), rng.gen_range(0, bound), rng.gen_range(0, bound));
+                let m = u16::encode(v);
+                match tree.entry(m) {
+                    LinearEntry::Occupied(entry) => {
+                        assert_eq!(entry.key(), m);
+                        if rng.gen() {
+                            assert_eq!(entry.remove_entry(), (m, expected.remove(&m).unwrap()));
+                        } else {
+                            assert_eq!(*entry.into_mut(), expected[&m]);
+                        }
+                    }
+                    LinearEntry::Vacant(entry) => {
+                        assert_eq!(entry.key(), m);
+                        assert_eq!(*entry.insert(i), i);
+                        expected.insert(m, i);
+                    }
+                }
+                *tree.entry(m).and_modify(|n| *n += 1).or_insert(0) += 1;
+                *expected.entry(m).and_modify(|n| *n += 1).or_insert(0) += 1;
+            }
+            let mut found: Vec<(u16, u16)> = tree.iter().map(|(m, &n)| (m.0, n)).collect();
+            found.sort_unstable();
+            assert!(found.into_iter().eq(expected.iter().map(|(&m, &n)| (m, n))));
+
+            // Removing through entries keeps the tree in the same shape as a freshly built tree.
+            let mortons: Vec<u16> = expected.keys().copied().collect();
+            for &m in mortons.iter().step_by(2) {
+                if let LinearEntry::Occupied(entry) = tree.entry(m) {
+                    assert_eq!(entry.remove(), expected.remove(&m).unwrap());
+                } else {
+                    panic!("morton {} should be occupied", m);
+                }
+            }
+            let mut rebuilt = LinearOctree::<u16, u16>::new();
+            rebuilt.extend(expected.iter().map(|(&m, &n)| (m, n)));
+            assert!(tree.internals == rebuilt.internals);
+            assert_eq!(tree.collect_fold(&Count), rebuilt.collect_fold(&Count));
+        }
+    }
+
+    #[test]
+    fn test_linear_octree_remove_retain_drain() {
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = SmallRng::from_seed([15; 16]);
+        for round in 0..20 {
+            let mask = if round % 2 == 0 { 0x1ff } else { u16::used_bits() };
+            let mut tree = LinearOctree::<u16, u16>::new();
+            tree.extend((0..rng.gen_range(0, 400)).map(|_| {
+                let m = rng.gen::<u16>() & mask;
+                (m, m)
+            }));
+            let mut mortons: Vec<u16> = tree.iter().map(|(m, _)| m.0).collect();
+            rng.shuffle(&mut mortons);
+
+            let (removed, kept) = mortons.split_at(mortons.len() / 3);
+            for &m in removed {
+                assert_eq!(tree.remove(m), Some(m));
+                assert_eq!(tree.remove(m), None);
+            }
+            let threshold = rng.gen::<u16>() & mask;
+            tree.retain(|m, &mut n| {
+                assert_eq!(m, n);
+                m < threshold
+            });
+            let kept: Vec<u16> = kept.iter().copied().filter(|&m| m < threshold).collect();
+
+            let mut rebuilt = LinearOctree::<u16, u16>::new();
+            rebuilt.extend(kept.iter().map(|&m| (m, m)));
+            assert_eq!(tree.len(), kept.len());
+            assert_eq!(tree.is_empty(), kept.is_empty());
+            assert!(tree.internals == rebuilt.internals);
+
+            let mut drained: Vec<(u16, u16)> = tree.drain().collect();
+            drained.sort_unstable();
+            let mut kept: Vec<(u16, u16)> = kept.iter().map(|&m| (m, m)).collect();
+            kept.sort_unstable();
+            assert_eq!(drained, kept);
+            assert!(tree.is_empty());
+            assert!(tree.internals == LinearOctree::<u16, u16>::new().internals);
+
+            rebuilt.clear();
+            assert!(rebuilt.is_empty());
+            assert!(rebuilt.internals == LinearOctree::<u16, u16>::new().internals);
+        }
+    }
+
+    #[test]
+    fn test_linear_octree_from_sorted_matches_insertion() {
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+        use nalgebra::Vector3;
+
+        let mut rng = SmallRng::from_seed([19; 16]);
+        for round in 0..20 {
+            let bound = if round % 2 == 0 { 4 } else { 1 << 5 };
+            // The last rounds have enough items to be split between threads by `par_from_unsorted`.
+            let len = if round < 16 { rng.gen_range(0, 300) } else { 20000 };
+            let items: Vec<(u16, usize)> = (0..len)
+                .map(|i| {
+                    let v = Vector3::new(rng.gen_range(0, bound), rng.gen_range(0, bound), rng.gen_range(0, bound));
+                    (u16::encode(v), i)
+                })
+                .collect();
+            let mut expected = LinearOctree::new();
+            expected.extend(items.iter().copied());
+            let mut sorted = items.clone();
+            sorted.sort_by_key(|&(m, _)| m);
+            for tree in [
+                LinearOctree::from_sorted(sorted),
+                LinearOctree::from_unsorted(items.clone()),
+                LinearOctree::par_from_unsorted(items),
+            ] {
+                assert_eq!(tree.leaves, expected.leaves);
+                assert_eq!(tree.internals, expected.internals);
+            }
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_linear_octree_par_collect_fold() {
+        for &n in &[0u16, 1, 2, 1000] {
+            let mut tree = LinearOctree::<u16, u16>::new();
+            tree.extend((0..n).map(|i| (i.wrapping_mul(40503) & u16::used_bits(), i)));
+            assert_eq!(tree.par_collect_fold(&Count), tree.collect_fold(&Count));
+        }
+    }
+
+    #[test]
+    fn test_linear_octree_spatial_index_conformance() {
+        use rand::Rng;
+        use nalgebra::Vector3;
+
+        // The coordinates are small so that many of the keys get replaced.
+        crate::index::conformance::check(LinearOctree::<usize, u16>::new(), |rng| {
+            u16::encode(Vector3::new(rng.gen_range(0, 12), rng.gen_range(0, 12), rng.gen_range(0, 12)))
+        });
+    }
+}
@@ -1,15 +1,33 @@
 //! Octree types and algorithms.
 
+mod arena;
+mod build;
+mod fold;
+mod knn;
 mod linear;
+mod multi;
 mod pointer;
-
-pub use self::linear::LinearOctree;
-pub use self::pointer::PointerOctree;
-
-use crate::morton::*;
-use nalgebra::Vector3;
+mod radius;
+
+pub use self::arena::{ArenaEntry, ArenaOccupiedEntry, ArenaOctree, ArenaVacantEntry};
+pub use self::fold::{FoldOctree, FoldOctreeIter, FoldRefMut};
+pub use self::knn::{Distance, Euclidean, SquaredEuclidean, Toroidal};
+pub use self::linear::{LinearEntry, LinearOccupiedEntry, LinearOctree, LinearVacantEntry};
+pub use self::multi::{Bucket, PointerMultiOctree};
+pub use self::pointer::{PointerEntry, PointerOccupiedEntry, PointerOctree, PointerVacantEntry};
+pub use self::radius::Ball;
+
+use crate::morton::{Morton, MortonWrapper};
+use crate::morton2::Morton2;
+use nalgebra::{Vector2, Vector3};
 use num_traits::{Float, FromPrimitive, ToPrimitive};
 
+/// The number of levels at the top of an octree whose children are folded in parallel by `par_collect_fold`.
+///
+/// Below these levels, each of the up to `8^PARALLEL_FOLD_LEVELS` subtrees is folded sequentially into its own map.
+#[cfg(feature = "rayon")]
+const PARALLEL_FOLD_LEVELS: usize = 2;
+
 /// Implement this trait to perform a tree fold across the octree.
 ///
 /// This will convert leaf nodes into the internal `Sum` type and then propogate them up to parent regions by
@@ -19,9 +37,10 @@ pub trait Folder<Item, M> {
     type Sum;
 
     /// `gather` converts a leaf node into the internal `Sum` type.
-    fn gather<'a>(&self, morton: M, item: &'a Item) -> Self::Sum;
+    fn gather(&self, morton: M, item: &Item) -> Self::Sum;
 
-    /// `fold` is allowed to assume the `it` gives at least one item and no more than 8 items.
+    /// `fold` is allowed to assume the `it` gives at least one item and no more than 8 items
+    /// (4 items for quadtrees).
     fn fold<I>(&self, it: I) -> Self::Sum
         where
             I: Iterator<Item = Self::Sum>;
@@ -33,7 +52,7 @@ impl<Item, M, F> Folder<Item, M> for &F
 {
     type Sum = F::Sum;
 
-    fn gather<'a>(&self, morton: M, item: &'a Item) -> Self::Sum {
+    fn gather(&self, morton: M, item: &Item) -> Self::Sum {
         (*self).gather(morton, item)
     }
 
@@ -49,11 +68,11 @@ macro_rules! tuple_folder {
     ({$($id: ident),* $(,)?}, {$($sm: ident),* $(,)?}, {$($acc: ident),* $(,)?}, {$($item: ident),* $(,)?}) => {
         #[allow(non_snake_case)]
         impl <Item, M, $($id:),*> Folder<Item, M> for ($($id),*)
-            where M: Morton, $($id: Folder<Item, M>,)*
+            where M: Copy, $($id: Folder<Item, M>,)*
         {
             type Sum = ($($id::Sum),*);
 
-            fn gather<'a>(&self, morton: M, item: &'a Item) -> Self::Sum {
+            fn gather(&self, morton: M, item: &Item) -> Self::Sum {
                 let ($(ref $id),*) = *self;
                 ($($id.gather(morton, item)),*)
             }
@@ -126,7 +145,7 @@ pub struct NullFolder;
 impl<Item, M> Folder<Item, M> for NullFolder {
     type Sum = ();
 
-    fn gather<'a>(&self, _: M, _: &'a Item) -> Self::Sum {}
+    fn gather(&self, _: M, _: &Item) -> Self::Sum {}
 
     fn fold<I>(&self, _: I) -> Self::Sum
         where
@@ -167,4 +186,29 @@ impl LeveledRegion {
             Some(m)
         }
     }
+
+    /// This is the same as `discretize`, but produces a 2d morton code from a `Vector2` `point`.
+    ///
+    /// ```
+    /// let region = space::LeveledRegion(0);
+    /// let inside_bounds = nalgebra::Vector2::new(0.5, -0.5);
+    /// assert!(region.discretize2::<f32, u64>(inside_bounds).is_some());
+    /// let outside_bounds = nalgebra::Vector2::new(1.5, 0.5);
+    /// assert!(region.discretize2::<f32, u64>(outside_bounds).is_none());
+    /// ```
+    pub fn discretize2<S, M>(self, point: Vector2<S>) -> Option<M>
+        where
+            S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+            M: Morton2,
+    {
+        let bound = (S::one() + S::one()).powi(self.0);
+        if point.iter().any(|n| n.abs() > bound) {
+            None
+        } else {
+            // Convert the point into normalized space.
+            let MortonWrapper(m) =
+                (point.map(|n| (n + bound) / (S::one() + S::one()).powi(self.0 + 1))).into();
+            Some(m)
+        }
+    }
 }
@@ -0,0 +1,275 @@
+use crate::morton::Morton;
+use crate::octree::{Ball, Distance, PointerEntry, PointerOctree};
+
+use nalgebra::Vector3;
+use num_traits::{Float, FromPrimitive, ToPrimitive};
+use smallvec::SmallVec;
+
+/// The items that share a single morton in a `PointerMultiOctree`.
+///
+/// Most mortons hold a single item, so that item is stored inline.
+pub type Bucket<T> = SmallVec<[T; 1]>;
+
+/// An octree that uses pointers for internal nodes and can hold multiple items at the same morton.
+///
+/// A `PointerOctree` replaces the existing item when a point discretizes to the same morton as another point.
+/// This instead keeps every item in a `Bucket` at its morton, so coincident points are never lost. Iteration
+/// and queries give back every item, along with its morton.
+///
+/// ```
+/// use space::{PointerMultiOctree, Morton};
+/// use nalgebra::Vector3;
+///
+/// let mut tree = PointerMultiOctree::<&str, u64>::new();
+/// let m = Morton::encode(Vector3::new(1, 2, 3));
+/// tree.insert(m, "first");
+/// tree.insert(m, "second");
+/// assert_eq!(tree.get(m), &["first", "second"]);
+/// assert_eq!(tree.len(), 2);
+/// assert_eq!(tree.iter().count(), 2);
+/// ```
+pub struct PointerMultiOctree<T, M> {
+    tree: PointerOctree<Bucket<T>, M>,
+    count: usize,
+}
+
+impl<T, M> Default for PointerMultiOctree<T, M> {
+    /// Create an empty octree.
+    fn default() -> Self {
+        Self {
+            tree: PointerOctree::default(),
+            count: 0,
+        }
+    }
+}
+
+impl<T, M> PointerMultiOctree<T, M>
+where
+    M: Morton,
+{
+    /// Create an empty octree. Calls Default impl.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the octree of buckets underneath, which can be used to fold over the buckets.
+    pub fn buckets(&self) -> &PointerOctree<Bucket<T>, M> {
+        &self.tree
+    }
+
+    /// Gets every item at a morton. This is empty if there are no items at the morton.
+    pub fn get(&self, morton: M) -> &[T] {
+        self.tree.get(morton).map_or(&[], |bucket| &bucket[..])
+    }
+
+    /// Gets mutable references to every item at a morton. This is empty if there are no items at the morton.
+    pub fn get_mut(&mut self, morton: M) -> &mut [T] {
+        self.tree.get_mut(morton).map_or(&mut [], |bucket| &mut bucket[..])
+    }
+
+    /// Adds an item at a morton, keeping any items that are already there.
+    pub fn insert(&mut self, morton: M, item: T) {
+        self.tree.entry(morton).or_default().push(item);
+        self.count += 1;
+    }
+
+    /// Removes every item at a morton and gives them back. This is empty if there were no items at the morton.
+    pub fn remove(&mut self, morton: M) -> Bucket<T> {
+        let bucket = self.tree.remove(morton).unwrap_or_default();
+        self.count -= bucket.len();
+        bucket
+    }
+
+    /// Removes the first item at a morton for which `f` gives back `true`.
+    ///
+    /// ```
+    /// use space::PointerMultiOctree;
+    ///
+    /// let mut tree = PointerMultiOctree::<u32, u64>::new();
+    /// tree.extend(vec![(7, 1), (7, 2), (7, 3)]);
+    /// assert_eq!(tree.remove_item(7, |&n| n == 2), Some(2));
+    /// assert_eq!(tree.remove_item(7, |&n| n == 2), None);
+    /// assert_eq!(tree.get(7), &[1, 3]);
+    /// ```
+    pub fn remove_item<F>(&mut self, morton: M, mut f: F) -> Option<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        if let PointerEntry::Occupied(mut entry) = self.tree.entry(morton) {
+            let ix = entry.get().iter().position(&mut f)?;
+            let item = entry.get_mut().remove(ix);
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+            self.count -= 1;
+            Some(item)
+        } else {
+            None
+        }
+    }
+
+    /// Iterate over all items and their morton codes in z-order.
+    ///
+    /// Items at the same morton are given back in the order they were inserted.
+    pub fn iter(&self) -> impl Iterator<Item = (M, &T)> {
+        self.tree
+            .iter()
+            .flat_map(|(morton, bucket)| bucket.iter().map(move |item| (morton, item)))
+    }
+
+    /// Iterate over all items and their morton codes in z-order, with mutable access to the items.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (M, &mut T)> {
+        self.tree
+            .iter_mut()
+            .flat_map(|(morton, bucket)| bucket.iter_mut().map(move |item| (morton, item)))
+    }
+
+    /// Iterates over all items inside of the axis-aligned box which has the mortons `min` and `max` as opposite
+    /// corners (both inclusive). See `PointerOctree::query_aabb`.
+    pub fn query_aabb(&self, min: M, max: M) -> impl Iterator<Item = (M, &T)> {
+        self.tree
+            .query_aabb(min, max)
+            .flat_map(|(morton, bucket)| bucket.iter().map(move |item| (morton, item)))
+    }
+
+    /// Same as `query_aabb`, but gives back mutable references to the items.
+    pub fn query_aabb_mut(&mut self, min: M, max: M) -> impl Iterator<Item = (M, &mut T)> {
+        self.tree
+            .query_aabb_mut(min, max)
+            .flat_map(|(morton, bucket)| bucket.iter_mut().map(move |item| (morton, item)))
+    }
+
+    /// Finds the `k` items closest to `point` according to `distance`, sorted from closest to farthest.
+    /// See `PointerOctree::knn`.
+    ///
+    /// Items at the same morton have the same distance, so they are given back in the order they were inserted.
+    pub fn knn<S, D>(&self, point: Vector3<S>, k: usize, distance: &D) -> Vec<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        D: Distance<S>,
+    {
+        // Every bucket has at least one item, so the closest `k` buckets contain the closest `k` items.
+        self.tree
+            .knn(point, k, distance)
+            .into_iter()
+            .flat_map(|(morton, bucket, d)| bucket.iter().map(move |item| (morton, item, d)))
+            .take(k)
+            .collect()
+    }
+
+    /// Finds an item closest to `point` according to `distance`. See `knn`.
+    pub fn nearest<S, D>(&self, point: Vector3<S>, distance: &D) -> Option<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        D: Distance<S>,
+    {
+        self.tree
+            .nearest(point, distance)
+            .map(|(morton, bucket, d)| (morton, &bucket[0], d))
+    }
+
+    /// Finds every item within the Euclidean distance `ball.radius` of `ball.center`, along with its distance.
+    /// See `PointerOctree::within_radius`.
+    pub fn within_radius<S>(&self, ball: &Ball<S>) -> Vec<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        self.tree
+            .within_radius(ball)
+            .into_iter()
+            .flat_map(|(morton, bucket, d)| bucket.iter().map(move |item| (morton, item, d)))
+            .collect()
+    }
+
+    /// Counts the items that `within_radius` would find.
+    pub fn count_within_radius<S>(&self, ball: &Ball<S>) -> usize
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        self.tree
+            .within_radius(ball)
+            .into_iter()
+            .map(|(_, bucket, _)| bucket.len())
+            .sum()
+    }
+
+    /// Returns the number of items in the tree.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the number of distinct mortons that have items in the tree.
+    pub fn morton_count(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Checks if the octree is empty.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+impl<T, M> Extend<(M, T)> for PointerMultiOctree<T, M>
+where
+    M: Morton,
+{
+    fn extend<I>(&mut self, it: I)
+    where
+        I: IntoIterator<Item = (M, T)>,
+    {
+        for (m, item) in it {
+            self.insert(m, item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::Euclidean;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_multi_octree_keeps_coincident_items() {
+        let mut rng = SmallRng::from_seed([16; 16]);
+        // Very few mortons, so most of them hold many items.
+        let items: Vec<(u32, usize)> = (0..2000)
+            .map(|i| (u32::encode(Vector3::new(rng.gen_range(0, 4), rng.gen_range(0, 4), 0)), i))
+            .collect();
+        let mut tree = PointerMultiOctree::new();
+        tree.extend(items.iter().copied());
+        assert_eq!(tree.len(), items.len());
+        assert_eq!(tree.morton_count(), 16);
+
+        // Iteration is in z-order, with the items of a morton in insertion order.
+        let mut expected = items.clone();
+        expected.sort_by_key(|&(m, _)| m);
+        assert!(tree.iter().map(|(m, &i)| (m, i)).eq(expected.iter().copied()));
+        assert_eq!(tree.query_aabb(0, u32::used_bits()).count(), items.len());
+
+        let point = Vector3::new(0.0, 0.0, 0.0);
+        let nearest = tree.knn(point, 100, &Euclidean);
+        assert_eq!(nearest.len(), 100);
+        assert!(nearest.iter().all(|&(m, _, _)| m == 0));
+        assert_eq!(tree.count_within_radius(&crate::octree::Ball::new(point, 1.0)), items.len());
+
+        // Remove single items, then whole buckets.
+        for &(m, i) in items.iter().step_by(3) {
+            assert_eq!(tree.remove_item(m, |&n| n == i), Some(i));
+        }
+        let mut removed = items.len() / 3 + 1;
+        assert_eq!(tree.len(), items.len() - removed);
+        for (_, item) in tree.iter_mut() {
+            *item += 1;
+        }
+        let m = items[1].0;
+        let bucket = tree.remove(m);
+        removed += bucket.len();
+        assert!(!bucket.is_empty());
+        assert!(tree.get(m).is_empty());
+        assert_eq!(tree.len(), items.len() - removed);
+        assert_eq!(tree.iter().count(), tree.len());
+    }
+}
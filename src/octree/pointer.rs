@@ -1,15 +1,23 @@
-use crate::morton::{MortonRegion, MortonRegionCache, Morton};
-use crate::octree::Folder;
+use crate::index::{self, SpatialIndex, SpatialKey};
+use crate::morton::{Morton, MortonBox, MortonRegion, MortonRegionCache};
+use crate::octree::build;
+use crate::octree::knn::{self, Candidate};
+use crate::octree::radius;
+#[cfg(feature = "rayon")]
+use crate::octree::PARALLEL_FOLD_LEVELS;
+use crate::octree::{Ball, Distance, Folder};
+use crate::StorageAccess;
 
 use itertools::Itertools;
-
-use rand::{
-    distributions::{Distribution, Standard},
-    Rng,
-};
+use log::trace;
+use nalgebra::Vector3;
+use num_traits::{Float, FromPrimitive, ToPrimitive};
+use rand::distributions::{Distribution, Standard};
+use rand::Rng;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use std::default::Default;
-
-use log::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[derive(Copy, Clone, Debug, Default)]
 pub struct Oct<T> {
@@ -54,10 +62,85 @@ where
     /// let mut tree = PointerOctree::<String, u64>::new();
     ///
     /// ```
+    #[must_use]
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Builds an octree from items sorted by morton in a single pass from the leaves up, which is much faster than
+    /// inserting them one at a time. When a morton appears more than once, its last item is kept like with `insert`.
+    ///
+    /// Panics if the mortons are not sorted. See `from_unsorted` for items in any order.
+    ///
+    /// ```
+    /// use space::{PointerOctree, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let mut items: Vec<(u64, u64)> = (0..100).map(|x| (Morton::encode(Vector3::new(x, 0, x)), x)).collect();
+    /// items.sort();
+    /// let tree = PointerOctree::from_sorted(items);
+    /// assert_eq!(tree.len(), 100);
+    /// assert_eq!(tree.get(Morton::encode(Vector3::new(7, 0, 7))), Some(&7));
+    /// ```
+    pub fn from_sorted<I>(items: I) -> Self
+    where
+        I: IntoIterator<Item = (M, T)>,
+    {
+        let mut count = 0;
+        let tree = build::build_sorted(
+            items,
+            |morton, item| {
+                count += 1;
+                Internal::Leaf(item, morton)
+            },
+            |_, children| Internal::Node(Box::new(Oct::new(children.map(Option::unwrap_or_default)))),
+        );
+        Self {
+            tree: tree.unwrap_or_default(),
+            count,
+        }
+    }
+
+    /// Same as `from_sorted`, but the items can be in any order. They are sorted with a radix sort first, which
+    /// takes linear time.
+    #[must_use]
+    pub fn from_unsorted(items: Vec<(M, T)>) -> Self {
+        Self::from_sorted(build::radix_sort(items))
+    }
+
+    /// Same as `from_unsorted`, but the octants at the top of the tree are sorted and built on separate threads.
+    ///
+    /// ```
+    /// use space::{PointerOctree, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let items: Vec<(u64, u64)> = (0..10000).map(|x| (Morton::encode(Vector3::new(x, x % 7, x % 13)), x)).collect();
+    /// let tree = PointerOctree::par_from_unsorted(items);
+    /// assert_eq!(tree.len(), 10000);
+    /// ```
+    #[must_use]
+    pub fn par_from_unsorted(items: Vec<(M, T)>) -> Self
+    where
+        M: Send,
+        T: Send,
+    {
+        let count = AtomicUsize::new(0);
+        let tree = build::build_unsorted_parallel(
+            items,
+            &|morton, item| {
+                count.fetch_add(1, Ordering::Relaxed);
+                Internal::Leaf(item, morton)
+            },
+            &|_, children: [Option<Internal<T, M>>; 8]| {
+                Internal::Node(Box::new(Oct::new(children.map(Option::unwrap_or_default))))
+            },
+        );
+        Self {
+            tree: tree.unwrap_or_default(),
+            count: count.into_inner(),
+        }
+    }
+
     /// Fetches an immutable reference to the value of a specific coordinate in the octree
     /// ```
     /// use space::{PointerOctree, Morton};
@@ -74,10 +157,10 @@ where
             .fold_while((&self.tree, 0), |(node, old_ix), i| {
                 use itertools::FoldWhile::{Continue, Done};
                 match node {
-                    Internal::Node(box Oct { ref children }) => {
+                    Internal::Node(ref oct) => {
                         // The index into the array to access the next octree node
                         let subindex = morton.get_level(i);
-                        Continue((&children[subindex], i))
+                        Continue((&oct.children[subindex], i))
                     }
                     Internal::Leaf(_, _) | Internal::None  => Done((node, old_ix)),
                 }
@@ -95,7 +178,7 @@ where
                 // Otherwise we must split them, which we must do outside of this scope due to the borrow.
             }
             Internal::None => None,
-            _ => {
+            Internal::Node(_) => {
                 unreachable!(
                     "space::PointerOctree::get(): can only get None or Leaf in this code area"
                 );
@@ -110,20 +193,23 @@ where
     /// use nalgebra::Vector3;
     ///
     /// let mut tree = PointerOctree::<String, u64>::new();
+    /// let m = Morton::encode(Vector3::<u64>::new(1, 2, 3));
+    /// assert!(tree.get_mut(m).is_none());
     ///
-    /// let fetched_value = tree.get(Morton::encode(Vector3::<u64>::new(1, 2, 3)));
-    /// assert!(fetched_value.is_none());
+    /// tree.insert(m, "hello".to_owned());
+    /// tree.get_mut(m).unwrap().push_str(" world");
+    /// assert_eq!(tree.get(m).unwrap(), "hello world");
     /// ```
-    pub fn get_mut(&mut self, morton: M) -> Option<&T> {
+    pub fn get_mut(&mut self, morton: M) -> Option<&mut T> {
         // Traverse the tree down to the node we need to operate on.
         let (tree_part, _) = (0..M::dim_bits())
             .fold_while((&mut self.tree, 0), |(node, old_ix), i| {
                 use itertools::FoldWhile::{Continue, Done};
                 match node {
-                    Internal::Node(box Oct { ref mut children }) => {
+                    Internal::Node(ref mut oct) => {
                         // The index into the array to access the next octree node
                         let subindex = morton.get_level(i);
-                        Continue((&mut children[subindex], i))
+                        Continue((&mut oct.children[subindex], i))
                     }
                     Internal::Leaf(_, _) | Internal::None  => Done((node, old_ix)),
                 }
@@ -141,7 +227,7 @@ where
                 // Otherwise we must split them, which we must do outside of this scope due to the borrow.
             }
             Internal::None => None,
-            _ => {
+            Internal::Node(_) => {
                 unreachable!(
                     "space::PointerOctree::get_mut(): can only get None or Leaf in this code area"
                 );
@@ -161,73 +247,86 @@ where
     /// ```
     pub fn insert(&mut self, morton: M, item: T) {
         // Traverse the tree down to the node we need to operate on.
+        // The level is the depth of the node we stop on, which is where its children would be indexed from.
         let (tree_part, level) = (0..M::dim_bits())
-            .fold_while((&mut self.tree, 0), |(node, old_ix), i| {
+            .fold_while((&mut self.tree, 0), |(node, depth), i| {
                 use itertools::FoldWhile::{Continue, Done};
                 match node {
-                    Internal::Node(box Oct { ref mut children }) => {
+                    Internal::Node(ref mut oct) => {
                         // The index into the array to access the next octree node
                         let subindex = morton.get_level(i);
-                        Continue((&mut children[subindex], i))
+                        Continue((&mut oct.children[subindex], i + 1))
                     }
-                    Internal::Leaf(_, _) | Internal::None => Done((node, old_ix)),
+                    Internal::Leaf(_, _) | Internal::None => Done((node, depth)),
                 }
             })
             .into_inner();
 
         match tree_part {
-            Internal::Leaf(ref mut leaf_item, dest_morton) => {
+            Internal::Leaf(ref mut leaf_item, dest_morton) if morton == *dest_morton => {
                 // If they have the same code then replace it.
-                if morton == *dest_morton {
-                    *leaf_item = item;
-                    // Don't increase the count here because we replaced only.
-                    return;
-                }
-                // Otherwise we must split them, which we must do outside of this scope due to the borrow.
-            }
-            Internal::None => {
-                // Simply add a new leaf.
-                *tree_part = Internal::Leaf(item, morton);
-                self.count += 1;
-                return;
+                // Don't increase the count here because we replaced only.
+                *leaf_item = item;
             }
             _ => {
-                unreachable!(
-                    "space::PointerOctree::insert(): can only get None or Leaf in this code area"
-                );
+                tree_part.place(morton, level, item);
+                self.count += 1;
             }
         }
+    }
 
-        let mut dest_old = Internal::empty_node();
-        std::mem::swap(&mut dest_old, tree_part);
-
-        if let Internal::Leaf(dest_item, dest_morton) = dest_old {
-            // Set our initial reference to the default node in the dest.
-            let mut building_node = tree_part;
-            // Create deeper nodes till they differ at some level.
-            for i in level + 1..M::dim_bits() {
-                // We know for sure that the dest is a node.
-                if let Internal::Node(box Oct { ref mut children }) = building_node {
-                    if morton.get_level(i) == dest_morton.get_level(i) {
-                        children[morton.get_level(i)] = Internal::empty_node();
-                        building_node = &mut children[morton.get_level(i)];
-                    } else {
-                        // We reached the end where they differ, so put them both into the node.
-                        children[morton.get_level(i)] = Internal::Leaf(item, morton);
-                        children[dest_morton.get_level(i)] = Internal::Leaf(dest_item, dest_morton);
-                        self.count += 1;
-                        return;
-                    }
-                } else {
-                    unreachable!("space::Octree::insert(): cant get a non-node in this section");
+    /// Gets the entry for a morton in the octree for in-place manipulation.
+    ///
+    /// Finding the entry walks down the tree once, only looking at nodes with a single child twice. An occupied entry
+    /// keeps the highest node that would collapse if the leaf was removed, which is the leaf itself unless its parent
+    /// has only one other child and that child is a leaf, so `get`, `get_mut` and `into_mut` walk down from there
+    /// instead of from the root.
+    ///
+    /// ```
+    /// use space::{PointerOctree, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let mut tree = PointerOctree::<usize, u64>::new();
+    /// let m = Morton::encode(Vector3::new(1, 2, 3));
+    /// *tree.entry(m).or_insert(0) += 1;
+    /// tree.entry(m).and_modify(|n| *n += 1).or_insert(0);
+    /// assert_eq!(tree.get(m), Some(&2));
+    /// ```
+    pub fn entry(&mut self, morton: M) -> PointerEntry<'_, T, M> {
+        // Removing an occupied entry can collapse the nodes above it, so the entry keeps the highest node
+        // that could collapse rather than the leaf itself. Nodes that can't collapse are skipped over.
+        let mut top = &mut self.tree;
+        let mut level = 0;
+        loop {
+            match top.scan(morton, level) {
+                Scan::Keeps(steps) => {
+                    top = top.descend_mut(morton, level, steps + 1);
+                    level += steps + 1;
+                }
+                Scan::Occupied => {
+                    return PointerEntry::Occupied(PointerOccupiedEntry {
+                        top,
+                        level,
+                        morton,
+                        count: &mut self.count,
+                    });
+                }
+                Scan::Vacant(steps) => {
+                    return PointerEntry::Vacant(PointerVacantEntry {
+                        slot: top.descend_mut(morton, level, steps),
+                        level: level + steps,
+                        morton,
+                        count: &mut self.count,
+                    });
                 }
             }
-        } else {
-            unreachable!("space::Octree::insert(): cant get a non-leaf in this code area")
         }
     }
 
-    /// Fetches a mututable reference to the value of a specific coordinate in the octree
+    /// Removes the item at a morton from the octree and gives it back, if it was present.
+    ///
+    /// Nodes that are left with a single leaf or no leaves are collapsed, so the tree has the same shape as if
+    /// the removed item was never inserted.
     ///
     /// ```
     /// use space::{PointerOctree, Morton};
@@ -243,44 +342,44 @@ where
     /// 
     /// let fetched_value = tree.remove(m);
     /// assert!(fetched_value == Some("hello".to_owned()));
+    /// assert!(tree.is_empty());
     /// ```
     pub fn remove(&mut self, morton: M) -> Option<T> {
-        // Traverse the tree down to the node we need to operate on.
-        let (tree_part, _) = (0..M::dim_bits())
-            .fold_while((&mut self.tree, 0), |(node, old_ix), i| {
-                use itertools::FoldWhile::{Continue, Done};
-                match node {
-                    Internal::Node(box Oct { ref mut children }) => {
-                        // The index into the array to access the next octree node
-                        let subindex = morton.get_level(i);
-                        Continue((&mut children[subindex], i))
-                    }
-                    Internal::Leaf(_, _) | Internal::None => Done((node, old_ix)),
-                }
-            })
-            .into_inner();
-        
-        let mut leaf = Internal::None;
-        std::mem::swap(&mut leaf, tree_part);
-
-        match leaf {
-            Internal::Leaf(leaf_item, _) => {
-                Some(leaf_item)
-            }
-            Internal::None => None,
-            _ => {
-                unreachable!(
-                    "space::Octree::PointerOctree(): can only get None or Leaf in this code area"
-                );
-            }
+        let item = self.tree.remove(morton, 0);
+        if item.is_some() {
+            self.count -= 1;
         }
+        item
     }
 
     /// Iterate over all octree nodes and their morton codes.
-    pub fn iter(&self) -> impl Iterator<Item = (M, &T)> {
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (M, &'a T)>
+    where
+        T: 'a,
+    {
         self.tree.iter()
     }
 
+    /// Iterate over all octree nodes and their morton codes in z-order, with mutable access to the items.
+    ///
+    /// ```
+    /// use space::{PointerOctree, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let mut tree = PointerOctree::<u64, u64>::new();
+    /// tree.extend((0..10).map(|x| (Morton::encode(Vector3::new(x, 0, 0)), x)));
+    /// for (_, item) in tree.iter_mut() {
+    ///     *item *= 2;
+    /// }
+    /// assert!(tree.iter().all(|(m, &item)| item == 2 * m.decode().x));
+    /// ```
+    pub fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (M, &'a mut T)>
+    where
+        T: 'a,
+    {
+        self.tree.iter_mut()
+    }
+
     /// Iterate over all octree nodes, but stop at `depth` to randomly sample a point.
     ///
     /// If `depth` is set to `0`, only one point will be returned, which will either be the only point or
@@ -291,7 +390,7 @@ where
         &'a self,
         depth: usize,
         rng: &'a mut R,
-    ) -> impl Iterator<Item = (M, &T)> + 'a {
+    ) -> impl Iterator<Item = (M, &'a T)> + 'a {
         self.tree.iter_rand(depth, rng)
     }
 
@@ -368,6 +467,144 @@ where
         self.tree.iter_explore_simple(MortonRegion::base(), explore)
     }
 
+    /// Iterates over all leaves inside of the axis-aligned box which has the mortons `min` and `max` as opposite
+    /// corners (both inclusive). Leaves are given back in z-order.
+    ///
+    /// Only the regions of the tree that overlap the box are visited.
+    ///
+    /// ```
+    /// use space::{PointerOctree, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let mut tree = PointerOctree::<usize, u64>::new();
+    /// for (i, &x) in [0, 1, 2, 3].iter().enumerate() {
+    ///     tree.insert(Morton::encode(Vector3::new(x, 1, 1)), i);
+    /// }
+    /// let min = Morton::encode(Vector3::new(1, 0, 0));
+    /// let max = Morton::encode(Vector3::new(2, 1, 1));
+    /// let mut found: Vec<usize> = tree.query_aabb(min, max).map(|(_, &i)| i).collect();
+    /// found.sort();
+    /// assert_eq!(found, vec![1, 2]);
+    /// ```
+    pub fn query_aabb(&self, min: M, max: M) -> AabbIter<'_, T, M> {
+        AabbIter::new(&self.tree, MortonBox::new(min, max))
+    }
+
+    /// Same as `query_aabb`, but gives back mutable references to the items.
+    ///
+    /// ```
+    /// use space::{PointerOctree, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let mut tree = PointerOctree::<usize, u64>::new();
+    /// let inside = Morton::encode(Vector3::new(1, 1, 1));
+    /// let outside = Morton::encode(Vector3::new(3, 1, 1));
+    /// tree.insert(inside, 0);
+    /// tree.insert(outside, 0);
+    /// for (_, item) in tree.query_aabb_mut(0, Morton::encode(Vector3::new(2, 2, 2))) {
+    ///     *item += 1;
+    /// }
+    /// assert_eq!(tree.get(inside), Some(&1));
+    /// assert_eq!(tree.get(outside), Some(&0));
+    /// ```
+    pub fn query_aabb_mut(&mut self, min: M, max: M) -> AabbIterMut<'_, T, M> {
+        AabbIterMut::new(&mut self.tree, MortonBox::new(min, max))
+    }
+
+    /// Finds the `k` leaves closest to `point` according to `distance`, sorted from closest to farthest.
+    ///
+    /// Each leaf is given back along with its distance. The `point` is in the normalized space of the octree
+    /// (see `Distance`), and the search visits regions in order of their distance to the `point`.
+    ///
+    /// ```
+    /// use space::{Euclidean, MortonWrapper, PointerOctree};
+    /// use nalgebra::Vector3;
+    ///
+    /// let mut tree = PointerOctree::<&str, u64>::new();
+    /// for &(name, x) in &[("a", 0.1), ("b", 0.4), ("c", 0.9)] {
+    ///     let MortonWrapper(m) = Vector3::new(x, 0.5, 0.5).into();
+    ///     tree.insert(m, name);
+    /// }
+    /// let nearest: Vec<&str> = tree.knn(Vector3::new(0.3, 0.5, 0.5), 2, &Euclidean).into_iter().map(|(_, &n, _)| n).collect();
+    /// assert_eq!(nearest, vec!["b", "a"]);
+    /// ```
+    pub fn knn<S, D>(&self, point: Vector3<S>, k: usize, distance: &D) -> Vec<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        D: Distance<S>,
+    {
+        knn::search(point, k, distance, &self.tree, |region, node, candidates| {
+            node.candidates(region, candidates);
+        })
+    }
+
+    /// Finds the leaf closest to `point` according to `distance`. See `knn`.
+    pub fn nearest<S, D>(&self, point: Vector3<S>, distance: &D) -> Option<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        D: Distance<S>,
+    {
+        self.knn(point, 1, distance).pop()
+    }
+
+    /// Finds every leaf within the Euclidean distance `ball.radius` of `ball.center`, along with its distance.
+    ///
+    /// The leaves are not given back in any particular order. See `within_radius_sorted` to sort them by distance.
+    ///
+    /// ```
+    /// use space::{PointerOctree, LeveledRegion};
+    /// use nalgebra::Vector3;
+    ///
+    /// let region = LeveledRegion(0);
+    /// let mut tree = PointerOctree::<&str, u64>::new();
+    /// for &(name, x) in &[("a", -0.5), ("b", 0.0), ("c", 0.5)] {
+    ///     tree.insert(region.discretize(Vector3::new(x, 0.0, 0.0)).unwrap(), name);
+    /// }
+    /// let ball = region.ball(Vector3::new(0.3, 0.0, 0.0), 0.35);
+    /// let mut found: Vec<&str> = tree.within_radius(&ball).into_iter().map(|(_, &n, _)| n).collect();
+    /// found.sort();
+    /// assert_eq!(found, vec!["b", "c"]);
+    /// assert_eq!(tree.count_within_radius(&ball), 2);
+    /// ```
+    pub fn within_radius<S>(&self, ball: &Ball<S>) -> Vec<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut found = Vec::new();
+        radius::search(
+            ball,
+            &self.tree,
+            |region, node, candidates| node.candidates(region, candidates),
+            |morton, item, distance: S| found.push((morton, item, distance.sqrt())),
+        );
+        found
+    }
+
+    /// Same as `within_radius`, but the leaves are sorted from closest to farthest.
+    pub fn within_radius_sorted<S>(&self, ball: &Ball<S>) -> Vec<(M, &T, S)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut found = self.within_radius(ball);
+        found.sort_unstable_by(|a, b| a.2.partial_cmp(&b.2).unwrap_or(std::cmp::Ordering::Equal));
+        found
+    }
+
+    /// Counts the leaves that `within_radius` would find without collecting them.
+    pub fn count_within_radius<S>(&self, ball: &Ball<S>) -> usize
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut count = 0;
+        radius::search(
+            ball,
+            &self.tree,
+            |region, node, candidates| node.candidates(region, candidates),
+            |_, _: &T, _| count += 1,
+        );
+        count
+    }
+
     /// This gathers the tree into a linear hashed octree map. This map contains every internal and leaf node
     /// as the sum type that the `folder` produces.
     pub fn collect_fold<E, F>(&self, folder: &F) -> E
@@ -382,6 +619,53 @@ where
         map
     }
 
+    /// Same as `collect_fold`, but folds the subtrees under the top levels of the tree in parallel with `rayon`.
+    ///
+    /// Each subtree is folded into its own map, and the maps are merged at the end, so this gives back the same
+    /// map as `collect_fold`.
+    ///
+    /// ```
+    /// use space::{Folder, Morton, MortonRegionMap, PointerOctree};
+    ///
+    /// struct Count;
+    ///
+    /// impl<M> Folder<(), M> for Count {
+    ///     type Sum = usize;
+    ///
+    ///     fn gather<'a>(&self, _: M, _: &'a ()) -> usize {
+    ///         1
+    ///     }
+    ///
+    ///     fn fold<I>(&self, it: I) -> usize
+    ///     where
+    ///         I: Iterator<Item = usize>,
+    ///     {
+    ///         it.sum()
+    ///     }
+    /// }
+    ///
+    /// let tree = PointerOctree::from_unsorted((0..1000u64).map(|i| (i * 7919, ())).collect());
+    /// let map: MortonRegionMap<usize, u64> = tree.par_collect_fold(&Count);
+    /// assert_eq!(map, tree.collect_fold(&Count));
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_collect_fold<E, F>(&self, folder: &F) -> E
+    where
+        T: Sync,
+        M: Sync,
+        F: Folder<T, M> + Sync,
+        F::Sum: Clone + Send,
+        E: Extend<(MortonRegion<M>, F::Sum)> + IntoIterator<Item = (MortonRegion<M>, F::Sum)> + Default + Send,
+    {
+        let mut maps: Vec<E> = Vec::new();
+        self.tree
+            .par_collect_fold(MortonRegion::base(), folder, &mut maps);
+        maps.into_iter().fold(E::default(), |mut map, part| {
+            map.extend(part);
+            map
+        })
+    }
+
     /// Returns the number of leaves in the tree.
     pub fn len(&self) -> usize {
         self.count
@@ -407,11 +691,248 @@ where
     }
 }
 
+impl<T, M> StorageAccess<T, M> for PointerOctree<T, M>
+where
+    M: Morton,
+{
+    fn iter<'a>(&'a self) -> impl Iterator<Item = (M, &'a T)>
+    where
+        T: 'a,
+    {
+        PointerOctree::iter(self)
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (M, &'a mut T)>
+    where
+        T: 'a,
+    {
+        PointerOctree::iter_mut(self)
+    }
+
+    fn insert(&mut self, morton: M, item: T) -> Option<T> {
+        match self.entry(morton) {
+            PointerEntry::Occupied(mut entry) => Some(entry.insert(item)),
+            PointerEntry::Vacant(entry) => {
+                entry.insert(item);
+                None
+            }
+        }
+    }
+
+    fn get(&self, morton: M) -> Option<&T> {
+        PointerOctree::get(self, morton)
+    }
+
+    fn get_mut(&mut self, morton: M) -> Option<&mut T> {
+        PointerOctree::get_mut(self, morton)
+    }
+
+    fn remove(&mut self, morton: M) -> Option<T> {
+        PointerOctree::remove(self, morton)
+    }
+
+    fn len(&self) -> usize {
+        PointerOctree::len(self)
+    }
+}
+
+impl<T, M, S> SpatialIndex<T, S> for PointerOctree<T, M>
+where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    type Key = M;
+
+    fn insert(&mut self, morton: M, item: T) -> Option<T> {
+        StorageAccess::insert(self, morton, item)
+    }
+
+    fn remove(&mut self, morton: M) -> Option<T> {
+        PointerOctree::remove(self, morton)
+    }
+
+    fn knn<D>(&self, point: Vector3<S>, k: usize, distance: &D) -> Vec<(M, &T, S)>
+    where
+        D: Distance<S>,
+    {
+        PointerOctree::knn(self, point, k, distance)
+    }
+
+    fn within_radius(&self, ball: &Ball<S>) -> Vec<(M, &T, S)> {
+        PointerOctree::within_radius(self, ball)
+    }
+
+    fn query_aabb(&self, min: Vector3<S>, max: Vector3<S>) -> Vec<(M, &T)> {
+        // The voxels at the corners can stick out of the box, so the leaves near the edges are checked again.
+        let (min_morton, max_morton) = index::aabb_mortons(min, max);
+        PointerOctree::query_aabb(self, min_morton, max_morton)
+            .filter(|(morton, _)| index::aabb_contains(min, max, morton.position()))
+            .collect()
+    }
+}
+
+/// A view into a single morton in a `PointerOctree`, which may either be vacant or occupied.
+///
+/// Produced by `PointerOctree::entry`.
+pub enum PointerEntry<'a, T, M> {
+    /// The morton has an item in the octree.
+    Occupied(PointerOccupiedEntry<'a, T, M>),
+    /// The morton has no item in the octree.
+    Vacant(PointerVacantEntry<'a, T, M>),
+}
+
+impl<'a, T, M> PointerEntry<'a, T, M>
+where
+    M: Morton,
+{
+    /// Gets the morton of the entry.
+    pub fn key(&self) -> M {
+        match self {
+            PointerEntry::Occupied(entry) => entry.key(),
+            PointerEntry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Inserts `default` if the entry is vacant and gives back a mutable reference to the item.
+    pub fn or_insert(self, default: T) -> &'a mut T {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of `default` if the entry is vacant and gives back a mutable reference to the item.
+    pub fn or_insert_with<F>(self, default: F) -> &'a mut T
+    where
+        F: FnOnce() -> T,
+    {
+        match self {
+            PointerEntry::Occupied(entry) => entry.into_mut(),
+            PointerEntry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Inserts `T::default()` if the entry is vacant and gives back a mutable reference to the item.
+    pub fn or_default(self) -> &'a mut T
+    where
+        T: Default,
+    {
+        self.or_insert_with(T::default)
+    }
+
+    /// Calls `f` on the item if the entry is occupied.
+    #[must_use]
+    pub fn and_modify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut T),
+    {
+        if let PointerEntry::Occupied(ref mut entry) = self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+/// An occupied entry of a `PointerOctree`.
+pub struct PointerOccupiedEntry<'a, T, M> {
+    /// The highest node that could collapse if the leaf is removed.
+    top: &'a mut Internal<T, M>,
+    /// The depth of `top` in the tree.
+    level: usize,
+    morton: M,
+    count: &'a mut usize,
+}
+
+impl<'a, T, M> PointerOccupiedEntry<'a, T, M>
+where
+    M: Morton,
+{
+    /// Gets the morton of the entry.
+    pub fn key(&self) -> M {
+        self.morton
+    }
+
+    /// Gets a reference to the item.
+    pub fn get(&self) -> &T {
+        self.top.leaf(self.morton, self.level)
+    }
+
+    /// Gets a mutable reference to the item.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.top.leaf_mut(self.morton, self.level)
+    }
+
+    /// Converts the entry into a mutable reference to the item that lives as long as the octree borrow.
+    pub fn into_mut(self) -> &'a mut T {
+        self.top.leaf_mut(self.morton, self.level)
+    }
+
+    /// Replaces the item and gives back the old item.
+    pub fn insert(&mut self, item: T) -> T {
+        std::mem::replace(self.get_mut(), item)
+    }
+
+    /// Removes the item from the octree and gives it back.
+    pub fn remove(self) -> T {
+        self.remove_entry().1
+    }
+
+    /// Removes the item from the octree and gives back its morton and the item.
+    ///
+    /// This collapses nodes in the same way as `PointerOctree::remove`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the leaf of the entry is missing, which can't happen while the entry borrows the octree.
+    pub fn remove_entry(self) -> (M, T) {
+        let item = self
+            .top
+            .remove(self.morton, self.level)
+            .expect("space::PointerOccupiedEntry::remove_entry(): the entry must be occupied");
+        *self.count -= 1;
+        (self.morton, item)
+    }
+}
+
+/// A vacant entry of a `PointerOctree`.
+pub struct PointerVacantEntry<'a, T, M> {
+    /// The node where the leaf needs to be placed, which is either `None` or a leaf with a different morton.
+    slot: &'a mut Internal<T, M>,
+    /// The depth of `slot` in the tree.
+    level: usize,
+    morton: M,
+    count: &'a mut usize,
+}
+
+impl<'a, T, M> PointerVacantEntry<'a, T, M>
+where
+    M: Morton,
+{
+    /// Gets the morton of the entry.
+    pub fn key(&self) -> M {
+        self.morton
+    }
+
+    /// Inserts the item at the morton of the entry and gives back a mutable reference to it.
+    pub fn insert(self, item: T) -> &'a mut T {
+        *self.count += 1;
+        self.slot.place(self.morton, self.level, item)
+    }
+}
+
+/// The result of scanning down the tree for an entry.
+enum Scan {
+    /// A node at this many steps down won't collapse if the leaf is removed.
+    Keeps(usize),
+    /// The leaf exists and no node below the starting node is guaranteed not to collapse.
+    Occupied,
+    /// The leaf doesn't exist and it would be placed this many steps down.
+    Vacant(usize),
+}
+
 /// Internal node of a pointer octree.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 enum Internal<T, M> {
     Node(Box<Oct<Internal<T, M>>>),
     Leaf(T, M),
+    #[default]
     None,
 }
 
@@ -420,15 +941,31 @@ where
     M: Morton,
 {
     /// Iterate over all octree nodes and their morton codes.
-    fn iter(&self) -> impl Iterator<Item = (M, &T)> {
-        use either::Either::*;
+    fn iter<'a>(&'a self) -> impl Iterator<Item = (M, &'a T)>
+    where
+        T: 'a,
+    {
+        use either::Either::{Left, Right};
         match self {
-            Internal::Node(box ref n) => Left(InternalIter::new(vec![(&n.children, 0)])),
+            Internal::Node(ref n) => Left(InternalIter::new(vec![(&n.children, 0)])),
             Internal::Leaf(ref item, morton) => Right(std::iter::once((*morton, item))),
             Internal::None => Left(InternalIter::new(vec![])),
         }
     }
 
+    /// Iterate over all octree nodes and their morton codes, with mutable access to the items.
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (M, &'a mut T)>
+    where
+        T: 'a,
+    {
+        use either::Either::{Left, Right};
+        match self {
+            Internal::Node(ref mut n) => Left(InternalIterMut::new(vec![n.children.iter_mut()])),
+            Internal::Leaf(ref mut item, morton) => Right(std::iter::once((*morton, item))),
+            Internal::None => Left(InternalIterMut::new(vec![])),
+        }
+    }
+
     /// Iterate over all octree nodes, but stop at `depth` to randomly sample a point.
     ///
     /// If `depth` is set to `0`, only one point will be returned, which will either be the only point or
@@ -439,22 +976,22 @@ where
         &'a self,
         depth: usize,
         rng: &'a mut R,
-    ) -> impl Iterator<Item = (M, &T)> + 'a {
-        use either::Either::*;
+    ) -> impl Iterator<Item = (M, &'a T)> + 'a {
+        use either::Either::{Left, Right};
         match self {
-            Internal::Node(box Oct { ref children }) => {
+            Internal::Node(ref oct) => {
                 if depth == 0 {
                     let mut choice = rng.gen_range(0, 8);
                     // Iterate until we find the first non-empty spot.
                     // This technically results in not completely random behavior
                     // since an octant that comes after more empty octants is more likely to be chosen.
-                    while let Internal::None = children[choice] {
+                    while let Internal::None = oct.children[choice] {
                         choice += 1;
                         choice %= 8;
                     }
-                    Left({ InternalRandIter::new(vec![(children, choice, 1)], depth, rng) })
+                    Left(InternalRandIter::new(vec![(&oct.children, choice, 1)], depth, rng))
                 } else {
-                    Left({ InternalRandIter::new(vec![(children, 0, 1)], depth, rng) })
+                    Left(InternalRandIter::new(vec![(&oct.children, 0, 1)], depth, rng))
                 }
             }
             Internal::Leaf(ref item, morton) => Right(std::iter::once((*morton, item))),
@@ -465,16 +1002,16 @@ where
     /// Get a single random leaf sample from this node (cant be none).
     fn sample(&self, morton: M) -> (M, &T) {
         match self {
-            Internal::Node(box Oct { ref children }) => {
+            Internal::Node(ref oct) => {
                 let mut choice = morton.get_level(0);
                 // Iterate until we find the first non-empty spot.
                 // This technically results in not completely random behavior
                 // since an octant that comes after more empty octants is more likely to be chosen.
-                while let Internal::None = children[choice] {
+                while let Internal::None = oct.children[choice] {
                     choice += 1;
                     choice %= 8;
                 }
-                children[choice].sample(morton << 3)
+                oct.children[choice].sample(morton << 3)
             }
             Internal::Leaf(ref item, morton) => (*morton, item),
             Internal::None => unreachable!("can't sample a none node"),
@@ -518,11 +1055,11 @@ where
         E: Extend<(MortonRegion<M>, F::Sum)> + Default,
     {
         match self {
-            Internal::Node(box Oct { ref children }) => {
+            Internal::Node(ref oct) => {
                 if region.level < M::dim_bits() {
                     let sum = folder
                         .fold((0..8).filter_map(|i| {
-                            children[i].collect_fold(region.enter(i), folder, map)
+                            oct.children[i].collect_fold(region.enter(i), folder, map)
                         }));
                     map.extend(std::iter::once((region, sum.clone())));
                     Some(sum)
@@ -535,7 +1072,48 @@ where
                 map.extend(std::iter::once((region, sum.clone())));
                 Some(sum)
             }
-            _ => None,
+            Internal::None => None,
+        }
+    }
+
+    /// Adds a map for every subtree under the parallel levels of the tree and for every node in them to `maps`.
+    #[cfg(feature = "rayon")]
+    fn par_collect_fold<E, F>(&self, region: MortonRegion<M>, folder: &F, maps: &mut Vec<E>) -> Option<F::Sum>
+    where
+        T: Sync,
+        M: Sync,
+        F: Folder<T, M> + Sync,
+        F::Sum: Clone + Send,
+        E: Extend<(MortonRegion<M>, F::Sum)> + Default + Send,
+    {
+        match self {
+            Internal::Node(ref oct) if region.level < PARALLEL_FOLD_LEVELS => {
+                let children: Vec<_> = oct.children[..]
+                    .par_iter()
+                    .enumerate()
+                    .map(|(i, child)| {
+                        let mut maps = Vec::new();
+                        let sum = child.par_collect_fold(region.enter(i), folder, &mut maps);
+                        (sum, maps)
+                    })
+                    .collect();
+                let mut sums = Vec::with_capacity(8);
+                for (sum, mut child_maps) in children {
+                    sums.extend(sum);
+                    maps.append(&mut child_maps);
+                }
+                let sum = folder.fold(sums.into_iter());
+                let mut map = E::default();
+                map.extend(std::iter::once((region, sum.clone())));
+                maps.push(map);
+                Some(sum)
+            }
+            _ => {
+                let mut map = E::default();
+                let sum = self.collect_fold(region, folder, &mut map);
+                maps.push(map);
+                sum
+            }
         }
     }
 
@@ -554,23 +1132,23 @@ where
         Standard: Distribution<M>,
     {
         match self {
-            Internal::Node(box Oct { ref children }) => {
+            Internal::Node(ref oct) => {
                 if let Some(sum) = cache.get_mut(&region).cloned() {
                     return Some(sum);
                 }
                 if depth == 0 {
                     let morton = rng.gen();
                     match self {
-                        Internal::Node(box Oct { ref children }) => {
+                        Internal::Node(ref oct) => {
                             let mut choice = morton.get_level(0);
                             // Iterate until we find the first non-empty spot.
                             // This technically results in not completely random behavior
                             // since an octant that comes after more empty octants is more likely to be chosen.
-                            while let Internal::None = children[choice] {
+                            while let Internal::None = oct.children[choice] {
                                 choice += 1;
                                 choice %= 8;
                             }
-                            let (morton, item) = children[choice].sample(morton << 3);
+                            let (morton, item) = oct.children[choice].sample(morton << 3);
                             let sum = folder.gather(morton, item);
                             cache.insert(region, sum.clone());
                             Some(sum)
@@ -584,13 +1162,12 @@ where
                     }
                 } else {
                     let sum = folder.fold(
-                        children
+                        oct.children
                             .iter()
                             .enumerate()
-                            .map(|(ix, child)| {
+                            .filter_map(|(ix, child)| {
                                 child.fold_rand(region.enter(ix), depth - 1, folder, cache, rng)
-                            })
-                            .filter_map(|c| c),
+                            }),
                     );
                     cache.insert(region, sum.clone());
                     Some(sum)
@@ -604,23 +1181,170 @@ where
                 });
                 Some(sum)
             }
+            Internal::None => None,
+        }
+    }
+
+    /// Places a new leaf at this node, which is at depth `level` in the tree and is either `None` or a leaf with a
+    /// different morton. Nodes are created until the two leaves are in different octants.
+    fn place(&mut self, morton: M, level: usize, item: T) -> &mut T {
+        let mut node = self;
+        if let Internal::Leaf(dest_item, dest_morton) = std::mem::replace(node, Internal::None) {
+            // Create deeper nodes till they differ at some level.
+            for i in level..M::dim_bits() {
+                *node = Internal::empty_node();
+                if let Internal::Node(ref mut oct) = node {
+                    if morton.get_level(i) != dest_morton.get_level(i) {
+                        // We reached the end where they differ, so put the old leaf into the node.
+                        oct.children[dest_morton.get_level(i)] = Internal::Leaf(dest_item, dest_morton);
+                        node = &mut oct.children[morton.get_level(i)];
+                        break;
+                    }
+                    node = &mut oct.children[morton.get_level(i)];
+                } else {
+                    unreachable!("space::PointerOctree::place(): cant get a non-node in this section");
+                }
+            }
+        }
+        *node = Internal::Leaf(item, morton);
+        if let Internal::Leaf(ref mut item, _) = node {
+            item
+        } else {
+            unreachable!("space::PointerOctree::place(): cant get a non-leaf in this section")
+        }
+    }
+
+    /// Scans down from this node, which is at depth `level` in the tree, towards where `morton` would be.
+    fn scan(&self, morton: M, level: usize) -> Scan {
+        let mut node = self;
+        let mut steps = 0;
+        loop {
+            match node {
+                Internal::Node(ref oct) => {
+                    let ix = morton.get_level(level + steps);
+                    if Self::keeps_shape(&oct.children, ix) {
+                        return Scan::Keeps(steps);
+                    }
+                    node = &oct.children[ix];
+                    steps += 1;
+                }
+                Internal::Leaf(_, dest_morton) if *dest_morton == morton => return Scan::Occupied,
+                _ => return Scan::Vacant(steps),
+            }
+        }
+    }
+
+    /// Checks if removing a leaf from `children[ix]` can never cause the node with these `children` to collapse.
+    fn keeps_shape(children: &[Self; 8], ix: usize) -> bool {
+        let mut others = children
+            .iter()
+            .enumerate()
+            .filter(|&(i, child)| i != ix && !matches!(child, Internal::None));
+        match (others.next(), others.next()) {
+            (None, _) => false,
+            (Some((_, other)), None) => {
+                // A node can only collapse into a leaf, so one of the two children must remain a node.
+                matches!(other, Internal::Node(_)) || matches!(children[ix], Internal::Node(_))
+            }
+            (Some(_), Some(_)) => true,
+        }
+    }
+
+    /// Follows `morton` down `steps` nodes from this node, which is at depth `level` in the tree.
+    fn descend_mut(&mut self, morton: M, level: usize, steps: usize) -> &mut Self {
+        let mut node = self;
+        for i in level..level + steps {
+            match node {
+                Internal::Node(ref mut oct) => node = &mut oct.children[morton.get_level(i)],
+                _ => unreachable!("space::PointerOctree::descend_mut(): can only get a Node in this code area"),
+            }
+        }
+        node
+    }
+
+    /// Gets the item of the leaf with `morton` below this node, which is at depth `level` in the tree.
+    fn leaf(&self, morton: M, level: usize) -> &T {
+        match self {
+            Internal::Node(ref oct) => oct.children[morton.get_level(level)].leaf(morton, level + 1),
+            Internal::Leaf(ref item, _) => item,
+            Internal::None => unreachable!("space::PointerOctree::leaf(): the leaf must exist"),
+        }
+    }
+
+    /// Same as `leaf`, but gives back a mutable reference.
+    fn leaf_mut(&mut self, morton: M, level: usize) -> &mut T {
+        match self {
+            Internal::Node(ref mut oct) => {
+                oct.children[morton.get_level(level)].leaf_mut(morton, level + 1)
+            }
+            Internal::Leaf(ref mut item, _) => item,
+            Internal::None => unreachable!("space::PointerOctree::leaf_mut(): the leaf must exist"),
+        }
+    }
+
+    /// Removes the leaf with `morton` from this node, which is at depth `level` in the tree.
+    fn remove(&mut self, morton: M, level: usize) -> Option<T> {
+        match self {
+            Internal::Node(ref mut oct) => {
+                let item = oct.children[morton.get_level(level)].remove(morton, level + 1)?;
+                self.collapse();
+                Some(item)
+            }
+            Internal::Leaf(_, dest_morton) if *dest_morton == morton => {
+                if let Internal::Leaf(item, _) = std::mem::replace(self, Internal::None) {
+                    Some(item)
+                } else {
+                    unreachable!("space::PointerOctree::remove(): can only get a Leaf in this code area")
+                }
+            }
             _ => None,
         }
     }
 
+    /// Replaces a node that has no leaves with `None` and a node that has only a single leaf child with the leaf.
+    ///
+    /// As long as the children of the node are already collapsed, this keeps the tree in its minimal shape.
+    fn collapse(&mut self) {
+        let replacement = match self {
+            Internal::Node(ref mut oct) => {
+                let mut occupied = oct.children.iter_mut().filter(|child| !matches!(child, Internal::None));
+                match (occupied.next(), occupied.next()) {
+                    (None, _) => Internal::None,
+                    (Some(child), None) if matches!(child, Internal::Leaf(_, _)) => {
+                        std::mem::replace(child, Internal::None)
+                    }
+                    _ => return,
+                }
+            }
+            _ => return,
+        };
+        *self = replacement;
+    }
+
+    /// Adds every non-empty child of this node to `candidates` for a search, or this node itself if it is a leaf.
+    fn candidates<'a>(&'a self, region: MortonRegion<M>, candidates: &mut Vec<Candidate<M, &'a Self, &'a T>>) {
+        match self {
+            Internal::Node(ref oct) => {
+                for (ix, child) in oct.children.iter().enumerate() {
+                    match child {
+                        Internal::Node(_) => candidates.push(Candidate::Region(region.enter(ix), child)),
+                        Internal::Leaf(ref item, morton) => candidates.push(Candidate::Leaf(*morton, item)),
+                        Internal::None => {}
+                    }
+                }
+            }
+            Internal::Leaf(ref item, morton) => candidates.push(Candidate::Leaf(*morton, item)),
+            Internal::None => {}
+        }
+    }
+
     /// Gives back a `Node` with 8 empty `None` nodes.
     #[inline]
     pub fn empty_node() -> Self {
-        use self::Internal::*;
-        Node(box Oct::new([
+        use self::Internal::{Node, None};
+        Node(Box::new(Oct::new([
             None, None, None, None, None, None, None, None,
-        ]))
-    }
-}
-
-impl<T, M> Default for Internal<T, M> {
-    fn default() -> Self {
-        Internal::None
+        ])))
     }
 }
 
@@ -650,11 +1374,48 @@ where
                 self.nodes.push((node, ix + 1));
             }
             match node[ix] {
-                Internal::Node(box Oct { ref children }) => self.nodes.push((children, 0)),
+                Internal::Node(ref oct) => self.nodes.push((&oct.children, 0)),
                 Internal::Leaf(ref item, morton) => {
                     return Some((morton, item));
                 }
-                _ => {}
+                Internal::None => {}
+            }
+        }
+        None
+    }
+}
+
+struct InternalIterMut<'a, T, M> {
+    nodes: Vec<std::slice::IterMut<'a, Internal<T, M>>>,
+}
+
+impl<'a, T, M> InternalIterMut<'a, T, M>
+where
+    M: Morton,
+{
+    fn new(nodes: Vec<std::slice::IterMut<'a, Internal<T, M>>>) -> Self {
+        InternalIterMut { nodes }
+    }
+}
+
+impl<'a, T, M> Iterator for InternalIterMut<'a, T, M>
+where
+    M: Morton,
+{
+    type Item = (M, &'a mut T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(children) = self.nodes.last_mut() {
+            match children.next() {
+                Some(Internal::Node(ref mut oct)) => self.nodes.push(oct.children.iter_mut()),
+                Some(Internal::Leaf(ref mut item, morton)) => {
+                    return Some((*morton, item));
+                }
+                Some(Internal::None) => {}
+                None => {
+                    self.nodes.pop();
+                }
             }
         }
         None
@@ -693,14 +1454,14 @@ where
                 self.nodes.push((node, ix + 1, level));
             }
             match node[ix] {
-                Internal::Node(box Oct { ref children }) => self.nodes.push((
-                    children,
+                Internal::Node(ref oct) => self.nodes.push((
+                    &oct.children,
                     if level >= self.depth {
                         let mut choice = self.rng.gen_range(0, 8);
                         // Iterate until we find the first non-empty spot.
                         // This technically results in not completely random behavior
                         // since an octant that comes after more empty octants is more likely to be chosen.
-                        while let Internal::None = children[choice] {
+                        while let Internal::None = oct.children[choice] {
                             choice += 1;
                             choice %= 8;
                         }
@@ -713,7 +1474,7 @@ where
                 Internal::Leaf(ref item, morton) => {
                     return Some((morton, item));
                 }
-                _ => {}
+                Internal::None => {}
             }
         }
         None
@@ -762,7 +1523,7 @@ where
     }
 }
 
-impl<'a, T, M, E, F, R> Iterator for FoldIter<'a, T, M, E, F, R>
+impl<T, M, E, F, R> Iterator for FoldIter<'_, T, M, E, F, R>
 where
     M: Morton,
     E: FnMut(MortonRegion<M>) -> bool,
@@ -779,10 +1540,10 @@ where
             // If we shouldn't go further into the region, then its time to do a random sample starting here.
             if (self.explore)(region) {
                 match node {
-                    Internal::Node(box Oct { ref children }) => {
+                    Internal::Node(ref oct) => {
                         trace!("traversing deeper due to node at level {}", region.level);
                         // Traverse deeper (we already checked if we didn't need to go further).
-                        for (ix, child) in children.iter().enumerate() {
+                        for (ix, child) in oct.children.iter().enumerate() {
                             self.nodes.push((child, region.enter(ix)));
                         }
                     }
@@ -796,7 +1557,7 @@ where
 
                         return Some((region, item));
                     }
-                    _ => {}
+                    Internal::None => {}
                 }
             } else {
                 trace!("chose not to go further");
@@ -811,9 +1572,8 @@ where
                         &mut self.cache,
                         &mut self.rng,
                     )
-                        .map(|item| {
+                        .inspect(|item| {
                             self.cache.insert(region, item.clone());
-                            item
                         })
                 }) {
                     return Some((region, r));
@@ -824,14 +1584,14 @@ where
     }
 }
 
-impl<'a, T, M, E, F, R> Into<MortonRegionCache<F::Sum, M>> for FoldIter<'a, T, M, E, F, R>
+impl<T, M, E, F, R> From<FoldIter<'_, T, M, E, F, R>> for MortonRegionCache<F::Sum, M>
 where
     F: Folder<T, M>,
     R: Rng,
     M: Morton,
 {
-    fn into(self) -> MortonRegionCache<F::Sum, M> {
-        self.cache
+    fn from(iter: FoldIter<'_, T, M, E, F, R>) -> Self {
+        iter.cache
     }
 }
 
@@ -866,12 +1626,12 @@ where
     fn next(&mut self) -> Option<Self::Item> {
         while let Some((node, region)) = self.nodes.pop() {
             match node {
-                Internal::Node(box Oct { ref children }) => {
+                Internal::Node(ref oct) => {
                     // If we shouldn't go further into the region, then take the first thing from the iterator.
                     if (self.explore)(region) {
                         trace!("traversing deeper due to node at level {}", region.level);
                         // Traverse deeper (we already checked if we didn't need to go further).
-                        for (ix, child) in children.iter().enumerate() {
+                        for (ix, child) in oct.children.iter().enumerate() {
                             self.nodes.push((child, region.enter(ix)));
                         }
                     } else {
@@ -889,7 +1649,131 @@ where
 
                     return Some((region, *morton, item));
                 }
-                _ => {}
+                Internal::None => {}
+            }
+        }
+        None
+    }
+}
+
+/// The stack of nodes that still need to be visited by an AABB query, along with their region and whether
+/// or not the region is completely inside the box.
+type AabbStack<N, M> = Vec<(N, MortonRegion<M>, bool)>;
+
+/// An iterator over the leaves of a `PointerOctree` inside of an axis-aligned box.
+///
+/// Produced by `PointerOctree::query_aabb`.
+pub struct AabbIter<'a, T, M>
+where
+    M: Morton,
+{
+    nodes: AabbStack<&'a Internal<T, M>, M>,
+    bounds: MortonBox<M>,
+    min: Vector3<M>,
+    max: Vector3<M>,
+}
+
+impl<'a, T, M> AabbIter<'a, T, M>
+where
+    M: Morton,
+{
+    fn new(node: &'a Internal<T, M>, bounds: MortonBox<M>) -> Self {
+        let region = MortonRegion::base();
+        AabbIter {
+            nodes: vec![(node, region, MortonBox::region_inside(region, bounds.min.decode(), bounds.max.decode()))],
+            bounds,
+            min: bounds.min.decode(),
+            max: bounds.max.decode(),
+        }
+    }
+}
+
+impl<'a, T, M> Iterator for AabbIter<'a, T, M>
+where
+    M: Morton,
+{
+    type Item = (M, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, region, inside)) = self.nodes.pop() {
+            match node {
+                Internal::Node(ref oct) => {
+                    // Push the children in reverse so that they come back out in z-order.
+                    for (ix, child) in oct.children.iter().enumerate().rev() {
+                        let child_region = region.enter(ix);
+                        if inside {
+                            self.nodes.push((child, child_region, true));
+                        } else if MortonBox::region_overlaps(child_region, self.min, self.max) {
+                            let child_inside = MortonBox::region_inside(child_region, self.min, self.max);
+                            self.nodes.push((child, child_region, child_inside));
+                        }
+                    }
+                }
+                Internal::Leaf(ref item, morton) if inside || self.bounds.contains(*morton) => {
+                    return Some((*morton, item));
+                }
+                Internal::Leaf(..) | Internal::None => {}
+            }
+        }
+        None
+    }
+}
+
+/// An iterator over mutable references to the leaves of a `PointerOctree` inside of an axis-aligned box.
+///
+/// Produced by `PointerOctree::query_aabb_mut`.
+pub struct AabbIterMut<'a, T, M>
+where
+    M: Morton,
+{
+    nodes: AabbStack<&'a mut Internal<T, M>, M>,
+    bounds: MortonBox<M>,
+    min: Vector3<M>,
+    max: Vector3<M>,
+}
+
+impl<'a, T, M> AabbIterMut<'a, T, M>
+where
+    M: Morton,
+{
+    fn new(node: &'a mut Internal<T, M>, bounds: MortonBox<M>) -> Self {
+        let region = MortonRegion::base();
+        AabbIterMut {
+            nodes: vec![(node, region, MortonBox::region_inside(region, bounds.min.decode(), bounds.max.decode()))],
+            bounds,
+            min: bounds.min.decode(),
+            max: bounds.max.decode(),
+        }
+    }
+}
+
+impl<'a, T, M> Iterator for AabbIterMut<'a, T, M>
+where
+    M: Morton,
+{
+    type Item = (M, &'a mut T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, region, inside)) = self.nodes.pop() {
+            match node {
+                Internal::Node(ref mut oct) => {
+                    // Push the children in reverse so that they come back out in z-order.
+                    for (ix, child) in oct.children.iter_mut().enumerate().rev() {
+                        let child_region = region.enter(ix);
+                        if inside {
+                            self.nodes.push((child, child_region, true));
+                        } else if MortonBox::region_overlaps(child_region, self.min, self.max) {
+                            let child_inside = MortonBox::region_inside(child_region, self.min, self.max);
+                            self.nodes.push((child, child_region, child_inside));
+                        }
+                    }
+                }
+                Internal::Leaf(ref mut item, morton) if inside || self.bounds.contains(*morton) => {
+                    return Some((*morton, item));
+                }
+                Internal::Leaf(..) | Internal::None => {}
             }
         }
         None
@@ -925,4 +1809,210 @@ mod tests {
 
         assert_eq!(octree.iter().count(), 5000);
     }
+
+    #[test]
+    fn test_octree_insert_rand_u32() {
+        let mut rng = SmallRng::from_seed([2; 16]);
+        let space = crate::octree::LeveledRegion(0);
+        let mortons: Vec<u32> = (0..1000)
+            .map(|_| {
+                let v = Vector3::<f64>::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+                space.discretize(v).unwrap()
+            })
+            .collect();
+
+        let mut octree = PointerOctree::<_, u32>::new();
+        octree.extend(mortons.iter().map(|&m| (m, m)));
+
+        let mut unique = mortons.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(octree.len(), unique.len());
+        assert!(octree.iter().all(|(m, &item)| m == item));
+        assert!(mortons.iter().all(|&m| octree.get(m) == Some(&m)));
+    }
+
+    #[test]
+    fn test_octree_insert_next_to_root_leaf() {
+        // These differ at the top level, so the leaf at the root has to be split by the first level of the morton.
+        let a = u64::encode(Vector3::new(0, 0, 0));
+        let b = u64::encode(Vector3::new(1 << (u64::dim_bits() - 1), 0, 0));
+        let mut octree = PointerOctree::<_, u64>::new();
+        octree.insert(a, 'a');
+        octree.insert(b, 'b');
+        assert_eq!(octree.len(), 2);
+        assert_eq!(octree.get(a), Some(&'a'));
+        assert_eq!(octree.get(b), Some(&'b'));
+        assert_eq!(octree.iter().collect::<Vec<_>>(), vec![(a, &'a'), (b, &'b')]);
+    }
+
+    #[test]
+    fn test_octree_query_aabb_brute_force() {
+        let mut rng = SmallRng::from_seed([6; 16]);
+        let mut octree = PointerOctree::<_, u32>::new();
+        // Keep the points dense enough in a small cube that boxes contain some of them.
+        octree.extend((0..3000).map(|i| {
+            let v = Vector3::new(rng.gen_range(0, 32), rng.gen_range(0, 32), rng.gen_range(0, 32));
+            (u32::encode(v), i)
+        }));
+        for _ in 0..200 {
+            let a = u32::encode(Vector3::new(rng.gen_range(0, 40), rng.gen_range(0, 40), rng.gen_range(0, 40)));
+            let b = u32::encode(Vector3::new(rng.gen_range(0, 40), rng.gen_range(0, 40), rng.gen_range(0, 40)));
+            let bounds = MortonBox::new(a, b);
+            let expected: Vec<(u32, usize)> = octree
+                .iter()
+                .filter(|&(m, _)| bounds.contains(m))
+                .map(|(m, &i)| (m, i))
+                .collect();
+            let found: Vec<(u32, usize)> = octree.query_aabb(a, b).map(|(m, &i)| (m, i)).collect();
+            // Both iterate in z-order.
+            assert_eq!(found, expected);
+            let found_mut: Vec<(u32, usize)> = octree.query_aabb_mut(b, a).map(|(m, &mut i)| (m, i)).collect();
+            assert_eq!(found_mut, expected);
+        }
+    }
+
+    #[test]
+    fn test_octree_remove_collapses() {
+        let mut rng = SmallRng::from_seed([11; 16]);
+        for round in 0..20 {
+            // Use a small space in some rounds so that leaves share deep nodes.
+            let bound = if round % 2 == 0 { 8 } else { 1 << 10 };
+            let mut mortons: Vec<u32> = (0..rng.gen_range(1, 300))
+                .map(|_| {
+                    let v = Vector3::new(rng.gen_range(0, bound), rng.gen_range(0, bound), rng.gen_range(0, bound));
+                    u32::encode(v)
+                })
+                .collect();
+            mortons.sort_unstable();
+            mortons.dedup();
+            rng.shuffle(&mut mortons);
+
+            let mut octree = PointerOctree::<_, u32>::new();
+            octree.extend(mortons.iter().map(|&m| (m, m)));
+            let split = rng.gen_range(0, mortons.len() + 1);
+            let (removed, kept) = mortons.split_at(split);
+            for &m in removed {
+                assert_eq!(octree.remove(m), Some(m));
+                assert_eq!(octree.remove(m), None);
+            }
+            // Removing mortons that were never inserted doesn't change anything.
+            assert_eq!(octree.remove(u32::used_bits()), None);
+
+            let mut expected = PointerOctree::<_, u32>::new();
+            expected.extend(kept.iter().map(|&m| (m, m)));
+            assert_eq!(octree.len(), kept.len());
+            assert_eq!(octree.is_empty(), kept.is_empty());
+            assert_eq!(format!("{:?}", octree.tree), format!("{:?}", expected.tree));
+        }
+    }
+
+    #[test]
+    fn test_octree_from_sorted_matches_insertion() {
+        let mut rng = SmallRng::from_seed([18; 16]);
+        for round in 0..20 {
+            // Use a small space in some rounds so that leaves share deep nodes and mortons repeat.
+            let bound = if round % 2 == 0 { 8 } else { 1 << 10 };
+            let len = if round < 16 { rng.gen_range(0, 300) } else { 20000 };
+            let items: Vec<(u32, usize)> = (0..len)
+                .map(|i| {
+                    let v = Vector3::new(rng.gen_range(0, bound), rng.gen_range(0, bound), rng.gen_range(0, bound));
+                    (u32::encode(v), i)
+                })
+                .collect();
+
+            let mut expected = PointerOctree::new();
+            expected.extend(items.iter().copied());
+            let mut sorted = items.clone();
+            sorted.sort_by_key(|&(m, _)| m);
+            for octree in [
+                PointerOctree::from_sorted(sorted),
+                PointerOctree::from_unsorted(items.clone()),
+                PointerOctree::par_from_unsorted(items),
+            ] {
+                assert_eq!(octree.len(), expected.len());
+                assert_eq!(format!("{:?}", octree.tree), format!("{:?}", expected.tree));
+            }
+        }
+    }
+
+    #[test]
+    fn test_octree_iter_mut() {
+        let mut rng = SmallRng::from_seed([12; 16]);
+        let mut mortons: Vec<u64> = (0..1000).map(|_| rng.gen::<u64>() & u64::used_bits()).collect();
+        mortons.sort_unstable();
+        mortons.dedup();
+
+        let mut octree = PointerOctree::<_, u64>::new();
+        octree.extend(mortons.iter().rev().map(|&m| (m, 0)));
+        // Mutable iteration is in z-order, just like `iter`.
+        assert!(octree.iter_mut().map(|(m, _)| m).eq(mortons.iter().copied()));
+        for (i, (_, item)) in octree.iter_mut().enumerate() {
+            *item = i;
+        }
+        for (i, &m) in mortons.iter().enumerate() {
+            assert_eq!(octree.get(m), Some(&i));
+            *octree.get_mut(m).unwrap() += 1;
+        }
+        assert!(octree.iter().enumerate().all(|(i, (_, &item))| item == i + 1));
+        assert_eq!(octree.get_mut(u64::used_bits() ^ mortons[0]), None);
+    }
+
+    #[test]
+    fn test_octree_entry() {
+        let mut rng = SmallRng::from_seed([13; 16]);
+        for round in 0..20 {
+            let bound = if round % 2 == 0 { 4 } else { 1 << 10 };
+            let mut octree = PointerOctree::<usize, u32>::new();
+            let mut expected = std::collections::BTreeMap::new();
+            for i in 0..500 {
+                let v = Vector3::new(rng.gen_range(0, bound), rng.gen_range(0, bound), rng.gen_range(0, bound));
+                let m = u32::encode(v);
+                match octree.entry(m) {
+                    PointerEntry::Occupied(mut entry) => {
+                        assert_eq!(entry.key(), m);
+                        assert_eq!(*entry.get(), expected[&m]);
+                        *entry.get_mut() += 1;
+                        *expected.get_mut(&m).unwrap() += 1;
+                        if rng.gen() {
+                            assert_eq!(entry.remove_entry(), (m, expected.remove(&m).unwrap()));
+                        } else {
+                            assert_eq!(*entry.into_mut(), expected[&m]);
+                        }
+                    }
+                    PointerEntry::Vacant(entry) => {
+                        assert_eq!(entry.key(), m);
+                        assert_eq!(*entry.insert(i), i);
+                        expected.insert(m, i);
+                    }
+                }
+                *octree.entry(m).and_modify(|n| *n += 1).or_insert(0) += 1;
+                *expected.entry(m).and_modify(|n| *n += 1).or_insert(0) += 1;
+                assert_eq!(octree.len(), expected.len());
+            }
+            assert!(octree.iter().map(|(m, &n)| (m, n)).eq(expected.iter().map(|(&m, &n)| (m, n))));
+
+            // Removing through entries keeps the tree in the same shape as a freshly built tree.
+            let mortons: Vec<u32> = expected.keys().copied().collect();
+            for &m in mortons.iter().step_by(2) {
+                if let PointerEntry::Occupied(entry) = octree.entry(m) {
+                    assert_eq!(entry.remove(), expected.remove(&m).unwrap());
+                } else {
+                    panic!("morton {} should be occupied", m);
+                }
+            }
+            let mut rebuilt = PointerOctree::<usize, u32>::new();
+            rebuilt.extend(expected.iter().map(|(&m, &n)| (m, n)));
+            assert_eq!(octree.len(), rebuilt.len());
+            assert_eq!(format!("{:?}", octree.tree), format!("{:?}", rebuilt.tree));
+        }
+    }
+
+    #[test]
+    fn test_octree_spatial_index_conformance() {
+        // The coordinates are small so that many of the keys get replaced.
+        crate::index::conformance::check(PointerOctree::<usize, u16>::new(), |rng| {
+            u16::encode(Vector3::new(rng.gen_range(0, 12), rng.gen_range(0, 12), rng.gen_range(0, 12)))
+        });
+    }
 }
@@ -0,0 +1,172 @@
+use crate::morton::{Morton, MortonRegion, MortonWrapper};
+use crate::octree::knn::{region_bounds, Candidate};
+use crate::octree::{Distance, LeveledRegion, SquaredEuclidean};
+
+use nalgebra::Vector3;
+use num_traits::{Float, FromPrimitive, ToPrimitive};
+
+/// A ball in the normalized space of an octree, where every axis is in `[0, 1)`, used for radius queries.
+///
+/// Use `LeveledRegion::ball` to create a ball from a center and radius in real space, or `Ball::from_morton`
+/// to create one around a morton with a radius in voxels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ball<S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// The center of the ball.
+    pub center: Vector3<S>,
+    /// The radius of the ball, which includes leaves at exactly this Euclidean distance from the center.
+    pub radius: S,
+}
+
+impl<S> Ball<S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    /// Creates a ball from a `center` and `radius` in normalized space.
+    pub fn new(center: Vector3<S>, radius: S) -> Self {
+        Self { center, radius }
+    }
+
+    /// Creates a ball around the center of the voxel of `morton`, with a `radius` measured in voxels.
+    ///
+    /// ```
+    /// use space::{Ball, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let morton = u64::encode(Vector3::new(4, 5, 6));
+    /// let ball = Ball::<f64>::from_morton(morton, 2.0);
+    /// assert!(ball.contains(u64::encode(Vector3::new(4, 5, 8))));
+    /// assert!(!ball.contains(u64::encode(Vector3::new(4, 7, 8))));
+    /// ```
+    pub fn from_morton<M>(morton: M, radius: S) -> Self
+    where
+        M: Morton,
+    {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+        let scale = (S::one() + S::one()).powi(-(M::dim_bits() as i32));
+        Self {
+            center: MortonWrapper(morton).into(),
+            radius: radius * scale,
+        }
+    }
+
+    /// Checks if the center of the voxel of `morton` is inside the ball.
+    pub fn contains<M>(&self, morton: M) -> bool
+    where
+        M: Morton,
+    {
+        SquaredEuclidean.distance(self.center, MortonWrapper(morton).into()) <= self.radius.powi(2)
+    }
+}
+
+impl LeveledRegion {
+    /// Creates a `Ball` for radius queries from a `center` and `radius` in the real space of this region.
+    ///
+    /// The center doesn't need to be inside of the region.
+    ///
+    /// ```
+    /// use space::{LeveledRegion, Morton};
+    /// use nalgebra::Vector3;
+    ///
+    /// let region = LeveledRegion(2);
+    /// let ball = region.ball(Vector3::new(1.0, 1.0, 1.0), 0.5);
+    /// assert!(ball.contains::<u64>(region.discretize(Vector3::new(1.0, 1.0, 1.4)).unwrap()));
+    /// assert!(!ball.contains::<u64>(region.discretize(Vector3::new(1.0, 1.0, 1.6)).unwrap()));
+    /// ```
+    pub fn ball<S>(self, center: Vector3<S>, radius: S) -> Ball<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let bound = (S::one() + S::one()).powi(self.0);
+        let scale = (S::one() + S::one()).powi(self.0 + 1);
+        Ball {
+            center: center.map(|n| (n + bound) / scale),
+            radius: radius / scale,
+        }
+    }
+}
+
+/// Visits every leaf inside of `ball` along with its squared distance to the center of the ball.
+///
+/// `expand` works the same way as it does for the nearest neighbor search. Regions which can't contain any
+/// leaves inside of the ball are skipped.
+pub(crate) fn search<S, M, N, L, F, V>(ball: &Ball<S>, root: N, mut expand: F, mut visit: V)
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton,
+    F: FnMut(MortonRegion<M>, N, &mut Vec<Candidate<M, N, L>>),
+    V: FnMut(M, L, S),
+{
+    let radius_squared = ball.radius.powi(2);
+    let mut nodes = vec![Candidate::Region(MortonRegion::base(), root)];
+    while let Some(candidate) = nodes.pop() {
+        match candidate {
+            Candidate::Region(region, node) => {
+                let (min, max) = region_bounds(region);
+                if SquaredEuclidean.box_distance(ball.center, min, max) <= radius_squared {
+                    expand(region, node, &mut nodes);
+                }
+            }
+            Candidate::Leaf(morton, item) => {
+                let distance = SquaredEuclidean.distance(ball.center, MortonWrapper(morton).into());
+                if distance <= radius_squared {
+                    visit(morton, item, distance);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::{LinearOctree, PointerOctree};
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_within_radius_brute_force() {
+        let mut rng = SmallRng::from_seed([8; 16]);
+        let region = LeveledRegion(3);
+        let mut points: Vec<u32> = (0..1000)
+            .map(|_| {
+                let v = Vector3::new(rng.gen_range(-8.0, 8.0), rng.gen_range(-8.0, 8.0), rng.gen_range(-8.0, 8.0));
+                region.discretize(v).unwrap()
+            })
+            .collect();
+        points.sort_unstable();
+        points.dedup();
+        let mut pointer = PointerOctree::new();
+        pointer.extend(points.iter().map(|&m| (m, m)));
+        let mut linear = LinearOctree::new();
+        linear.extend(points.iter().map(|&m| (m, m)));
+
+        for i in 0..100 {
+            let ball = if i % 2 == 0 {
+                let center = Vector3::new(rng.gen_range(-9.0, 9.0), rng.gen_range(-9.0, 9.0), rng.gen_range(-9.0, 9.0));
+                region.ball(center, rng.gen_range(0.0, 6.0))
+            } else {
+                Ball::from_morton(points[rng.gen_range(0, points.len())], rng.gen_range(0.0, 200.0))
+            };
+            let mut expected: Vec<u32> = points.iter().copied().filter(|&m| ball.contains(m)).collect();
+            expected.sort_unstable();
+
+            let mut found: Vec<u32> = pointer.within_radius(&ball).into_iter().map(|(m, _, _)| m).collect();
+            found.sort_unstable();
+            assert_eq!(found, expected);
+            let mut found: Vec<u32> = linear.within_radius(&ball).into_iter().map(|(m, _, _)| m).collect();
+            found.sort_unstable();
+            assert_eq!(found, expected);
+
+            for sorted in &[pointer.within_radius_sorted(&ball), linear.within_radius_sorted(&ball)] {
+                assert_eq!(sorted.len(), expected.len());
+                assert!(sorted.iter().all(|&(m, &item, _)| m == item));
+                assert!(sorted.windows(2).all(|w| w[0].2 <= w[1].2));
+            }
+            assert_eq!(pointer.count_within_radius(&ball), expected.len());
+            assert_eq!(linear.count_within_radius(&ball), expected.len());
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{
+    morton::{MortonMap, MortonWrapper},
+    morton2::{morton2_levels, Morton2, Morton2Region, Morton2RegionMap},
+    octree::Folder,
+};
+
+/// A linear hashed quadtree. This has constant time lookup for a given region or morton code.
+///
+/// ```
+/// use space::LinearQuadtree;
+/// use space::morton2::Morton2;
+/// use nalgebra::Vector2;
+///
+/// let mut tree = LinearQuadtree::<String, u64>::new();
+/// let coord = Vector2::<u64>::new(1, 2);
+///
+/// // Insert a value into the tree
+/// tree.insert(Morton2::encode(coord), "test1".to_string());
+///
+/// // Fetch a value at a specific coordinate
+/// assert_eq!("test1", tree.get(Morton2::encode(coord)).unwrap());
+///
+/// // Fetch a value that doesnt exist
+/// assert!(tree.get(Morton2::encode(Vector2::<u64>::new(4, 5))).is_none());
+/// ```
+#[derive(Clone)]
+pub struct LinearQuadtree<T, M> {
+    /// The leaves of the quadtree.
+    leaves: MortonMap<T, M>,
+    /// The each internal node either contains a `null` Morton or a non-null Morton which points to a leaf.
+    /// Nodes which are not explicity stated implicitly indicate that it must be traversed deeper.
+    internals: Morton2RegionMap<M, M>,
+}
+
+impl<T, M> Default for LinearQuadtree<T, M>
+where
+    M: Morton2,
+{
+    /// Create a default, empty linear quadtree.
+    fn default() -> Self {
+        let mut internals = Morton2RegionMap::default();
+        internals.insert(Morton2Region::default(), M::null());
+        Self {
+            leaves: MortonMap::<_, M>::default(),
+            internals,
+        }
+    }
+}
+
+impl<T, M> LinearQuadtree<T, M>
+where
+    M: Morton2,
+{
+    /// Create an empty quadtree. Calls Default impl.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get iterator to the underlying `MortonMap`.
+    pub fn iter(&self) -> impl Iterator<Item = (&MortonWrapper<M>, &T)> {
+        self.leaves.iter()
+    }
+
+    /// Get mutable iterator to the underlying `MortonMap`.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&MortonWrapper<M>, &mut T)> {
+        self.leaves.iter_mut()
+    }
+
+    /// Inserts the item into the quadtree.
+    ///
+    /// If another element occupied the exact same morton, it will be evicted and replaced.
+    pub fn insert(&mut self, morton: M, item: T) {
+        use std::collections::hash_map::Entry::{Occupied, Vacant};
+        // First we must insert the node into the leaves.
+        match self.leaves.entry(MortonWrapper(morton)) {
+            Occupied(mut o) => {
+                o.insert(item);
+            }
+            Vacant(v) => {
+                v.insert(item);
+
+                // Because it was vacant, we need to adjust the tree's internal nodes.
+                for mut region in morton2_levels(morton) {
+                    // Check if the region is in the map.
+                    if let Occupied(mut o) = self.internals.entry(region) {
+                        if o.get().is_null() {
+                            // It was null, so just replace the null with the leaf.
+                            *o.get_mut() = morton;
+                            return;
+                        }
+                        // It was not null, so it is a leaf that must be moved to its sub-region.
+                        // We also need to populate the other null nodes created by this operation.
+                        let leaf = o.remove_entry().1;
+                        // Keep making the tree deeper until both leaves differ.
+                        for level in region.level..M::dim_bits() {
+                            let leaf_level = leaf.get_level(level);
+                            let item_level = morton.get_level(level);
+                            if leaf_level == item_level {
+                                // They were the same so set every other region to null.
+                                for i in 0..4 {
+                                    if i != leaf_level {
+                                        self.internals.insert(region.enter(i), M::null());
+                                    }
+                                }
+                                region = region.enter(leaf_level);
+                            } else {
+                                // They were different, so set the other 2 regions null and make 2 leaves.
+                                for i in 0..4 {
+                                    if i == leaf_level {
+                                        self.internals.insert(region.enter(i), leaf);
+                                    } else if i == item_level {
+                                        self.internals.insert(region.enter(i), morton);
+                                    } else {
+                                        self.internals.insert(region.enter(i), M::null());
+                                    }
+                                }
+                                // Now we must return as we have added the leaves.
+                                return;
+                            }
+                        }
+                        unreachable!();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fetches an immutable reference to the value of a specific coordinate in the quadtree.
+    pub fn get(&self, morton: M) -> Option<&T> {
+        self.leaves.get(&MortonWrapper(morton))
+    }
+
+    /// Fetches a mutable reference to the value of a specific coordinate in the quadtree.
+    pub fn get_mut(&mut self, morton: M) -> Option<&mut T> {
+        self.leaves.get_mut(&MortonWrapper(morton))
+    }
+
+    /// This gathers the quadtree in a tree fold by gathering leaves with `gatherer` and folding with `folder`.
+    /// This has exactly `n` `gather` operations and approximately `4/3 * n` `fold` operations.
+    pub fn collect_fold<F>(&self, folder: &F) -> Morton2RegionMap<F::Sum, M>
+    where
+        F: Folder<T, M>,
+        F::Sum: Clone,
+    {
+        let mut map = Morton2RegionMap::default();
+        self.collect_fold_region(Morton2Region::base(), folder, &mut map);
+        map
+    }
+
+    /// Same as `collect_fold`, but adds things to a morton region map and gives back the region.
+    pub fn collect_fold_region<F>(
+        &self,
+        region: Morton2Region<M>,
+        folder: &F,
+        map: &mut Morton2RegionMap<F::Sum, M>,
+    ) -> Option<F::Sum>
+    where
+        F: Folder<T, M>,
+        F::Sum: Clone,
+    {
+        match self.internals.get(&region) {
+            Some(m) if !m.is_null() => {
+                // This is a leaf node.
+                let sum = folder.gather(*m, &self.leaves[&MortonWrapper(*m)]);
+                map.insert(region, sum.clone());
+                Some(sum)
+            }
+            None => {
+                // This needs to be traversed deeper.
+                let sum = folder.fold(
+                    (0..4).filter_map(|i| self.collect_fold_region(region.enter(i), folder, map)),
+                );
+                map.insert(region, sum.clone());
+                Some(sum)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<T, M> Extend<(M, T)> for LinearQuadtree<T, M>
+where
+    M: Morton2,
+{
+    fn extend<I>(&mut self, it: I)
+    where
+        I: IntoIterator<Item = (M, T)>,
+    {
+        for (morton, item) in it {
+            self.insert(morton, item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Count;
+
+    impl<M> Folder<u64, M> for Count {
+        type Sum = usize;
+
+        fn gather(&self, _: M, _: &u64) -> Self::Sum {
+            1
+        }
+
+        fn fold<I>(&self, it: I) -> Self::Sum
+        where
+            I: Iterator<Item = Self::Sum>,
+        {
+            it.sum()
+        }
+    }
+
+    #[test]
+    fn test_linear_quadtree_fold() {
+        let mut tree = LinearQuadtree::<u64, u64>::new();
+        let mortons = (0..1000u64).map(|i| Morton2::encode(nalgebra::Vector2::new(i * 7919, i * 104_729)));
+        tree.extend(mortons.map(|m| (m, m)));
+
+        assert!(tree.iter().all(|(m, &item)| m.0 == item));
+        let folds = tree.collect_fold(&Count);
+        assert_eq!(folds[&Morton2Region::base()], 1000);
+        let quadrants: usize = (0..4)
+            .filter_map(|i| folds.get(&Morton2Region::base().enter(i)))
+            .sum();
+        assert_eq!(quadrants, 1000);
+    }
+}
@@ -0,0 +1,9 @@
+//! Quadtree types, which are the 2d counterparts of the octree types.
+//!
+//! These are keyed by `Morton2` codes and use the same `Folder` trait as the octrees.
+
+mod linear;
+mod pointer;
+
+pub use self::linear::LinearQuadtree;
+pub use self::pointer::PointerQuadtree;
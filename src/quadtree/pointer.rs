@@ -0,0 +1,332 @@
+use crate::morton2::{Morton2, Morton2Region};
+use crate::octree::Folder;
+
+use itertools::Itertools;
+
+/// A quadtree that uses pointers for internal nodes.
+///
+/// ```
+/// use space::PointerQuadtree;
+/// use space::morton2::Morton2;
+/// use nalgebra::Vector2;
+///
+/// let mut tree = PointerQuadtree::<String, u64>::new();
+/// let m = Morton2::encode(Vector2::<u64>::new(1, 2));
+/// tree.insert(m, "test1".to_string());
+/// assert_eq!(tree.get(m).unwrap(), "test1");
+/// assert_eq!(tree.len(), 1);
+/// ```
+pub struct PointerQuadtree<T, M> {
+    tree: Internal<T, M>,
+    count: usize,
+}
+
+impl<T, M> Default for PointerQuadtree<T, M> {
+    /// Create an empty quadtree.
+    fn default() -> Self {
+        Self {
+            tree: Internal::default(),
+            count: 0,
+        }
+    }
+}
+
+impl<T, M> PointerQuadtree<T, M>
+where
+    M: Morton2,
+{
+    /// Create an empty quadtree. Calls Default impl.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fetches an immutable reference to the value of a specific coordinate in the quadtree.
+    pub fn get(&self, morton: M) -> Option<&T> {
+        // Traverse the tree down to the node we need to operate on.
+        let tree_part = (0..M::dim_bits())
+            .fold_while(&self.tree, |node, i| {
+                use itertools::FoldWhile::{Continue, Done};
+                match node {
+                    Internal::Node(ref children) => Continue(&children[morton.get_level(i)]),
+                    Internal::Leaf(_, _) | Internal::None => Done(node),
+                }
+            })
+            .into_inner();
+
+        match tree_part {
+            Internal::Leaf(ref leaf_item, dest_morton) if morton == *dest_morton => Some(leaf_item),
+            _ => None,
+        }
+    }
+
+    /// Fetches a mutable reference to the value of a specific coordinate in the quadtree.
+    pub fn get_mut(&mut self, morton: M) -> Option<&mut T> {
+        // Traverse the tree down to the node we need to operate on.
+        let tree_part = (0..M::dim_bits())
+            .fold_while(&mut self.tree, |node, i| {
+                use itertools::FoldWhile::{Continue, Done};
+                match node {
+                    Internal::Node(ref mut children) => Continue(&mut children[morton.get_level(i)]),
+                    Internal::Leaf(_, _) | Internal::None => Done(node),
+                }
+            })
+            .into_inner();
+
+        match tree_part {
+            Internal::Leaf(ref mut leaf_item, dest_morton) if morton == *dest_morton => Some(leaf_item),
+            _ => None,
+        }
+    }
+
+    /// Insert an item with a point and replace the existing item if they would both occupy the same space.
+    pub fn insert(&mut self, morton: M, item: T) {
+        // Traverse the tree down to the node we need to operate on.
+        // The level is the depth of the node we stop on, which is where its children would be indexed from.
+        let (tree_part, level) = (0..M::dim_bits())
+            .fold_while((&mut self.tree, 0), |(node, depth), i| {
+                use itertools::FoldWhile::{Continue, Done};
+                match node {
+                    Internal::Node(ref mut children) => {
+                        Continue((&mut children[morton.get_level(i)], i + 1))
+                    }
+                    Internal::Leaf(_, _) | Internal::None => Done((node, depth)),
+                }
+            })
+            .into_inner();
+
+        match tree_part {
+            Internal::Leaf(ref mut leaf_item, dest_morton) => {
+                // If they have the same code then replace it.
+                if morton == *dest_morton {
+                    *leaf_item = item;
+                    return;
+                }
+                // Otherwise we must split them, which we must do outside of this scope due to the borrow.
+            }
+            Internal::None => {
+                // Simply add a new leaf.
+                *tree_part = Internal::Leaf(item, morton);
+                self.count += 1;
+                return;
+            }
+            Internal::Node(_) => {
+                unreachable!(
+                    "space::PointerQuadtree::insert(): can only get None or Leaf in this code area"
+                );
+            }
+        }
+
+        if let Internal::Leaf(dest_item, dest_morton) =
+            std::mem::replace(tree_part, Internal::empty_node())
+        {
+            // Set our initial reference to the default node in the dest.
+            let mut building_node = tree_part;
+            // Create deeper nodes till they differ at some level.
+            for i in level..M::dim_bits() {
+                if let Internal::Node(ref mut children) = building_node {
+                    if morton.get_level(i) == dest_morton.get_level(i) {
+                        children[morton.get_level(i)] = Internal::empty_node();
+                        building_node = &mut children[morton.get_level(i)];
+                    } else {
+                        // We reached the end where they differ, so put them both into the node.
+                        children[morton.get_level(i)] = Internal::Leaf(item, morton);
+                        children[dest_morton.get_level(i)] = Internal::Leaf(dest_item, dest_morton);
+                        self.count += 1;
+                        return;
+                    }
+                } else {
+                    unreachable!("space::PointerQuadtree::insert(): cant get a non-node in this section");
+                }
+            }
+        } else {
+            unreachable!("space::PointerQuadtree::insert(): cant get a non-leaf in this code area")
+        }
+    }
+
+    /// Removes the item at a morton from the quadtree and gives it back, if it was present.
+    pub fn remove(&mut self, morton: M) -> Option<T> {
+        // Traverse the tree down to the node we need to operate on.
+        let tree_part = (0..M::dim_bits())
+            .fold_while(&mut self.tree, |node, i| {
+                use itertools::FoldWhile::{Continue, Done};
+                match node {
+                    Internal::Node(ref mut children) => Continue(&mut children[morton.get_level(i)]),
+                    Internal::Leaf(_, _) | Internal::None => Done(node),
+                }
+            })
+            .into_inner();
+
+        match tree_part {
+            Internal::Leaf(_, dest_morton) if morton == *dest_morton => {
+                if let Internal::Leaf(item, _) = std::mem::replace(tree_part, Internal::None) {
+                    self.count -= 1;
+                    Some(item)
+                } else {
+                    unreachable!("space::PointerQuadtree::remove(): can only get a Leaf in this code area")
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Iterate over all quadtree leaves and their morton codes in z-order.
+    pub fn iter(&self) -> impl Iterator<Item = (M, &T)> {
+        use either::Either::{Left, Right};
+        match self.tree {
+            Internal::Node(ref children) => Left(InternalIter {
+                nodes: vec![(&**children, 0)],
+            }),
+            Internal::Leaf(ref item, morton) => Right(std::iter::once((morton, item))),
+            Internal::None => Left(InternalIter { nodes: vec![] }),
+        }
+    }
+
+    /// This gathers the tree into a linear hashed quadtree map. This map contains every internal and leaf node
+    /// as the sum type that the `folder` produces.
+    pub fn collect_fold<E, F>(&self, folder: &F) -> E
+    where
+        F: Folder<T, M>,
+        F::Sum: Clone,
+        E: Extend<(Morton2Region<M>, F::Sum)> + Default,
+    {
+        let mut map = E::default();
+        self.tree.collect_fold(Morton2Region::base(), folder, &mut map);
+        map
+    }
+
+    /// Returns the number of leaves in the tree.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Checks if the quadtree is empty.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+impl<T, M> Extend<(M, T)> for PointerQuadtree<T, M>
+where
+    M: Morton2,
+{
+    fn extend<I>(&mut self, it: I)
+    where
+        I: IntoIterator<Item = (M, T)>,
+    {
+        for (m, item) in it {
+            self.insert(m, item);
+        }
+    }
+}
+
+/// Internal node of a pointer quadtree.
+#[derive(Clone, Debug, Default)]
+enum Internal<T, M> {
+    Node(Box<[Internal<T, M>; 4]>),
+    Leaf(T, M),
+    #[default]
+    None,
+}
+
+impl<T, M> Internal<T, M>
+where
+    M: Morton2,
+{
+    fn collect_fold<E, F>(&self, region: Morton2Region<M>, folder: &F, map: &mut E) -> Option<F::Sum>
+    where
+        F: Folder<T, M>,
+        F::Sum: Clone,
+        E: Extend<(Morton2Region<M>, F::Sum)>,
+    {
+        match self {
+            Internal::Node(ref children) => {
+                let sum = folder.fold(
+                    children
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(i, child)| child.collect_fold(region.enter(i), folder, map)),
+                );
+                map.extend(std::iter::once((region, sum.clone())));
+                Some(sum)
+            }
+            Internal::Leaf(ref item, morton) => {
+                let sum = folder.gather(*morton, item);
+                map.extend(std::iter::once((region, sum.clone())));
+                Some(sum)
+            }
+            Internal::None => None,
+        }
+    }
+
+    /// Gives back a `Node` with 4 empty `None` nodes.
+    #[inline]
+    fn empty_node() -> Self {
+        Internal::Node(Box::new([Internal::None, Internal::None, Internal::None, Internal::None]))
+    }
+}
+
+struct InternalIter<'a, T, M> {
+    nodes: Vec<(&'a [Internal<T, M>; 4], usize)>,
+}
+
+impl<'a, T, M> Iterator for InternalIter<'a, T, M>
+where
+    M: Morton2,
+{
+    type Item = (M, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, ix)) = self.nodes.pop() {
+            if ix != 3 {
+                self.nodes.push((node, ix + 1));
+            }
+            match node[ix] {
+                Internal::Node(ref children) => self.nodes.push((children, 0)),
+                Internal::Leaf(ref item, morton) => {
+                    return Some((morton, item));
+                }
+                Internal::None => {}
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector2;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_quadtree_insert_remove_rand() {
+        let mut rng = SmallRng::from_seed([3; 16]);
+        let space = crate::octree::LeveledRegion(0);
+        let mut mortons: Vec<u32> = (0..2000)
+            .map(|_| {
+                let v = Vector2::<f64>::new(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0));
+                space.discretize2(v).unwrap()
+            })
+            .collect();
+        mortons.sort_unstable();
+        mortons.dedup();
+
+        let mut tree = PointerQuadtree::<_, u32>::new();
+        tree.extend(mortons.iter().rev().map(|&m| (m, m)));
+        assert_eq!(tree.len(), mortons.len());
+        // Iteration is in z-order.
+        assert!(tree.iter().map(|(m, _)| m).eq(mortons.iter().copied()));
+        assert!(mortons.iter().all(|&m| tree.get(m) == Some(&m)));
+
+        let (removed, kept) = mortons.split_at(mortons.len() / 2);
+        for &m in removed {
+            assert_eq!(tree.remove(m), Some(m));
+            assert_eq!(tree.remove(m), None);
+        }
+        assert_eq!(tree.len(), kept.len());
+        assert!(tree.iter().map(|(m, _)| m).eq(kept.iter().copied()));
+    }
+}
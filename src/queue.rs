@@ -0,0 +1,30 @@
+//! The priority queue entry shared by the best-first nearest neighbor searches.
+
+use std::cmp::Ordering;
+
+/// A candidate of a best-first search in a `BinaryHeap`, which gives back the closest candidate first.
+pub(crate) struct Queued<S, C> {
+    pub(crate) distance: S,
+    pub(crate) candidate: C,
+}
+
+impl<S: PartialOrd, C> PartialEq for Queued<S, C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<S: PartialOrd, C> Eq for Queued<S, C> {}
+
+impl<S: PartialOrd, C> PartialOrd for Queued<S, C> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<S: PartialOrd, C> Ord for Queued<S, C> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // `BinaryHeap` is a max heap, so this is reversed.
+        other.distance.partial_cmp(&self.distance).unwrap_or(Ordering::Equal)
+    }
+}
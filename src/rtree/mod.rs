@@ -0,0 +1,653 @@
+//! R-trees over axis-aligned boxes.
+
+mod split;
+
+pub use self::split::{InsertStrategy, Quadratic, RStar};
+
+use crate::octree::{Distance, SquaredEuclidean};
+use crate::queue::Queued;
+
+use nalgebra::Vector3;
+use num_traits::Float;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::marker::PhantomData;
+
+/// The most entries that a node can have before it is split.
+const MAX_ENTRIES: usize = 16;
+/// The fewest entries that a node other than the root can have.
+const MIN_ENTRIES: usize = 6;
+/// The number of entries that an overflowing node gives up to be reinserted with `InsertStrategy::FORCED_REINSERT`.
+const REINSERT_ENTRIES: usize = 5;
+
+/// An axis-aligned box, which includes its boundary.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb<S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// The corner of the box with the smallest coordinates.
+    pub min: Vector3<S>,
+    /// The corner of the box with the largest coordinates.
+    pub max: Vector3<S>,
+}
+
+impl<S> Aabb<S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// Creates the box spanning between two opposite corners, which can be given in any order.
+    ///
+    /// ```
+    /// use space::Aabb;
+    /// use nalgebra::Vector3;
+    ///
+    /// let aabb = Aabb::new(Vector3::new(3.0, 1.0, 5.0), Vector3::new(1.0, 4.0, 2.0));
+    /// assert_eq!(aabb.min, Vector3::new(1.0, 1.0, 2.0));
+    /// assert_eq!(aabb.max, Vector3::new(3.0, 4.0, 5.0));
+    /// ```
+    pub fn new(a: Vector3<S>, b: Vector3<S>) -> Self {
+        Self {
+            min: a.zip_map(&b, S::min),
+            max: a.zip_map(&b, S::max),
+        }
+    }
+
+    /// Creates an empty box at a single point.
+    pub fn point(point: Vector3<S>) -> Self {
+        Self { min: point, max: point }
+    }
+
+    /// Gets the center of the box.
+    pub fn center(&self) -> Vector3<S> {
+        self.min.zip_map(&self.max, |min, max| (min + max) / (S::one() + S::one()))
+    }
+
+    /// Gets the volume of the box.
+    pub fn volume(&self) -> S {
+        self.sides().iter().fold(S::one(), |volume, &side| volume * side)
+    }
+
+    /// Gets the sum of the lengths of the sides of the box along each axis.
+    pub fn margin(&self) -> S {
+        self.sides().iter().fold(S::zero(), |margin, &side| margin + side)
+    }
+
+    /// Gets the smallest box that holds both boxes.
+    #[must_use]
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.zip_map(&other.min, S::min),
+            max: self.max.zip_map(&other.max, S::max),
+        }
+    }
+
+    /// Gets the volume of the space that both boxes hold.
+    pub fn overlap(&self, other: &Self) -> S {
+        let min = self.min.zip_map(&other.min, S::max);
+        let max = self.max.zip_map(&other.max, S::min);
+        max.zip_map(&min, |max, min| (max - min).max(S::zero()))
+            .iter()
+            .fold(S::one(), |volume, &side| volume * side)
+    }
+
+    /// Checks if the boxes share any point, including points on their boundaries.
+    pub fn intersects(&self, other: &Self) -> bool {
+        (0..3).all(|i| self.min[i] <= other.max[i] && other.min[i] <= self.max[i])
+    }
+
+    /// Checks if `other` is entirely inside of this box.
+    pub fn contains(&self, other: &Self) -> bool {
+        (0..3).all(|i| self.min[i] <= other.min[i] && other.max[i] <= self.max[i])
+    }
+
+    /// Checks if `point` is inside of this box.
+    pub fn contains_point(&self, point: Vector3<S>) -> bool {
+        self.contains(&Self::point(point))
+    }
+
+    /// Gets the lengths of the sides of the box along each axis.
+    fn sides(&self) -> Vector3<S> {
+        self.max.zip_map(&self.min, |max, min| max - min)
+    }
+}
+
+/// An R-tree, which holds items at axis-aligned boxes and finds the boxes that intersect, contain or are near others.
+///
+/// Unlike the octrees, which hold each item at a single point, the boxes can have any size and can overlap, and any
+/// number of items can have the same box. Each node of the tree holds up to 16 boxes, which are the boxes of its items
+/// or of the nodes below it.
+///
+/// `P` is the strategy that decides where boxes are inserted and how full nodes are split, which is `Quadratic` for a
+/// classic R-tree or `RStar` for an R*-tree (see `RStarTree`). An R*-tree takes longer to insert into, but its nodes
+/// overlap less, so it is usually faster to query.
+///
+/// ```
+/// use space::{Aabb, RTree};
+/// use nalgebra::Vector3;
+///
+/// let mut tree = RTree::<_, f64>::new();
+/// tree.insert(Aabb::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 2.0, 2.0)), "big");
+/// tree.insert(Aabb::new(Vector3::new(1.0, 1.0, 1.0), Vector3::new(1.5, 1.5, 1.5)), "small");
+/// tree.insert(Aabb::new(Vector3::new(5.0, 5.0, 5.0), Vector3::new(6.0, 6.0, 6.0)), "far");
+///
+/// let query = Aabb::new(Vector3::new(1.2, 1.2, 1.2), Vector3::new(3.0, 3.0, 3.0));
+/// let mut names: Vec<&str> = tree.intersecting(&query).into_iter().map(|(_, &name)| name).collect();
+/// names.sort();
+/// assert_eq!(names, vec!["big", "small"]);
+/// ```
+pub struct RTree<T, S, P = Quadratic>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    root: Node<T, S>,
+    /// The height of the root, where nodes of items are at height 0.
+    height: usize,
+    len: usize,
+    strategy: PhantomData<P>,
+}
+
+/// An R*-tree, which is an `RTree` that uses forced reinsertion and splits nodes to minimize their overlap.
+pub type RStarTree<T, S> = RTree<T, S, RStar>;
+
+impl<T, S, P> Default for RTree<T, S, P>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// Create an empty tree.
+    fn default() -> Self {
+        Self {
+            root: Node { entries: Vec::new() },
+            height: 0,
+            len: 0,
+            strategy: PhantomData,
+        }
+    }
+}
+
+impl<T, S, P> RTree<T, S, P>
+where
+    S: Float + std::fmt::Debug + 'static,
+    P: InsertStrategy,
+{
+    /// Create an empty tree. Calls Default impl.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts an item at a box. Items that are already at the same box are kept.
+    pub fn insert(&mut self, aabb: Aabb<S>, item: T) {
+        self.insert_entry(0, aabb, Child::Item(item));
+        self.len += 1;
+    }
+
+    /// Removes an item at exactly the box `aabb` and gives it back, if there is one.
+    pub fn remove(&mut self, aabb: &Aabb<S>) -> Option<T> {
+        self.remove_with(aabb, |_| true)
+    }
+
+    /// Removes an item at exactly the box `aabb` for which `f` gives back true and gives it back, if there is one.
+    ///
+    /// ```
+    /// use space::{Aabb, RStarTree};
+    /// use nalgebra::Vector3;
+    ///
+    /// let aabb = Aabb::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0));
+    /// let mut tree = RStarTree::<_, f64>::new();
+    /// tree.insert(aabb, 1);
+    /// tree.insert(aabb, 2);
+    /// assert_eq!(tree.remove_with(&aabb, |&n| n == 2), Some(2));
+    /// assert_eq!(tree.remove_with(&aabb, |&n| n == 2), None);
+    /// assert_eq!(tree.len(), 1);
+    /// ```
+    pub fn remove_with<F>(&mut self, aabb: &Aabb<S>, mut f: F) -> Option<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut orphans = Vec::new();
+        let item = remove_from(&mut self.root, self.height, aabb, &mut f, &mut orphans)?;
+        self.len -= 1;
+        // A root with a single child isn't needed, since its child holds the same boxes.
+        while self.height > 0 && self.root.entries.len() <= 1 {
+            match self.root.entries.pop() {
+                Some((_, Child::Node(node))) => {
+                    self.root = node;
+                    self.height -= 1;
+                }
+                Some((_, Child::Item(_))) => unreachable!("space::RTree::remove_with(): found an item above height 0"),
+                None => self.height = 0,
+            }
+        }
+        for (aabb, item) in orphans {
+            self.insert_entry(0, aabb, Child::Item(item));
+        }
+        Some(item)
+    }
+
+    /// Finds every item whose box intersects `aabb`, including boxes that only touch it.
+    pub fn intersecting(&self, aabb: &Aabb<S>) -> Vec<(Aabb<S>, &T)> {
+        self.search(|bounds| bounds.intersects(aabb), |item| item.intersects(aabb))
+    }
+
+    /// Finds every item whose box is entirely inside of `aabb`.
+    pub fn contained_in(&self, aabb: &Aabb<S>) -> Vec<(Aabb<S>, &T)> {
+        self.search(|bounds| bounds.intersects(aabb), |item| aabb.contains(item))
+    }
+
+    /// Finds every item whose box entirely holds `aabb`. Use `Aabb::point` to find the boxes holding a point.
+    ///
+    /// ```
+    /// use space::{Aabb, RTree};
+    /// use nalgebra::Vector3;
+    ///
+    /// let tree: RTree<_, f64> = (0..10)
+    ///     .map(|n| (Aabb::new(Vector3::new(0.0, 0.0, 0.0), Vector3::repeat(f64::from(n))), n))
+    ///     .collect();
+    /// let mut found: Vec<i32> = tree
+    ///     .containing(&Aabb::point(Vector3::new(6.5, 0.5, 2.0)))
+    ///     .into_iter()
+    ///     .map(|(_, &n)| n)
+    ///     .collect();
+    /// found.sort();
+    /// assert_eq!(found, vec![7, 8, 9]);
+    /// ```
+    pub fn containing(&self, aabb: &Aabb<S>) -> Vec<(Aabb<S>, &T)> {
+        self.search(|bounds| bounds.contains(aabb), |item| item.contains(aabb))
+    }
+
+    /// Finds the `k` items whose boxes are closest to `point` according to `distance`, sorted from closest to
+    /// farthest, along with their distance.
+    ///
+    /// The distance to a box is `Distance::box_distance`, so it is zero for every box that holds `point`.
+    pub fn knn<D>(&self, point: Vector3<S>, k: usize, distance: &D) -> Vec<(Aabb<S>, &T, S)>
+    where
+        D: Distance<S>,
+    {
+        let mut found = Vec::with_capacity(k);
+        if k == 0 {
+            return found;
+        }
+        let mut queue = BinaryHeap::new();
+        queue.push(Queued {
+            distance: S::zero(),
+            candidate: Candidate::Node(&self.root),
+        });
+        while let Some(Queued { distance: d, candidate }) = queue.pop() {
+            match candidate {
+                Candidate::Item(aabb, item) => {
+                    // Nothing left in the queue can be closer than this item.
+                    found.push((aabb, item, d));
+                    if found.len() == k {
+                        break;
+                    }
+                }
+                Candidate::Node(node) => {
+                    queue.extend(node.entries.iter().map(|(aabb, child)| Queued {
+                        distance: distance.box_distance(point, aabb.min, aabb.max),
+                        candidate: match child {
+                            Child::Item(item) => Candidate::Item(*aabb, item),
+                            Child::Node(node) => Candidate::Node(node),
+                        },
+                    }));
+                }
+            }
+        }
+        found
+    }
+
+    /// Finds the item whose box is closest to `point` according to `distance`. See `knn`.
+    pub fn nearest<D>(&self, point: Vector3<S>, distance: &D) -> Option<(Aabb<S>, &T, S)>
+    where
+        D: Distance<S>,
+    {
+        self.knn(point, 1, distance).pop()
+    }
+
+    /// Iterates over every item and its box, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (Aabb<S>, &T)> {
+        self.search(|_| true, |_| true).into_iter()
+    }
+
+    /// Removes every item from the tree.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Returns the number of items in the tree.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Checks if the tree is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Finds every item whose box passes `item`, only going into nodes whose bounds pass `node`.
+    fn search<N, I>(&self, node: N, item: I) -> Vec<(Aabb<S>, &T)>
+    where
+        N: Fn(&Aabb<S>) -> bool,
+        I: Fn(&Aabb<S>) -> bool,
+    {
+        let mut found = Vec::new();
+        let mut nodes = vec![&self.root];
+        while let Some(next) = nodes.pop() {
+            for (aabb, child) in &next.entries {
+                match child {
+                    Child::Item(it) if item(aabb) => found.push((*aabb, it)),
+                    Child::Node(child) if node(aabb) => nodes.push(child),
+                    _ => {}
+                }
+            }
+        }
+        found
+    }
+
+    /// Inserts an entry into a node at `height`, starting from the root and splitting the root if it gets too many
+    /// entries. This also inserts every entry that was given up for reinsertion along the way.
+    fn insert_entry(&mut self, height: usize, aabb: Aabb<S>, child: Child<T, S>) {
+        let mut queue = vec![(height, aabb, child)];
+        // Forced reinsertion only happens once at each height for each insertion, otherwise it could go on forever.
+        let mut reinserted = vec![false; self.height + 1];
+        while let Some((height, aabb, child)) = queue.pop() {
+            let split = insert_into::<T, S, P>(
+                &mut self.root,
+                self.height,
+                true,
+                (height, aabb, child),
+                &mut reinserted,
+                &mut queue,
+            );
+            if let Some(sibling) = split {
+                let old = std::mem::replace(&mut self.root, Node { entries: Vec::new() });
+                self.root.entries = vec![(old.bounds(), Child::Node(old)), sibling];
+                self.height += 1;
+                reinserted.push(false);
+            }
+        }
+    }
+}
+
+impl<T, S, P> Extend<(Aabb<S>, T)> for RTree<T, S, P>
+where
+    S: Float + std::fmt::Debug + 'static,
+    P: InsertStrategy,
+{
+    fn extend<I>(&mut self, it: I)
+    where
+        I: IntoIterator<Item = (Aabb<S>, T)>,
+    {
+        for (aabb, item) in it {
+            self.insert(aabb, item);
+        }
+    }
+}
+
+impl<T, S, P> std::iter::FromIterator<(Aabb<S>, T)> for RTree<T, S, P>
+where
+    S: Float + std::fmt::Debug + 'static,
+    P: InsertStrategy,
+{
+    fn from_iter<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = (Aabb<S>, T)>,
+    {
+        let mut tree = Self::default();
+        tree.extend(it);
+        tree
+    }
+}
+
+struct Node<T, S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// The children of the node along with the boxes that bound them.
+    entries: Vec<(Aabb<S>, Child<T, S>)>,
+}
+
+impl<T, S> Node<T, S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// Gets the box that bounds every entry of the node, which must have at least one.
+    fn bounds(&self) -> Aabb<S> {
+        let (first, rest) = self.entries.split_first().expect("space::RTree: a node with no entries has no bounds");
+        rest.iter().fold(first.0, |bounds, (aabb, _)| bounds.union(aabb))
+    }
+
+    /// Moves every item under this node into `items`.
+    fn into_items(self, items: &mut Vec<(Aabb<S>, T)>) {
+        for (aabb, child) in self.entries {
+            match child {
+                Child::Item(item) => items.push((aabb, item)),
+                Child::Node(node) => node.into_items(items),
+            }
+        }
+    }
+}
+
+enum Child<T, S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    Item(T),
+    Node(Node<T, S>),
+}
+
+/// An entry to insert into a node at some height.
+type Pending<T, S> = (usize, Aabb<S>, Child<T, S>);
+
+/// Inserts `entry` into the node of its height under `node`, which is at `height`.
+///
+/// If a node gets too many entries, it either gives up some of them to `queue` to be inserted again or it is split.
+/// When `node` itself is split, the new node is given back for the caller to add next to it.
+fn insert_into<T, S, P>(
+    node: &mut Node<T, S>,
+    height: usize,
+    root: bool,
+    entry: Pending<T, S>,
+    reinserted: &mut [bool],
+    queue: &mut Vec<Pending<T, S>>,
+) -> Option<(Aabb<S>, Child<T, S>)>
+where
+    S: Float + std::fmt::Debug + 'static,
+    P: InsertStrategy,
+{
+    let (target, aabb, child) = entry;
+    if height == target {
+        node.entries.push((aabb, child));
+    } else {
+        let ix = P::choose_subtree(&node.entries, &aabb, height - 1 == target);
+        let split = match &mut node.entries[ix] {
+            (bounds, Child::Node(next)) => {
+                let split = insert_into::<T, S, P>(next, height - 1, false, (target, aabb, child), reinserted, queue);
+                // Reinsertion can also take entries away, so the bounds are found again instead of being grown.
+                *bounds = next.bounds();
+                split
+            }
+            (_, Child::Item(_)) => unreachable!("space::RTree::insert(): found an item above height 0"),
+        };
+        node.entries.extend(split);
+    }
+    if node.entries.len() <= MAX_ENTRIES {
+        None
+    } else if P::FORCED_REINSERT && !root && !reinserted[height] {
+        reinserted[height] = true;
+        let center = node.bounds().center();
+        let distance = |aabb: &Aabb<S>| SquaredEuclidean.distance(aabb.center(), center);
+        node.entries.sort_by(|a, b| distance(&a.0).partial_cmp(&distance(&b.0)).unwrap_or(Ordering::Equal));
+        // The farthest entries are given up, and they are reinserted from closest to farthest.
+        let farthest = node.entries.split_off(node.entries.len() - REINSERT_ENTRIES);
+        queue.extend(farthest.into_iter().rev().map(|(aabb, child)| (height, aabb, child)));
+        None
+    } else {
+        let sibling = Node {
+            entries: P::split(&mut node.entries, MIN_ENTRIES),
+        };
+        Some((sibling.bounds(), Child::Node(sibling)))
+    }
+}
+
+/// Removes an item at exactly `aabb` for which `f` gives back true from under `node`, which is at `height`.
+///
+/// Nodes that are left with too few entries are removed, and the items under them are moved into `orphans` to be
+/// inserted again.
+fn remove_from<T, S, F>(
+    node: &mut Node<T, S>,
+    height: usize,
+    aabb: &Aabb<S>,
+    f: &mut F,
+    orphans: &mut Vec<(Aabb<S>, T)>,
+) -> Option<T>
+where
+    S: Float + std::fmt::Debug + 'static,
+    F: FnMut(&T) -> bool,
+{
+    if height == 0 {
+        let ix = node.entries.iter().position(|entry| match entry {
+            (bounds, Child::Item(item)) => bounds == aabb && f(item),
+            (_, Child::Node(_)) => false,
+        })?;
+        return match node.entries.swap_remove(ix).1 {
+            Child::Item(item) => Some(item),
+            Child::Node(_) => unreachable!(),
+        };
+    }
+    for ix in 0..node.entries.len() {
+        let (bounds, child) = &mut node.entries[ix];
+        let next = match child {
+            Child::Node(next) if bounds.contains(aabb) => next,
+            _ => continue,
+        };
+        if let Some(item) = remove_from(next, height - 1, aabb, f, orphans) {
+            if next.entries.len() < MIN_ENTRIES {
+                if let (_, Child::Node(next)) = node.entries.swap_remove(ix) {
+                    next.into_items(orphans);
+                }
+            } else {
+                *bounds = next.bounds();
+            }
+            return Some(item);
+        }
+    }
+    None
+}
+
+/// Something the nearest neighbor search still has to look at.
+enum Candidate<'a, T, S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    Node(&'a Node<T, S>),
+    Item(Aabb<S>, &'a T),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::Euclidean;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_aabb(rng: &mut SmallRng) -> Aabb<f64> {
+        let corner = Vector3::new(rng.gen(), rng.gen(), rng.gen());
+        let size = Vector3::new(rng.gen_range(0.0, 0.1), rng.gen_range(0.0, 0.1), rng.gen_range(0.0, 0.1));
+        Aabb::new(corner, corner + size)
+    }
+
+    /// Checks that the bounds of every node hold its entries exactly and that every node other than the root has the
+    /// right number of entries at the same depth, then gives back the number of items.
+    fn check_node(node: &Node<usize, f64>, height: usize, root: bool) -> usize {
+        assert!(node.entries.len() <= MAX_ENTRIES);
+        assert!(root || node.entries.len() >= MIN_ENTRIES);
+        node.entries
+            .iter()
+            .map(|(bounds, child)| match child {
+                Child::Item(_) => {
+                    assert_eq!(height, 0);
+                    1
+                }
+                Child::Node(next) => {
+                    assert!(height > 0);
+                    assert_eq!(*bounds, next.bounds());
+                    check_node(next, height - 1, false)
+                }
+            })
+            .sum()
+    }
+
+    fn check_against_brute_force<P: InsertStrategy>() {
+        let mut rng = SmallRng::from_seed([24; 16]);
+        let mut tree = RTree::<usize, f64, P>::new();
+        let mut reference: Vec<(Aabb<f64>, usize)> = Vec::new();
+        for round in 0..10 {
+            for i in 0..300 {
+                let aabb = random_aabb(&mut rng);
+                tree.insert(aabb, round * 300 + i);
+                reference.push((aabb, round * 300 + i));
+            }
+            for _ in 0..100 {
+                let ix = rng.gen_range(0, reference.len());
+                let (aabb, item) = reference.swap_remove(ix);
+                assert_eq!(tree.remove_with(&aabb, |&n| n == item), Some(item));
+            }
+            assert_eq!(tree.remove(&random_aabb(&mut rng)), None);
+            assert_eq!(tree.len(), reference.len());
+            assert_eq!(check_node(&tree.root, tree.height, true), reference.len());
+
+            for _ in 0..20 {
+                let query = random_aabb(&mut rng).union(&random_aabb(&mut rng));
+                let sorted = |mut found: Vec<usize>| {
+                    found.sort_unstable();
+                    found
+                };
+                let expected = |f: &dyn Fn(&Aabb<f64>) -> bool| {
+                    sorted(reference.iter().filter(|(aabb, _)| f(aabb)).map(|&(_, n)| n).collect())
+                };
+                let found = |found: Vec<(Aabb<f64>, &usize)>| sorted(found.into_iter().map(|(_, &n)| n).collect());
+                assert_eq!(found(tree.intersecting(&query)), expected(&|aabb| aabb.intersects(&query)));
+                assert_eq!(found(tree.contained_in(&query)), expected(&|aabb| query.contains(aabb)));
+                let inner = Aabb::point(query.center());
+                assert_eq!(found(tree.containing(&inner)), expected(&|aabb| aabb.contains(&inner)));
+
+                let point = query.center();
+                let k = rng.gen_range(1, 20);
+                let distances: Vec<f64> = tree.knn(point, k, &Euclidean).into_iter().map(|(_, _, d)| d).collect();
+                let mut expected: Vec<f64> = reference
+                    .iter()
+                    .map(|(aabb, _)| Euclidean.box_distance(point, aabb.min, aabb.max))
+                    .collect();
+                expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                expected.truncate(k);
+                assert_eq!(distances, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rtree_matches_brute_force() {
+        check_against_brute_force::<Quadratic>();
+    }
+
+    #[test]
+    fn test_rstar_tree_matches_brute_force() {
+        check_against_brute_force::<RStar>();
+    }
+
+    #[test]
+    fn test_rtree_remove_everything() {
+        let mut rng = SmallRng::from_seed([25; 16]);
+        let items: Vec<Aabb<f64>> = (0..1000).map(|_| random_aabb(&mut rng)).collect();
+        let mut tree: RStarTree<usize, f64> = items.iter().copied().zip(0..).collect();
+        for (n, aabb) in items.iter().enumerate() {
+            assert_eq!(tree.remove_with(aabb, |&m| m == n), Some(n));
+        }
+        assert!(tree.is_empty());
+        assert_eq!(tree.height, 0);
+        assert!(tree.nearest(Vector3::new(0.5, 0.5, 0.5), &Euclidean).is_none());
+    }
+}
@@ -0,0 +1,239 @@
+use super::Aabb;
+
+use num_traits::Float;
+use std::cmp::Ordering;
+
+/// How an `RTree` chooses where to insert a box and how it splits a node that has too many entries.
+///
+/// The entries that these functions are given are the boxes of a node's children along with the children themselves,
+/// which they must keep together.
+pub trait InsertStrategy {
+    /// Whether a node that has too many entries first gives up some of them to be inserted again from the root, once
+    /// per level for each insertion, before it is split.
+    const FORCED_REINSERT: bool;
+
+    /// Chooses which of the `entries` of a node to insert `aabb` under. `last` is true if `aabb` will be inserted
+    /// directly into the node of the chosen entry, rather than further down.
+    fn choose_subtree<S, C>(entries: &[(Aabb<S>, C)], aabb: &Aabb<S>, last: bool) -> usize
+    where
+        S: Float + std::fmt::Debug + 'static;
+
+    /// Splits the entries of a node into two groups which each have at least `min` entries, leaving one group in
+    /// `entries` and giving back the other.
+    fn split<S, C>(entries: &mut Vec<(Aabb<S>, C)>, min: usize) -> Vec<(Aabb<S>, C)>
+    where
+        S: Float + std::fmt::Debug + 'static;
+}
+
+/// The original R-tree strategy from Guttman, which splits nodes with the quadratic cost algorithm.
+///
+/// Insertion goes under the entry whose box grows the least. Splitting starts with the two entries that would waste
+/// the most space together and then adds the entry with the strongest preference for one group at a time.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Quadratic;
+
+impl InsertStrategy for Quadratic {
+    const FORCED_REINSERT: bool = false;
+
+    fn choose_subtree<S, C>(entries: &[(Aabb<S>, C)], aabb: &Aabb<S>, _: bool) -> usize
+    where
+        S: Float + std::fmt::Debug + 'static,
+    {
+        min_index(entries.len(), |ix| {
+            let bounds = &entries[ix].0;
+            (enlargement(bounds, aabb), bounds.volume())
+        })
+    }
+
+    fn split<S, C>(remaining: &mut Vec<(Aabb<S>, C)>, min: usize) -> Vec<(Aabb<S>, C)>
+    where
+        S: Float + std::fmt::Debug + 'static,
+    {
+        let mut entries = std::mem::take(remaining);
+        let mut seeds = (0, 1);
+        let mut most_waste = (S::neg_infinity(), S::neg_infinity());
+        for i in 0..entries.len() {
+            for j in i + 1..entries.len() {
+                let (a, b) = (&entries[i].0, &entries[j].0);
+                let union = a.union(b);
+                let waste = (
+                    union.volume() - a.volume() - b.volume(),
+                    union.margin() - a.margin() - b.margin(),
+                );
+                if waste > most_waste {
+                    most_waste = waste;
+                    seeds = (i, j);
+                }
+            }
+        }
+        // The second seed is after the first, so removing it first leaves the first one where it was.
+        let b = entries.swap_remove(seeds.1);
+        let a = entries.swap_remove(seeds.0);
+        let mut bounds = (a.0, b.0);
+        let mut groups = (vec![a], vec![b]);
+        while !entries.is_empty() {
+            // A group that needs every remaining entry to reach the minimum gets all of them.
+            if groups.0.len() + entries.len() <= min {
+                groups.0.append(&mut entries);
+                break;
+            }
+            if groups.1.len() + entries.len() <= min {
+                groups.1.append(&mut entries);
+                break;
+            }
+            let costs = |aabb: &Aabb<S>| (enlargement(&bounds.0, aabb), enlargement(&bounds.1, aabb));
+            let preference = |(a, b): ((S, S), (S, S))| ((a.0 - b.0).abs(), (a.1 - b.1).abs());
+            let ix = max_index(entries.len(), |ix| preference(costs(&entries[ix].0)));
+            let entry = entries.swap_remove(ix);
+            let (cost_a, cost_b) = costs(&entry.0);
+            let key_a = (cost_a, bounds.0.volume(), groups.0.len());
+            let key_b = (cost_b, bounds.1.volume(), groups.1.len());
+            if key_a <= key_b {
+                bounds.0 = bounds.0.union(&entry.0);
+                groups.0.push(entry);
+            } else {
+                bounds.1 = bounds.1.union(&entry.0);
+                groups.1.push(entry);
+            }
+        }
+        *remaining = groups.0;
+        groups.1
+    }
+}
+
+/// The R*-tree strategy from Beckmann et al., which uses forced reinsertion and splits nodes to minimize overlap.
+///
+/// Insertion into a node of leaves goes under the entry whose box grows to overlap its siblings the least. Splitting
+/// sorts the entries along the axis where the groups have the smallest margins and splits them where the groups
+/// overlap the least. A node that has too many entries first has the entries farthest from its center reinserted,
+/// which lets the tree fix poor early choices as it grows.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RStar;
+
+impl InsertStrategy for RStar {
+    const FORCED_REINSERT: bool = true;
+
+    fn choose_subtree<S, C>(entries: &[(Aabb<S>, C)], aabb: &Aabb<S>, last: bool) -> usize
+    where
+        S: Float + std::fmt::Debug + 'static,
+    {
+        if !last {
+            return Quadratic::choose_subtree(entries, aabb, last);
+        }
+        min_index(entries.len(), |ix| {
+            let bounds = &entries[ix].0;
+            let grown = bounds.union(aabb);
+            let overlap = entries
+                .iter()
+                .enumerate()
+                .filter(|&(other, _)| other != ix)
+                .fold(S::zero(), |overlap, (_, (sibling, _))| {
+                    overlap + grown.overlap(sibling) - bounds.overlap(sibling)
+                });
+            (overlap, enlargement(bounds, aabb), bounds.volume())
+        })
+    }
+
+    fn split<S, C>(entries: &mut Vec<(Aabb<S>, C)>, min: usize) -> Vec<(Aabb<S>, C)>
+    where
+        S: Float + std::fmt::Debug + 'static,
+    {
+        let axis = min_index(3, |axis| {
+            [false, true].iter().fold(S::zero(), |margin, &upper| {
+                sort_along(entries, axis, upper);
+                distributions(entries, min)
+                    .into_iter()
+                    .fold(margin, |margin, (_, a, b)| margin + a.margin() + b.margin())
+            })
+        });
+        let mut candidates = Vec::new();
+        for &upper in &[false, true] {
+            sort_along(entries, axis, upper);
+            candidates.extend(
+                distributions(entries, min)
+                    .into_iter()
+                    .map(|(split, a, b)| ((a.overlap(&b), a.volume() + b.volume()), upper, split)),
+            );
+        }
+        let (_, upper, split) = candidates[min_index(candidates.len(), |ix| candidates[ix].0)];
+        sort_along(entries, axis, upper);
+        entries.split_off(split)
+    }
+}
+
+/// Gets how much the volume and then the margin of `bounds` grow to hold `aabb`.
+fn enlargement<S>(bounds: &Aabb<S>, aabb: &Aabb<S>) -> (S, S)
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    let union = bounds.union(aabb);
+    (union.volume() - bounds.volume(), union.margin() - bounds.margin())
+}
+
+/// Sorts entries along an axis by either the lower or the upper side of their boxes.
+fn sort_along<S, C>(entries: &mut [(Aabb<S>, C)], axis: usize, upper: bool)
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    let side = |aabb: &Aabb<S>| if upper { aabb.max[axis] } else { aabb.min[axis] };
+    entries.sort_by(|a, b| side(&a.0).partial_cmp(&side(&b.0)).unwrap_or(Ordering::Equal));
+}
+
+/// Gets every way to split the entries in their current order into two groups with at least `min` entries, as the
+/// number of entries in the first group along with the bounds of both groups.
+fn distributions<S, C>(entries: &[(Aabb<S>, C)], min: usize) -> Vec<(usize, Aabb<S>, Aabb<S>)>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    let prefixes: Vec<Aabb<S>> = entries
+        .iter()
+        .scan(None, |bounds: &mut Option<Aabb<S>>, (aabb, _)| {
+            *bounds = Some(bounds.map_or(*aabb, |bounds| bounds.union(aabb)));
+            *bounds
+        })
+        .collect();
+    let mut suffixes: Vec<Aabb<S>> = entries
+        .iter()
+        .rev()
+        .scan(None, |bounds: &mut Option<Aabb<S>>, (aabb, _)| {
+            *bounds = Some(bounds.map_or(*aabb, |bounds| bounds.union(aabb)));
+            *bounds
+        })
+        .collect();
+    suffixes.reverse();
+    (min..=entries.len() - min)
+        .map(|split| (split, prefixes[split - 1], suffixes[split]))
+        .collect()
+}
+
+/// Gets the index from `0..len` with the smallest key, preferring the first one.
+fn min_index<K, F>(len: usize, mut key: F) -> usize
+where
+    K: PartialOrd,
+    F: FnMut(usize) -> K,
+{
+    (0..len)
+        .map(|ix| (key(ix), ix))
+        .fold(None, |best: Option<(K, usize)>, (key, ix)| match best {
+            Some(best) if best.0 <= key => Some(best),
+            _ => Some((key, ix)),
+        })
+        .expect("space::rtree::min_index(): there must be at least one index")
+        .1
+}
+
+/// Gets the index from `0..len` with the largest key, preferring the first one.
+fn max_index<K, F>(len: usize, key: F) -> usize
+where
+    K: PartialOrd,
+    F: Fn(usize) -> K,
+{
+    (0..len)
+        .map(|ix| (key(ix), ix))
+        .fold(None, |best: Option<(K, usize)>, (key, ix)| match best {
+            Some(best) if best.0 >= key => Some(best),
+            _ => Some((key, ix)),
+        })
+        .expect("space::rtree::max_index(): there must be at least one index")
+        .1
+}